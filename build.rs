@@ -1,6 +1,7 @@
 extern crate glsl_to_spirv;
 use std::error::Error;
 use std::io::Read;
+use std::path::Path;
 
 use glsl_to_spirv::ShaderType;
 
@@ -36,7 +37,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     }
                 }
             }
-            let source = std::fs::read_to_string(&in_path)?;
+            let source = resolve_includes(&std::fs::read_to_string(&in_path)?)?;
             let mut compiled_file = glsl_to_spirv::compile(&source, shader_type)?;
             // Read the binary data from the compiled file
             let mut compiled_bytes = Vec::new();
@@ -59,4 +60,22 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Replaces each `#include "name"` line with the contents of `shaders/src/include/name`,
+/// since the compiler is given a single source string.
+fn resolve_includes(source: &str) -> Result<String, Box<dyn Error>> {
+    let mut resolved = String::new();
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("#include") {
+            let name = trimmed["#include".len()..].trim().trim_matches('"');
+            let included = std::fs::read_to_string(Path::new("shaders/src/include").join(name))?;
+            resolved.push_str(&resolve_includes(&included)?);
+        } else {
+            resolved.push_str(line);
+            resolved.push('\n');
+        }
+    }
+    Ok(resolved)
+}
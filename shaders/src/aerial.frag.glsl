@@ -0,0 +1,123 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "geometry.glsl"
+#include "uniforms.glsl"
+#include "phase.glsl"
+
+layout(std140, set = 1, binding = 0) uniform PlanetList {
+    uint planet_count;
+    PlanetData planets[MAX_PLANETS];
+};
+
+layout(std140, set = 2, binding = 0) uniform Stars {
+    uint star_count;
+    StarData[MAX_STARS] stars;
+};
+
+// The color and depth of the scene, which is drawn to a separate target.
+layout(set = 3, binding = 0) uniform sampler2D scene_color;
+layout(set = 3, binding = 1) uniform sampler2D scene_depth;
+
+layout(std140, set = 4, binding = 0) uniform RingList {
+    uint ring_count;
+    RingData rings[MAX_RINGS];
+};
+
+layout(std140, set = 5, binding = 0) uniform OccluderList {
+    uint occluder_count;
+    OccluderData occluders[MAX_OCCLUDERS];
+};
+
+layout(location = 0) in vec2 ndc;
+layout(location = 1) flat in mat4 view;
+layout(location = 5) flat in mat4 inv_proj;
+layout(location = 9) flat in mat4 proj;
+
+layout(location = 0) out vec4 target;
+
+#include "star_flux.glsl"
+
+// The paths to scene geometry are usually short, so fewer samples are needed than for the sky.
+const int NUM_IN_SCATTER = 16;
+
+#include "rings.glsl"
+#include "shadows.glsl"
+#include "scattering.glsl"
+
+// This must match `atmosphere.frag.glsl`, so that the haze isn't added to pixels which are already covered by the atmosphere.
+const float DEPTH_PADDING = 0.9;
+
+// The distance to the ground in front of the eye, matching `atmosphere.frag.glsl`.
+// The ground is missed when the eye is below it, so that a camera in a valley of the terrain still sees the haze.
+float ground_distance( vec3 eye, vec3 dir ) {
+    vec2 f = ray_vs_sphere( eye, dir, R_INNER );
+    return f.x <= f.y && f.x > 0.0 ? f.x : MAX;
+}
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(scene_depth, texel, 0).r;
+    // Pixels without any geometry are left for the background and the sky.
+    if (depth >= 1.0) {
+        discard;
+    }
+    vec3 color = texelFetch(scene_color, texel, 0).rgb;
+
+    // The position of the pixel in view space.
+    vec4 view_pos = inv_proj * vec4(ndc, depth, 1.0);
+    vec3 pos = view_pos.xyz / view_pos.w;
+    float dist = length(pos);
+    vec3 dir = pos / dist;
+
+    // The shadows of the rings and of other bodies darken the geometry, unless the atmosphere pass draws over the ground there (which darkens it instead).
+    bool covered = false;
+    for (uint p = 0; p < planet_count; p++) {
+        float gk = 1.0 / planets[p].radius;
+        vec3 eye = -(view * vec4(planets[p].center, 1)).xyz * gk;
+        vec2 e = ray_vs_sphere(eye, dir, planets[p].atmosphere_radius * gk);
+        float ground = ground_distance(eye, dir);
+        if (e.x <= e.y && e.y >= 0.0 && ground < MAX && dist * gk > ground * DEPTH_PADDING) {
+            covered = true;
+        }
+    }
+    if (!covered) {
+        color *= stars_shadow(pos);
+    }
+
+    for (uint p = 0; p < planet_count; p++) {
+        // The factor which scales down to 'normalized' scale (planet radius of 1.0).
+        const float gk = 1.0 / planets[p].radius;
+        // The relative atmosphere radius.
+        const float ar = planets[p].atmosphere_radius / planets[p].radius;
+        vec3 planet_pos = (view * vec4(planets[p].center, 1)).xyz;
+        vec3 eye = -planet_pos * gk;
+
+        vec2 e = ray_vs_sphere(eye, dir, ar);
+        if (e.x > e.y || e.y < 0.0) {
+            continue;
+        }
+        // The atmosphere pass already draws over geometry which is behind its depth (such as the ground seen from orbit).
+        if (dist * gk > min(e.y, ground_distance(eye, dir)) * DEPTH_PADDING) {
+            continue;
+        }
+        // Only the part of the atmosphere between the camera and the geometry is added.
+        vec2 segment = vec2(max(e.x, 0.0), min(e.y, dist * gk));
+        if (segment.x >= segment.y) {
+            continue;
+        }
+
+        vec3 haze = vec3(0.0);
+        for (uint s = 0; s < star_count; s++) {
+            vec3 starpos = (view * vec4(stars[s].center, 1)).xyz;
+            vec3 l = normalize(starpos - planet_pos);
+            vec4 I = in_scatter(eye, dir, segment, l, ar, p, s) * planets[p].atmosphere_density;
+            haze += I.rgb * stars[s].color * stars[s].intensity * star_weight( s, planet_pos );
+        }
+        // The scene and the haze are both linear, so the geometry is dimmed by the atmosphere in front of it and the haze is added.
+        color = color * view_transmittance(eye, dir, segment, ar, p) + haze;
+    }
+
+    target = vec4(color, 1.0);
+    gl_FragDepth = depth;
+}
@@ -1,26 +1,10 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
-
-const float PI = 3.14159265359;
-const float MAX = 10000.0;
-
-const uint MAX_PLANETS = 8;
-const uint MAX_STARS = 4;
-
-struct PlanetData {
-    vec3 center;
-    float radius;
-    vec3 hue;
-    float atmosphere_radius;
-    float atmosphere_density;
-};
-
-struct StarData {
-    vec3 center;
-    float radius;
-    vec3 color;
-};
+#include "geometry.glsl"
+#include "uniforms.glsl"
+#include "phase.glsl"
+#include "tone_map.glsl"
 
 layout(std140, set = 1, binding = 0) uniform PlanetList {
     uint planet_count;
@@ -32,6 +16,16 @@ layout(std140, set = 2, binding = 0) uniform Stars {
     StarData[MAX_STARS] stars;
 };
 
+layout(std140, set = 3, binding = 0) uniform RingList {
+    uint ring_count;
+    RingData rings[MAX_RINGS];
+};
+
+layout(std140, set = 4, binding = 0) uniform OccluderList {
+    uint occluder_count;
+    OccluderData occluders[MAX_OCCLUDERS];
+};
+
 layout(location = 0) in vec2 ndc;
 layout(location = 1) flat in mat4 view;
 layout(location = 5) flat in mat4 inv_proj;
@@ -39,120 +33,67 @@ layout(location = 9) flat in mat4 proj;
 
 layout(location = 0) out vec4 target;
 
-// ray intersects sphere
-// e = -b +/- sqrt( b^2 - c )
-vec2 ray_vs_sphere( vec3 p, vec3 dir, float r ) {
-    float b = dot( p, dir );
-    float c = dot( p, p ) - r * r;
-
-    float d = b * b - c;
-    if ( d < 0.0 ) {
-        return vec2( MAX, -MAX );
-    }
-    d = sqrt( d );
-
-    return vec2( -b - d, -b + d );
-}
+#include "star_flux.glsl"
 
-// Mie
-// g : ( -0.75, -0.999 )
-//      3 * ( 1 - g^2 )               1 + c^2
-// F = ----------------- * -------------------------------
-//      8pi * ( 2 + g^2 )     ( 1 + g^2 - 2 * g * c )^(3/2)
-float phase_mie( float g, float c, float cc ) {
-    float gg = g * g;
-
-    float a = ( 1.0 - gg ) * ( 1.0 + cc );
-
-    float b = 1.0 + gg - 2.0 * g * c;
-    b *= sqrt( b );
-    b *= 2.0 + gg;
-
-    return ( 3.0 / 8.0 / PI ) * a / b;
-}
-
-// Rayleigh
-// g : 0
-// F = 3/16PI * ( 1 + c^2 )
-float phase_ray( float cc ) {
-    return ( 3.0 / 16.0 / PI ) * ( 1.0 + cc );
-}
-
-// scatter const
-const float R_INNER = 1.0;
-const float R = R_INNER + 0.5;
-
-const int NUM_OUT_SCATTER = 4;
 const int NUM_IN_SCATTER = 40;
 
-float density( vec3 p, float ph ) {
-    return exp( -max( length( p ) - R_INNER, 0.0 ) / ph );
+#include "rings.glsl"
+#include "shadows.glsl"
+#include "scattering.glsl"
+
+// The asymmetry of the Mie halo around the sun, which is much sharper than the general sky glow.
+const float HALO_G = -0.98;
+const float HALO_STRENGTH = 0.02;
+// The smallest angular radius (in radians) of the sun disc, so that distant stars still show up as a point.
+const float MIN_DISC_ANGLE = 0.0025;
+// The width of the soft edge of the sun disc, relative to its angular radius.
+const float DISC_EDGE = 1.15;
+
+// The sun disc and its halo as seen through the atmosphere, which is reddened by the transmittance along the view ray.
+vec3 sun_disc( vec3 dir, vec3 starpos, float radius, vec3 transmittance ) {
+    float dist = length( starpos );
+    vec3 sd = starpos / dist;
+    float angle = max( asin( min( radius / dist, 1.0 ) ), MIN_DISC_ANGLE );
+    float c = dot( dir, sd );
+    float disc = smoothstep( cos( angle * DISC_EDGE ), cos( angle ), c );
+    float halo = phase_mie( HALO_G, -c, c * c ) * HALO_STRENGTH;
+    return ( disc + halo ) * transmittance;
 }
 
-float optic( vec3 p, vec3 q, float ph ) {
-    vec3 s = ( q - p ) / float( NUM_OUT_SCATTER );
-    vec3 v = p + s * 0.5;
+const float DEPTH_PADDING = 0.9;
 
-    float sum = 0.0;
-    for ( int i = 0; i < NUM_OUT_SCATTER; i++ ) {
-        sum += density( v, ph );
-        v += s;
+// The light scattered towards the camera by a single atmosphere, along the part of the view ray within it.
+// The color is in linear space and the alpha is the coverage of the atmosphere.
+vec4 shade_atmosphere( uint p, vec3 dir, vec2 e, bool sky ) {
+    // The factor which scales down to 'normalized' scale (planet radius of 1.0).
+    const float gk = 1.0 / planets[p].radius;
+    // The relative atmosphere radius.
+    const float ar = planets[p].atmosphere_radius / planets[p].radius;
+    // The center of the planet in view space.
+    vec3 planet_pos = (view * vec4(planets[p].center, 1)).xyz;
+
+    // Apply scale down.
+    vec3 eye = -planet_pos * gk;
+    e *= gk;
+    // The light of every star is added together, so that each one colors the part of the sky around it.
+    vec4 sum = vec4( 0.0 );
+    for (uint s = 0; s < star_count; s++) {
+        vec3 starpos = (view * vec4(stars[s].center, 1)).xyz;
+        vec3 l = normalize(starpos - planet_pos);
+        vec4 I = in_scatter(eye, dir, e, l, ar, p, s) * planets[p].atmosphere_density;
+        if (sky) {
+            vec3 sun = sun_disc(dir, starpos, stars[s].radius, view_transmittance(eye, dir, e, ar, p));
+            I.rgb += sun;
+            I.a = max(I.a, max(sun.r, max(sun.g, sun.b)));
+        }
+        // The scattered light takes on the current color and brightness of the star, which changes for variable stars,
+        // and is weighted by the flux of the star at the planet.
+        sum.rgb += I.rgb * stars[s].color * stars[s].intensity * star_weight( s, planet_pos );
+        sum.a = max(sum.a, I.a);
     }
-    sum *= length( s );
-
     return sum;
 }
 
-vec4 in_scatter( vec3 o, vec3 dir, vec2 e, vec3 l, float ar ) {
-    const float rf = ar * 0.85;
-    const float ph_ray = 0.01 * rf;
-    const float ph_mie = 0.004 * rf;
-
-    const vec4 k_ray = vec4( 3.8, 13.5, 33.1, 10.0 );
-    const vec4 k_mie = vec4( 21.0 );
-    const float k_mie_ex = 1.1;
-
-    vec4 sum_ray = vec4( 0.0 );
-    vec4 sum_mie = vec4( 0.0 );
-
-    float n_ray0 = 0.0;
-    float n_mie0 = 0.0;
-
-    float len = ( e.y - e.x ) / float( NUM_IN_SCATTER );
-    vec3 s = dir * len;
-    vec3 v = o + dir * ( e.x + len * 0.5 );
-
-    for ( int i = 0; i < NUM_IN_SCATTER; i++, v += s ) {
-        float d_ray = density( v, ph_ray ) * len;
-        float d_mie = density( v, ph_mie ) * len;
-
-        n_ray0 += d_ray;
-        n_mie0 += d_mie;
-
-        vec2 f = ray_vs_sphere( v, l, ar );
-        vec3 u = v + l * f.y;
-
-        float n_ray1 = optic( v, u, ph_ray );
-        float n_mie1 = optic( v, u, ph_mie );
-
-        vec4 att = exp( - ( n_ray0 + n_ray1 ) * k_ray - ( n_mie0 + n_mie1 ) * k_mie * k_mie_ex );
-
-        sum_ray += d_ray * att;
-        sum_mie += d_mie * att;
-    }
-
-    float c  = dot( dir, -l );
-    float cc = c * c;
-    vec4 scatter =
-    sum_ray * k_ray * phase_ray( cc ) +
-    sum_mie * k_mie * phase_mie( -0.78, c, cc );
-
-
-    return scatter;
-}
-
-const float DEPTH_PADDING = 0.9;
-
 void main()
 {
     target = vec4( 0.0 );
@@ -163,38 +104,69 @@ void main()
     vec4 csp = inv_proj * vec4(ndc, 0.5, 1.0);
     vec3 dir = normalize(csp.xyz);
 
+    // Find every atmosphere along the view ray.
+    uint hit_count = 0;
+    uint hits[MAX_PLANETS];
+    vec2 segments[MAX_PLANETS];
+    // The distance to the nearest ground, which blocks the rest of the ray including the sun and any atmospheres behind it.
+    float ground = MAX;
     for (uint p = 0; p < planet_count; p++) {
-        // The factor which scales down to 'normalized' scale (planet radius of 1.0).
-        const float gk = 1.0 / planets[p].radius;
-        // The relative atmosphere radius.
-        const float ar = planets[p].atmosphere_radius / planets[p].radius;
         vec3 eye = -(view * vec4(planets[p].center, 1)).xyz;
-        //eye *= gk;
-
-        if (dot(eye, vec3(0, 0, 1)) < 0.0) {
-            continue;
-        }
+        // Atmospheres which are entirely behind the camera are skipped, but the camera can be inside an atmosphere.
         vec2 e = ray_vs_sphere( eye, dir, planets[p].atmosphere_radius );
-        if ( e.x > e.y ) {
+        if ( e.x > e.y || e.y <= 0.0 ) {
             continue;
         }
+        // When the camera is inside the atmosphere, the ray starts at the camera instead of where it enters the atmosphere.
+        e.x = max( e.x, 0.0 );
+        // The ground is only hit in front of the camera. A camera below the radius of the planet (such as in a valley of the terrain)
+        // sees the sky as if it were standing on the ground.
         vec2 f = ray_vs_sphere( eye, dir, planets[p].radius );
-        e.y = min( e.y, f.x );
-        // We need to apply padding to ensure that the atmosphere frag depth is sufficiently greater than the planet mesh depth.
-        vec4 world_ndc = (proj * vec4(dir * e.y * DEPTH_PADDING, 1.0));
-        gl_FragDepth = world_ndc.z / world_ndc.w;
-
-        // Apply scale down.
-        e *= gk;
-        eye *= gk;
-        for (uint s = 0; s < star_count; s++) {
-            vec3 starpos = (view * vec4(stars[s].center, 1)).xyz;
-            vec3 l = normalize(starpos - planets[p].center);
-            vec4 I = in_scatter(eye, dir, e, l, ar) * planets[p].atmosphere_density;
-            vec4 c = pow(I, vec4(1.0/2.2));
-            target = c;
-            return;
+        if (f.x <= f.y && f.x > 0.0) {
+            ground = min( ground, f.x );
         }
+
+        // Insert the atmosphere so that the list stays sorted from front to back.
+        uint i = hit_count;
+        while (i > 0 && segments[i - 1].x > e.x) {
+            hits[i] = hits[i - 1];
+            segments[i] = segments[i - 1];
+            i--;
+        }
+        hits[i] = p;
+        segments[i] = e;
+        hit_count++;
+    }
+    if (hit_count == 0 || segments[0].x >= ground) {
+        return;
+    }
+
+    // We need to apply padding to ensure that the atmosphere frag depth is sufficiently greater than the planet mesh depth.
+    vec4 world_ndc = (proj * vec4(dir * min(segments[0].y, ground) * DEPTH_PADDING, 1.0));
+    gl_FragDepth = world_ndc.z / world_ndc.w;
+
+    // Composite the atmospheres from front to back, so that nearer atmospheres cover the ones behind them.
+    vec3 color = vec3( 0.0 );
+    float coverage = 0.0;
+    for (uint i = 0; i < hit_count; i++) {
+        // Atmospheres which start behind the ground are hidden by it.
+        if (segments[i].x >= ground) {
+            break;
+        }
+        vec2 e = vec2(segments[i].x, min(segments[i].y, ground));
+        vec4 I = shade_atmosphere(hits[i], dir, e, ground >= MAX);
+        float a = clamp(I.a, 0.0, 1.0);
+        color += (1.0 - coverage) * a * I.rgb;
+        coverage += (1.0 - coverage) * a;
+    }
+    // The color is divided by the coverage since it is alpha blended.
+    target = tone_map(vec4(color / max(coverage, 0.0001), coverage));
+
+    // The shadows of the rings and of other bodies darken the ground behind the atmosphere, by covering more of it.
+    if (ground < MAX) {
+        float shadow = stars_shadow(dir * ground);
+        float a = 1.0 - (1.0 - target.a) * shadow;
+        target.rgb *= target.a / max(a, 0.0001);
+        target.a = a;
     }
-    target = vec4(0);
 }
@@ -0,0 +1,314 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "geometry.glsl"
+#include "uniforms.glsl"
+#include "phase.glsl"
+#include "tone_map.glsl"
+
+layout(std140, set = 1, binding = 0) uniform PlanetList {
+    uint planet_count;
+    PlanetData planets[MAX_PLANETS];
+};
+
+layout(std140, set = 2, binding = 0) uniform Stars {
+    uint star_count;
+    StarData[MAX_STARS] stars;
+};
+
+// The lookup tables, with a layer for each atmosphere, which are generated by `planet/lut.rs`.
+layout(set = 3, binding = 0) uniform sampler2DArray lut;
+
+layout(std140, set = 4, binding = 0) uniform RingList {
+    uint ring_count;
+    RingData rings[MAX_RINGS];
+};
+
+layout(std140, set = 5, binding = 0) uniform OccluderList {
+    uint occluder_count;
+    OccluderData occluders[MAX_OCCLUDERS];
+};
+
+layout(location = 0) in vec2 ndc;
+layout(location = 1) flat in mat4 view;
+layout(location = 5) flat in mat4 inv_proj;
+layout(location = 9) flat in mat4 proj;
+
+layout(location = 0) out vec4 target;
+
+#include "star_flux.glsl"
+
+// The asymmetry of the Mie halo around the sun, which is much sharper than the general sky glow.
+const float HALO_G = -0.98;
+const float HALO_STRENGTH = 0.02;
+// The smallest angular radius (in radians) of the sun disc, so that distant stars still show up as a point.
+const float MIN_DISC_ANGLE = 0.0025;
+// The width of the soft edge of the sun disc, relative to its angular radius.
+const float DISC_EDGE = 1.15;
+
+// The sizes of the lookup tables, which are mirrored in `planet/lut.rs`.
+const int LUT_R_SIZE = 16;
+const int LUT_MU_SIZE = 32;
+const int LUT_MU_S_SIZE = 32;
+const int LUT_NU_SIZE = 8;
+const int TRANSMITTANCE_HEIGHT = 32;
+const float LUT_WIDTH = float( LUT_NU_SIZE * LUT_MU_S_SIZE );
+const int LUT_LAYER_HEIGHT = 2 * LUT_R_SIZE * LUT_MU_SIZE + TRANSMITTANCE_HEIGHT;
+const float MU_S_MIN = -0.2;
+// The number of points along the view ray at which the shadows of the rings are sampled.
+const int NUM_SHADOW_SAMPLES = 4;
+
+// The extinction coefficient which determines the alpha of the atmosphere.
+const float K_ALPHA = 10.0;
+
+float coord_from_unit( float x, int size ) {
+    return 0.5 / float( size ) + x * ( 1.0 - 1.0 / float( size ) );
+}
+
+float distance_to_top( float ar, float r, float mu ) {
+    float discriminant = r * r * ( mu * mu - 1.0 ) + ar * ar;
+    return max( -r * mu + sqrt( max( discriminant, 0.0 ) ), 0.0 );
+}
+
+float distance_to_ground( float r, float mu ) {
+    float discriminant = r * r * ( mu * mu - 1.0 ) + 1.0;
+    return max( -r * mu - sqrt( max( discriminant, 0.0 ) ), 0.0 );
+}
+
+bool intersects_ground( float r, float mu ) {
+    return mu < 0.0 && r * r * ( mu * mu - 1.0 ) + 1.0 >= 0.0;
+}
+
+// Samples a layer of the lookup tables, where the coordinates are in texels.
+vec4 lut_fetch( uint layer, float x, float y ) {
+    return texture(lut, vec3(x / LUT_WIDTH, y / float(LUT_LAYER_HEIGHT), float(layer)));
+}
+
+// The transmittance from a point at radius r to the top of the atmosphere, along a ray which doesn't hit the ground.
+vec4 lut_transmittance_to_top( uint layer, float ar, float r, float mu ) {
+    float h = sqrt( ar * ar - 1.0 );
+    float rho = sqrt( max( r * r - 1.0, 0.0 ) );
+    float d = distance_to_top( ar, r, mu );
+    float d_min = ar - r;
+    float d_max = rho + h;
+    float x_mu = d_max > d_min ? ( d - d_min ) / ( d_max - d_min ) : 0.0;
+    float x_r = rho / h;
+    float u = coord_from_unit( x_mu, int( LUT_WIDTH ) );
+    float v = coord_from_unit( x_r, TRANSMITTANCE_HEIGHT );
+    float base = float( 2 * LUT_R_SIZE * LUT_MU_SIZE );
+    return lut_fetch( layer, u * LUT_WIDTH, base + v * float( TRANSMITTANCE_HEIGHT ) );
+}
+
+// The transmittance between a point at radius r and the point d along the ray in the direction mu.
+vec4 lut_transmittance( uint layer, float ar, float r, float mu, float d, bool ground ) {
+    float r_d = clamp( sqrt( d * d + 2.0 * r * mu * d + r * r ), 1.0, ar );
+    float mu_d = clamp( ( r * mu + d ) / r_d, -1.0, 1.0 );
+    if ( ground ) {
+        return min( lut_transmittance_to_top( layer, ar, r_d, -mu_d ) / max( lut_transmittance_to_top( layer, ar, r, -mu ), vec4( 1e-6 ) ), vec4( 1.0 ) );
+    }
+    return min( lut_transmittance_to_top( layer, ar, r, mu ) / max( lut_transmittance_to_top( layer, ar, r_d, mu_d ), vec4( 1e-6 ) ), vec4( 1.0 ) );
+}
+
+// The Rayleigh (kind 0) or Mie (kind 1) scattering along a ray, without the scattering coefficients and phase function.
+// The altitude and the angle between the view and the sun are interpolated manually since they are packed into each layer.
+vec4 lut_scattering( uint layer, uint kind, float ar, float r, float mu, float mu_s, float nu, bool ground ) {
+    float h = sqrt( ar * ar - 1.0 );
+    float rho = sqrt( max( r * r - 1.0, 0.0 ) );
+    float x_r = clamp( rho / h, 0.0, 1.0 );
+
+    float r_mu = r * mu;
+    float discriminant = r_mu * r_mu - r * r + 1.0;
+    float u_mu;
+    if ( ground ) {
+        float d = -r_mu - sqrt( max( discriminant, 0.0 ) );
+        float d_min = r - 1.0;
+        float d_max = rho;
+        u_mu = 0.5 - 0.5 * coord_from_unit( d_max == d_min ? 0.0 : ( d - d_min ) / ( d_max - d_min ), LUT_MU_SIZE / 2 );
+    } else {
+        float d = -r_mu + sqrt( max( discriminant + h * h, 0.0 ) );
+        float d_min = ar - r;
+        float d_max = rho + h;
+        u_mu = 0.5 + 0.5 * coord_from_unit( ( d - d_min ) / ( d_max - d_min ), LUT_MU_SIZE / 2 );
+    }
+
+    float d_min = ar - 1.0;
+    float d_max = h;
+    float a = ( distance_to_top( ar, 1.0, mu_s ) - d_min ) / ( d_max - d_min );
+    float big_a = ( distance_to_top( ar, 1.0, MU_S_MIN ) - d_min ) / ( d_max - d_min );
+    float u_mu_s = coord_from_unit( max( 1.0 - a / big_a, 0.0 ) / ( 1.0 + a ), LUT_MU_S_SIZE );
+
+    float tr = x_r * float( LUT_R_SIZE - 1 );
+    float i0 = min( floor( tr ), float( LUT_R_SIZE - 2 ) );
+    float fr = tr - i0;
+    float tn = ( nu + 1.0 ) * 0.5 * float( LUT_NU_SIZE - 1 );
+    float n0 = min( floor( tn ), float( LUT_NU_SIZE - 2 ) );
+    float fn = tn - n0;
+
+    float base = float( kind * LUT_R_SIZE * LUT_MU_SIZE );
+    float x = u_mu_s * float( LUT_MU_S_SIZE );
+    float y = base + u_mu * float( LUT_MU_SIZE );
+    vec4 s00 = lut_fetch( layer, n0 * float( LUT_MU_S_SIZE ) + x, y + i0 * float( LUT_MU_SIZE ) );
+    vec4 s01 = lut_fetch( layer, ( n0 + 1.0 ) * float( LUT_MU_S_SIZE ) + x, y + i0 * float( LUT_MU_SIZE ) );
+    vec4 s10 = lut_fetch( layer, n0 * float( LUT_MU_S_SIZE ) + x, y + ( i0 + 1.0 ) * float( LUT_MU_SIZE ) );
+    vec4 s11 = lut_fetch( layer, ( n0 + 1.0 ) * float( LUT_MU_S_SIZE ) + x, y + ( i0 + 1.0 ) * float( LUT_MU_SIZE ) );
+    return mix( mix( s00, s01, fn ), mix( s10, s11, fn ), fr );
+}
+
+// The sun disc and its halo as seen through the atmosphere, which is reddened by the transmittance along the view ray.
+vec3 sun_disc( vec3 dir, vec3 starpos, float radius, vec3 transmittance ) {
+    float dist = length( starpos );
+    vec3 sd = starpos / dist;
+    float angle = max( asin( min( radius / dist, 1.0 ) ), MIN_DISC_ANGLE );
+    float c = dot( dir, sd );
+    float disc = smoothstep( cos( angle * DISC_EDGE ), cos( angle ), c );
+    float halo = phase_mie( HALO_G, -c, c * c ) * HALO_STRENGTH;
+    return ( disc + halo ) * transmittance;
+}
+
+#include "rings.glsl"
+#include "shadows.glsl"
+
+const float DEPTH_PADDING = 0.9;
+
+// The light scattered towards the camera by a single atmosphere, along the part of the view ray within it.
+// The color is in linear space and the alpha is the coverage of the atmosphere.
+vec4 shade_atmosphere( uint p, vec3 dir, vec2 e, bool sky ) {
+    // The factor which scales down to 'normalized' scale (planet radius of 1.0).
+    const float gk = 1.0 / planets[p].radius;
+    // The relative atmosphere radius.
+    const float ar = planets[p].atmosphere_radius / planets[p].radius;
+    const uint layer = planets[p].lut_layer;
+    // The center of the planet in view space.
+    vec3 planet_pos = (view * vec4(planets[p].center, 1)).xyz;
+
+    // Apply scale down, and start the ray where it enters the atmosphere (or at the camera if it is inside).
+    vec3 eye = -planet_pos * gk;
+    e *= gk;
+    vec3 x = eye + dir * max( e.x, 0.0 );
+    float r = clamp( length( x ), 1.0, ar );
+    float mu = dot( x, dir ) / length( x );
+    bool ground = intersects_ground( r, mu );
+    float d = e.y - max( e.x, 0.0 );
+    // The ray is cut short when it is hidden by something else (such as the ground of another planet).
+    bool clipped = d < ( ground ? distance_to_ground( r, mu ) : distance_to_top( ar, r, mu ) ) - 1e-3;
+    float r_d = clamp( sqrt( d * d + 2.0 * r * mu * d + r * r ), 1.0, ar );
+    vec4 view_transmittance = clipped ? lut_transmittance( layer, ar, r, mu, d, ground ) : vec4( 0.0 );
+
+    vec4 k_ray = vec4( planets[p].rayleigh, K_ALPHA );
+    // The light of every star is added together, so that each one colors the part of the sky around it.
+    vec4 sum = vec4( 0.0 );
+    for (uint s = 0; s < star_count; s++) {
+        vec3 starpos = (view * vec4(stars[s].center, 1)).xyz;
+        vec3 l = normalize(starpos - planet_pos);
+        float mu_s = dot( x, l ) / length( x );
+        float nu = dot( dir, l );
+        vec4 ray = lut_scattering( layer, 0, ar, r, mu, mu_s, nu, ground );
+        vec4 mie = lut_scattering( layer, 1, ar, r, mu, mu_s, nu, ground );
+        if ( clipped ) {
+            // Remove the light scattered beyond the end of the ray.
+            float mu_s_d = clamp( ( r * mu_s + d * nu ) / r_d, -1.0, 1.0 );
+            float mu_d = clamp( ( r * mu + d ) / r_d, -1.0, 1.0 );
+            ray = max( ray - view_transmittance * lut_scattering( layer, 0, ar, r_d, mu_d, mu_s_d, nu, ground ), vec4( 0.0 ) );
+            mie = max( mie - view_transmittance * lut_scattering( layer, 1, ar, r_d, mu_d, mu_s_d, nu, ground ), vec4( 0.0 ) );
+        }
+
+        // The tables don't include the shadows of the rings and of other bodies, so they are sampled along the ray instead.
+        float shadow = 0.0;
+        for ( int k = 0; k < NUM_SHADOW_SAMPLES; k++ ) {
+            vec3 q = planet_pos + ( x + dir * d * ( float( k ) + 0.5 ) / float( NUM_SHADOW_SAMPLES ) ) * planets[p].radius;
+            shadow += ring_shadow( q, l ) * body_shadow( q, s, planet_pos );
+        }
+        shadow /= float( NUM_SHADOW_SAMPLES );
+        float c = -nu;
+        float cc = c * c;
+        vec4 I = ( ray * k_ray * phase_ray( cc ) + mie * planets[p].mie * phase_mie( planets[p].mie_anisotropy, c, cc ) ) * planets[p].atmosphere_density;
+        I.rgb *= shadow;
+        if (sky) {
+            vec3 sun = sun_disc(dir, starpos, stars[s].radius, lut_transmittance_to_top( layer, ar, r, mu ).rgb);
+            I.rgb += sun;
+            I.a = max(I.a, max(sun.r, max(sun.g, sun.b)));
+        }
+        // The scattered light takes on the current color and brightness of the star, which changes for variable stars,
+        // and is weighted by the flux of the star at the planet.
+        sum.rgb += I.rgb * stars[s].color * stars[s].intensity * star_weight( s, planet_pos );
+        sum.a = max(sum.a, I.a);
+    }
+    return sum;
+}
+
+void main()
+{
+    target = vec4( 0.0 );
+    gl_FragDepth = 1.0;
+
+    if (planet_count <= 0) return;
+
+    vec4 csp = inv_proj * vec4(ndc, 0.5, 1.0);
+    vec3 dir = normalize(csp.xyz);
+
+    // Find every atmosphere along the view ray.
+    uint hit_count = 0;
+    uint hits[MAX_PLANETS];
+    vec2 segments[MAX_PLANETS];
+    // The distance to the nearest ground, which blocks the rest of the ray including the sun and any atmospheres behind it.
+    float ground = MAX;
+    for (uint p = 0; p < planet_count; p++) {
+        vec3 eye = -(view * vec4(planets[p].center, 1)).xyz;
+        // Atmospheres which are entirely behind the camera are skipped, but the camera can be inside an atmosphere.
+        vec2 e = ray_vs_sphere( eye, dir, planets[p].atmosphere_radius );
+        if ( e.x > e.y || e.y <= 0.0 ) {
+            continue;
+        }
+        // When the camera is inside the atmosphere, the ray starts at the camera instead of where it enters the atmosphere.
+        e.x = max( e.x, 0.0 );
+        // The ground is only hit in front of the camera. A camera below the radius of the planet (such as in a valley of the terrain)
+        // sees the sky as if it were standing on the ground.
+        vec2 f = ray_vs_sphere( eye, dir, planets[p].radius );
+        if (f.x <= f.y && f.x > 0.0) {
+            ground = min( ground, f.x );
+        }
+
+        // Insert the atmosphere so that the list stays sorted from front to back.
+        uint i = hit_count;
+        while (i > 0 && segments[i - 1].x > e.x) {
+            hits[i] = hits[i - 1];
+            segments[i] = segments[i - 1];
+            i--;
+        }
+        hits[i] = p;
+        segments[i] = e;
+        hit_count++;
+    }
+    if (hit_count == 0 || segments[0].x >= ground) {
+        return;
+    }
+
+    // We need to apply padding to ensure that the atmosphere frag depth is sufficiently greater than the planet mesh depth.
+    vec4 world_ndc = (proj * vec4(dir * min(segments[0].y, ground) * DEPTH_PADDING, 1.0));
+    gl_FragDepth = world_ndc.z / world_ndc.w;
+
+    // Composite the atmospheres from front to back, so that nearer atmospheres cover the ones behind them.
+    vec3 color = vec3( 0.0 );
+    float coverage = 0.0;
+    for (uint i = 0; i < hit_count; i++) {
+        // Atmospheres which start behind the ground are hidden by it.
+        if (segments[i].x >= ground) {
+            break;
+        }
+        vec2 e = vec2(segments[i].x, min(segments[i].y, ground));
+        vec4 I = shade_atmosphere(hits[i], dir, e, ground >= MAX);
+        float a = clamp(I.a, 0.0, 1.0);
+        color += (1.0 - coverage) * a * I.rgb;
+        coverage += (1.0 - coverage) * a;
+    }
+    // The color is divided by the coverage since it is alpha blended.
+    target = tone_map(vec4(color / max(coverage, 0.0001), coverage));
+
+    // The shadows of the rings and of other bodies darken the ground behind the atmosphere, by covering more of it.
+    if (ground < MAX) {
+        float shadow = stars_shadow(dir * ground);
+        float a = 1.0 - (1.0 - target.a) * shadow;
+        target.rgb *= target.a / max(a, 0.0001);
+        target.a = a;
+    }
+}
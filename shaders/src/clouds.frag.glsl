@@ -0,0 +1,247 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "geometry.glsl"
+#include "uniforms.glsl"
+#include "phase.glsl"
+#include "noise.glsl"
+#include "tone_map.glsl"
+
+const uint MAX_CLOUDS = 8;
+struct CloudData {
+    // Rotates world space directions into the planet's frame, including the rotation of the wind.
+    mat4 orientation;
+    vec3 center;
+    float radius;
+    float altitude;
+    float thickness;
+    float coverage;
+    float density;
+    vec3 color;
+    float scale;
+    float evolution;
+    float seed;
+    // The index of the planet's atmosphere in the planet list, or past its end if the planet has no atmosphere.
+    uint planet;
+};
+
+layout(std140, set = 1, binding = 0) uniform CloudList {
+    uint cloud_count;
+    CloudData clouds[MAX_CLOUDS];
+};
+
+layout(std140, set = 2, binding = 0) uniform Stars {
+    uint star_count;
+    StarData[MAX_STARS] stars;
+};
+
+layout(std140, set = 3, binding = 0) uniform PlanetList {
+    uint planet_count;
+    PlanetData planets[MAX_PLANETS];
+};
+
+// Whether the layer has a texture at set 4, which is specialized by the render group.
+layout(constant_id = 0) const bool TEXTURED = false;
+
+// An equirectangular map of the coverage in the red channel.
+layout(set = 4, binding = 0) uniform sampler2D coverage_map;
+
+// The cloud layer drawn by this draw call.
+layout(push_constant) uniform CloudIndex {
+    uint cloud_index;
+};
+
+layout(location = 0) in vec2 ndc;
+layout(location = 1) flat in mat4 view;
+layout(location = 5) flat in mat4 inv_proj;
+layout(location = 9) flat in mat4 proj;
+
+layout(location = 0) out vec4 target;
+
+#include "star_flux.glsl"
+#include "optics.glsl"
+
+// Clouds scatter most light forwards, with a weaker lobe backwards which brightens them when seen with the sun behind the camera.
+const float FORWARD_G = 0.6;
+const float BACKWARD_G = -0.3;
+const float BACKWARD_WEIGHT = 0.3;
+
+float phase_clouds( float c ) {
+    return mix( phase_hg( FORWARD_G, c ), phase_hg( BACKWARD_G, c ), BACKWARD_WEIGHT );
+}
+
+const int NUM_CLOUD_STEPS = 24;
+const int NUM_LIGHT_STEPS = 4;
+const int NUM_OCTAVES = 5;
+
+// The light from the sky which reaches the clouds from every direction, relative to the direct light.
+const float AMBIENT = 0.15;
+// The transmittance below which the rest of the clouds are hidden.
+const float MIN_TRANSMITTANCE = 0.01;
+// The opacity at which the depth of the clouds is written.
+const float DEPTH_ALPHA = 0.1;
+
+// The coverage of the clouds at a position in the planet's frame, from 0 (clear) to 1 (overcast).
+// A coverage map decides where the clouds are, and the noise breaks up their edges.
+float cloud_coverage( vec3 p, int octaves ) {
+    CloudData cloud = clouds[cloud_index];
+    vec3 n = normalize( p );
+    vec3 offset = vec3( cloud.seed * 7.31, cloud.seed * 3.17, cloud.evolution );
+    if ( TEXTURED ) {
+        vec2 uv = vec2( atan( n.x, -n.z ) / ( 2.0 * PI ) + 0.5, acos( clamp( n.y, -1.0, 1.0 ) ) / PI );
+        float map = texture(coverage_map, uv).r;
+        return map * mix( 0.6, 1.0, fbm( n * cloud.scale * 4.0 + offset, octaves ) );
+    }
+    return fbm( n * cloud.scale + offset, octaves );
+}
+
+// The extinction coefficient of the clouds at a position relative to the center of the planet, in normalized units.
+float cloud_density( vec3 v, int octaves ) {
+    CloudData cloud = clouds[cloud_index];
+    float r0 = 1.0 + cloud.altitude;
+    float h = ( length( v ) - r0 ) / cloud.thickness;
+    if ( h < 0.0 || h > 1.0 ) {
+        return 0.0;
+    }
+    // The clouds have flat bottoms and rounded tops.
+    float profile = smoothstep( 0.0, 0.1, h ) * smoothstep( 1.0, 0.4, h );
+
+    // View space positions are turned into world space directions (the view matrix has no scale) and then into the planet's frame.
+    vec3 local = mat3( cloud.orientation ) * ( transpose( mat3( view ) ) * v );
+    float n = cloud_coverage( local, octaves );
+    float c = max( cloud.coverage, 0.001 );
+    float d = clamp( ( n - ( 1.0 - c ) ) / c, 0.0, 1.0 );
+
+    return d * profile * cloud.density / cloud.thickness;
+}
+
+// The optical depth of the clouds between a point and the top of the clouds towards a star.
+float light_depth( vec3 v, vec3 l, float r1 ) {
+    CloudData cloud = clouds[cloud_index];
+    float len = min( ray_vs_sphere( v, l, r1 ).y, cloud.thickness * 2.0 ) / float( NUM_LIGHT_STEPS );
+    vec3 s = l * len;
+    vec3 u = v + s * 0.5;
+    float sum = 0.0;
+    for ( int i = 0; i < NUM_LIGHT_STEPS; i++, u += s ) {
+        sum += cloud_density( u, NUM_OCTAVES - 2 );
+    }
+    return sum * len;
+}
+
+// The light of every star which is scattered towards the camera at a point in the clouds.
+vec3 cloud_light( vec3 v, vec3 dir, vec3 planet_pos, float ar, float r1 ) {
+    CloudData cloud = clouds[cloud_index];
+    bool has_atmosphere = cloud.planet < planet_count;
+    // The sky light is tinted by the Rayleigh scattering of the atmosphere.
+    vec3 rayleigh = has_atmosphere ? planets[cloud.planet].rayleigh : vec3( 0.0 );
+    float k = max( rayleigh.r, max( rayleigh.g, rayleigh.b ) );
+    vec3 sky = k > 0.0 ? mix( vec3( 1.0 ), rayleigh / k, 0.5 ) : vec3( 1.0 );
+
+    vec3 sum = vec3( 0.0 );
+    for ( uint s = 0; s < star_count; s++ ) {
+        vec3 starpos = ( view * vec4( stars[s].center, 1 ) ).xyz;
+        vec3 l = normalize( starpos - planet_pos );
+        // Each star is weighted by its flux at the planet.
+        vec3 star = stars[s].color * stars[s].intensity * star_weight( s, planet_pos );
+
+        // The night side of the planet still gets some light from the sky near the terminator.
+        float day = smoothstep( -0.1, 0.3, dot( normalize( v ), l ) );
+        vec3 ambient = AMBIENT * day * sky * star;
+
+        vec2 f = ray_vs_sphere( v, l, 1.0 );
+        if ( f.x > 0.0 && f.x <= f.y ) {
+            sum += ambient;
+            continue;
+        }
+        float shadow = exp( -light_depth( v, l, r1 ) );
+        vec3 sun = has_atmosphere ? transmittance_to_top( v, l, ar, cloud.planet ) : vec3( 1.0 );
+        vec3 direct = shadow * sun * phase_clouds( dot( dir, l ) ) * 4.0 * PI;
+        sum += ( direct + ambient ) * star;
+    }
+    return sum * cloud.color;
+}
+
+void main()
+{
+    target = vec4( 0.0 );
+    gl_FragDepth = 1.0;
+
+    if ( cloud_index >= cloud_count ) {
+        discard;
+    }
+    CloudData cloud = clouds[cloud_index];
+
+    vec4 csp = inv_proj * vec4( ndc, 0.5, 1.0 );
+    vec3 dir = normalize( csp.xyz );
+
+    // The factor which scales down to 'normalized' scale (planet radius of 1.0).
+    float gk = 1.0 / cloud.radius;
+    // Without an atmosphere the light reaches the clouds unfiltered.
+    float ar = cloud.planet < planet_count ? planets[cloud.planet].atmosphere_radius / planets[cloud.planet].radius : 1.0;
+    vec3 planet_pos = ( view * vec4( cloud.center, 1 ) ).xyz;
+    vec3 eye = -planet_pos * gk;
+
+    float r0 = 1.0 + cloud.altitude;
+    float r1 = r0 + cloud.thickness;
+
+    // Find the part of the view ray within the shell of the clouds, which works from orbit, within the clouds and from the ground.
+    vec2 outer = ray_vs_sphere( eye, dir, r1 );
+    if ( outer.x > outer.y || outer.y < 0.0 ) {
+        discard;
+    }
+    float t0 = max( outer.x, 0.0 );
+    float t1 = outer.y;
+    vec2 inner = ray_vs_sphere( eye, dir, r0 );
+    if ( inner.x <= inner.y ) {
+        if ( inner.x > 0.0 ) {
+            t1 = min( t1, inner.x );
+        } else {
+            t0 = max( t0, inner.y );
+        }
+    }
+    vec2 ground = ray_vs_sphere( eye, dir, 1.0 );
+    if ( ground.x <= ground.y && ground.x > 0.0 ) {
+        t1 = min( t1, ground.x );
+    }
+    if ( t0 >= t1 ) {
+        discard;
+    }
+
+    // The samples are jittered per pixel, which hides the banding of the steps.
+    float jitter = hash( vec3( gl_FragCoord.xy, cloud.evolution ) );
+    float len = ( t1 - t0 ) / float( NUM_CLOUD_STEPS );
+    vec3 v = eye + dir * ( t0 + len * jitter );
+
+    vec3 color = vec3( 0.0 );
+    float transmittance = 1.0;
+    float depth = MAX;
+    for ( int i = 0; i < NUM_CLOUD_STEPS; i++, v += dir * len ) {
+        float sigma = cloud_density( v, NUM_OCTAVES );
+        if ( sigma <= 0.0 ) {
+            continue;
+        }
+        // Integrate the light over the step, so that the result doesn't depend on the number of steps.
+        float step_transmittance = exp( -sigma * len );
+        color += transmittance * ( 1.0 - step_transmittance ) * cloud_light( v, dir, planet_pos, ar, r1 );
+        transmittance *= step_transmittance;
+        if ( depth >= MAX && transmittance < 1.0 - DEPTH_ALPHA ) {
+            depth = t0 + len * ( float( i ) + jitter );
+        }
+        if ( transmittance < MIN_TRANSMITTANCE ) {
+            break;
+        }
+    }
+
+    float alpha = 1.0 - transmittance;
+    if ( alpha < 0.003 ) {
+        discard;
+    }
+    if ( depth >= MAX ) {
+        depth = t0;
+    }
+    vec4 world_ndc = proj * vec4( dir * depth * cloud.radius, 1.0 );
+    gl_FragDepth = world_ndc.z / world_ndc.w;
+
+    // The color is divided by the coverage since it is alpha blended.
+    target = tone_map( vec4( color / alpha, alpha ) );
+}
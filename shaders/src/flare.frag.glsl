@@ -0,0 +1,68 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+const uint MAX_STARS = 4;
+const uint MAX_FLARE_ELEMENTS = 16;
+
+const uint KIND_GHOST = 0;
+const uint KIND_HALO = 1;
+
+const float HALO_RADIUS = 0.85;
+const float HALO_WIDTH = 0.08;
+
+struct FlareStarData {
+    vec3 center;
+    float radius;
+    vec3 color;
+    float visibility;
+    float brightness;
+};
+
+struct FlareElementData {
+    vec3 color;
+    float position;
+    float scale;
+    float intensity;
+    uint kind;
+};
+
+layout(std140, set = 1, binding = 0) uniform FlareStars {
+    vec2 viewport;
+    uint star_count;
+    FlareStarData stars[MAX_STARS];
+};
+
+layout(std140, set = 2, binding = 0) uniform FlareElements {
+    uint element_count;
+    float flare_intensity;
+    FlareElementData elements[MAX_FLARE_ELEMENTS];
+};
+
+layout(location = 0) flat in uint star_idx;
+layout(location = 1) flat in uint element_idx;
+layout(location = 2) in vec2 local;
+
+layout(location = 0) out vec4 target;
+
+void main() {
+    FlareStarData star = stars[star_idx];
+    FlareElementData element = elements[element_idx];
+
+    float r = length(local);
+    if (r > 1.0) {
+        discard;
+    }
+
+    float shape;
+    if (element.kind == KIND_HALO) {
+        float x = (r - HALO_RADIUS) / HALO_WIDTH;
+        shape = exp(-x * x);
+    } else {
+        // A soft disc which is slightly brighter at the rim, like the reflection of the aperture.
+        shape = (1.0 - smoothstep(0.7, 1.0, r)) * (0.6 + 0.4 * r * r);
+    }
+
+    float intensity = shape * element.intensity * flare_intensity * star.visibility * star.brightness;
+    // The flare is blended additively so the alpha is ignored.
+    target = vec4(element.color * star.color * intensity, 1.0);
+}
@@ -0,0 +1,67 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(std140, set = 0, binding = 0) uniform ViewArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+    uniform mat4 proj_view;
+};
+
+const uint MAX_STARS = 4;
+const uint MAX_FLARE_ELEMENTS = 16;
+
+struct FlareStarData {
+    vec3 center;
+    float radius;
+    vec3 color;
+    float visibility;
+    float brightness;
+};
+
+struct FlareElementData {
+    vec3 color;
+    float position;
+    float scale;
+    float intensity;
+    uint kind;
+};
+
+layout(std140, set = 1, binding = 0) uniform FlareStars {
+    vec2 viewport;
+    uint star_count;
+    FlareStarData stars[MAX_STARS];
+};
+
+layout(std140, set = 2, binding = 0) uniform FlareElements {
+    uint element_count;
+    float flare_intensity;
+    FlareElementData elements[MAX_FLARE_ELEMENTS];
+};
+
+layout(location = 0) in vec3 pos;
+
+layout(location = 0) flat out uint star_idx;
+layout(location = 1) flat out uint element_idx;
+layout(location = 2) out vec2 local;
+
+void main() {
+    star_idx = gl_InstanceIndex / element_count;
+    element_idx = gl_InstanceIndex % element_count;
+    local = pos.xy;
+
+    FlareStarData star = stars[star_idx];
+    FlareElementData element = elements[element_idx];
+
+    vec4 clip = proj_view * vec4(star.center, 1.0);
+    if (clip.w <= 0.0 || star.visibility <= 0.0) {
+        // The star is behind the camera or hidden, so collapse the sprite.
+        gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        return;
+    }
+
+    // The flare axis runs from the star through the center of the screen.
+    vec2 light = clip.xy / clip.w;
+    vec2 center = light * (1.0 - element.position);
+    vec2 size = vec2(element.scale * viewport.y / viewport.x, element.scale);
+    gl_Position = vec4(center + pos.xy * size, 0.0, 1.0);
+}
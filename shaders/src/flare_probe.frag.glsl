@@ -0,0 +1,15 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The depth test must happen before the counter is incremented, otherwise occluded samples would be counted.
+layout(early_fragment_tests) in;
+
+layout(std430, set = 2, binding = 0) buffer Samples {
+    uint samples[];
+};
+
+layout(location = 0) flat in uint idx;
+
+void main() {
+    atomicAdd(samples[idx], 1u);
+}
@@ -0,0 +1,42 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The width and height of the probe in pixels.
+const float PROBE_SIZE = 8.0;
+
+layout(std140, set = 0, binding = 0) uniform ViewArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+    uniform mat4 proj_view;
+};
+
+const uint MAX_STARS = 4;
+struct FlareStarData {
+    vec3 center;
+    float radius;
+    vec3 color;
+    float visibility;
+    float brightness;
+};
+
+layout(std140, set = 1, binding = 0) uniform FlareStars {
+    vec2 viewport;
+    uint star_count;
+    FlareStarData stars[MAX_STARS];
+};
+
+layout(location = 0) in vec3 pos;
+
+layout(location = 0) flat out uint idx;
+
+void main() {
+    FlareStarData star = stars[gl_InstanceIndex];
+    vec3 center = (view * vec4(star.center, 1.0)).xyz;
+    // Move the probe to the surface of the star facing the camera so that the star's own billboard does not occlude it.
+    vec3 probe = center - normalize(center) * star.radius;
+    vec4 clip = proj * vec4(probe, 1.0);
+    // Offset by a constant number of pixels so that the visible fraction is independent of the star's size on screen.
+    clip.xy += pos.xy * (PROBE_SIZE / viewport) * clip.w;
+    idx = gl_InstanceIndex;
+    gl_Position = clip;
+}
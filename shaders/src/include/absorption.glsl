@@ -0,0 +1,31 @@
+// The absorbing layer of an atmosphere (such as ozone), which removes light without scattering it.
+// This matches `AbsorptionData` in `planet/mod.rs`.
+struct AbsorptionData {
+    vec3 coefficients;
+    // The radius of the densest part of the absorbing layer, relative to the planet's radius.
+    float center;
+    float width;
+};
+
+// The number of samples of the absorbing layer along each path.
+const int NUM_ABSORPTION_SAMPLES = 4;
+
+// The density of the absorbing layer, which peaks at its center and falls to zero at its width above and below it.
+float absorption_density( AbsorptionData layer, vec3 p ) {
+    return max( 1.0 - abs( length( p ) - layer.center ) / layer.width, 0.0 );
+}
+
+// The optical depth of the absorbing layer between two points.
+float optic_absorption( AbsorptionData layer, vec3 p, vec3 q ) {
+    vec3 s = ( q - p ) / float( NUM_ABSORPTION_SAMPLES );
+    vec3 v = p + s * 0.5;
+
+    float sum = 0.0;
+    for ( int i = 0; i < NUM_ABSORPTION_SAMPLES; i++ ) {
+        sum += absorption_density( layer, v );
+        v += s;
+    }
+    sum *= length( s );
+
+    return sum;
+}
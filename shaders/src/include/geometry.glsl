@@ -0,0 +1,18 @@
+// The constants and intersection tests shared by the shaders which trace rays through planets and their atmospheres.
+const float PI = 3.14159265359;
+const float MAX = 10000.0;
+
+// ray intersects sphere
+// e = -b +/- sqrt( b^2 - c )
+vec2 ray_vs_sphere( vec3 p, vec3 dir, float r ) {
+    float b = dot( p, dir );
+    float c = dot( p, p ) - r * r;
+
+    float d = b * b - c;
+    if ( d < 0.0 ) {
+        return vec2( MAX, -MAX );
+    }
+    d = sqrt( d );
+
+    return vec2( -b - d, -b + d );
+}
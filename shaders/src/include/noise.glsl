@@ -0,0 +1,32 @@
+// The noise which breaks up the clouds and the lights of cities.
+
+float hash( vec3 p ) {
+    p = fract( p * 0.3183099 + 0.1 );
+    p *= 17.0;
+    return fract( p.x * p.y * p.z * ( p.x + p.y + p.z ) );
+}
+
+// Smooth value noise in the range 0 to 1.
+float value_noise( vec3 x ) {
+    vec3 i = floor( x );
+    vec3 f = fract( x );
+    f = f * f * ( 3.0 - 2.0 * f );
+
+    return mix( mix( mix( hash( i + vec3( 0, 0, 0 ) ), hash( i + vec3( 1, 0, 0 ) ), f.x ),
+                     mix( hash( i + vec3( 0, 1, 0 ) ), hash( i + vec3( 1, 1, 0 ) ), f.x ), f.y ),
+                mix( mix( hash( i + vec3( 0, 0, 1 ) ), hash( i + vec3( 1, 0, 1 ) ), f.x ),
+                     mix( hash( i + vec3( 0, 1, 1 ) ), hash( i + vec3( 1, 1, 1 ) ), f.x ), f.y ), f.z );
+}
+
+float fbm( vec3 p, int octaves ) {
+    float sum = 0.0;
+    float amplitude = 0.5;
+    float total = 0.0;
+    for ( int i = 0; i < octaves; i++ ) {
+        sum += value_noise( p ) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        p = p * 2.03 + vec3( 1.7, 9.2, 5.3 );
+    }
+    return sum / total;
+}
@@ -0,0 +1,50 @@
+// The optical depth of an atmosphere and the light which passes through it, in units where the planet's radius is 1.0.
+// This is included after the `planets` uniform.
+const float R_INNER = 1.0;
+
+const int NUM_OUT_SCATTER = 4;
+
+float density( vec3 p, float ph ) {
+    return exp( -max( length( p ) - R_INNER, 0.0 ) / ph );
+}
+
+float optic( vec3 p, vec3 q, float ph ) {
+    vec3 s = ( q - p ) / float( NUM_OUT_SCATTER );
+    vec3 v = p + s * 0.5;
+
+    float sum = 0.0;
+    for ( int i = 0; i < NUM_OUT_SCATTER; i++ ) {
+        sum += density( v, ph );
+        v += s;
+    }
+    sum *= length( s );
+
+    return sum;
+}
+
+// The Rayleigh and Mie scale heights of a planet, which are mirrored in `planet/scattering.rs`.
+vec2 scale_heights( uint p, float ar ) {
+    float rf = ar * 0.85;
+    return vec2( planets[p].rayleigh_scale_height, planets[p].mie_scale_height ) * rf;
+}
+
+// The fraction of the light travelling from v in the direction l which leaves the atmosphere.
+vec3 transmittance_to_top( vec3 v, vec3 l, float ar, uint p ) {
+    vec2 ph = scale_heights( p, ar );
+    vec3 u = v + l * max( ray_vs_sphere( v, l, ar ).y, 0.0 );
+    float n_ray = optic( v, u, ph.x );
+    float n_mie = optic( v, u, ph.y );
+    float n_abs = optic_absorption( planets[p].absorption, v, u );
+    return exp( -n_ray * planets[p].rayleigh - n_mie * planets[p].mie * planets[p].mie_extinction - n_abs * planets[p].absorption.coefficients );
+}
+
+// The fraction of light which passes through the atmosphere along the view ray.
+vec3 view_transmittance( vec3 o, vec3 dir, vec2 e, float ar, uint p ) {
+    vec2 ph = scale_heights( p, ar );
+    vec3 a = o + dir * max( e.x, 0.0 );
+    vec3 b = o + dir * e.y;
+    float n_ray = optic( a, b, ph.x );
+    float n_mie = optic( a, b, ph.y );
+    float n_abs = optic_absorption( planets[p].absorption, a, b );
+    return exp( -n_ray * planets[p].rayleigh - n_mie * planets[p].mie * planets[p].mie_extinction - n_abs * planets[p].absorption.coefficients );
+}
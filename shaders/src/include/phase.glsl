@@ -0,0 +1,32 @@
+// The phase functions, which give the fraction of light scattered at each angle. These are included after `geometry.glsl`.
+
+// Mie
+// g : ( -0.75, -0.999 )
+//      3 * ( 1 - g^2 )               1 + c^2
+// F = ----------------- * -------------------------------
+//      8pi * ( 2 + g^2 )     ( 1 + g^2 - 2 * g * c )^(3/2)
+float phase_mie( float g, float c, float cc ) {
+    float gg = g * g;
+
+    float a = ( 1.0 - gg ) * ( 1.0 + cc );
+
+    float b = 1.0 + gg - 2.0 * g * c;
+    b *= sqrt( b );
+    b *= 2.0 + gg;
+
+    return ( 3.0 / 8.0 / PI ) * a / b;
+}
+
+// Rayleigh
+// g : 0
+// F = 3/16PI * ( 1 + c^2 )
+float phase_ray( float cc ) {
+    return ( 3.0 / 16.0 / PI ) * ( 1.0 + cc );
+}
+
+// Henyey-Greenstein
+float phase_hg( float g, float c ) {
+    float gg = g * g;
+    float b = 1.0 + gg - 2.0 * g * c;
+    return ( 1.0 - gg ) / ( 4.0 * PI * b * sqrt( b ) );
+}
@@ -0,0 +1,23 @@
+// The banded density of ring systems. This is included after the `rings` uniform.
+
+// The smallest cosine of the angle between a ray and the plane of the rings, which limits the optical depth of grazing rays.
+const float MIN_SLANT = 0.01;
+
+float band_hash( float i, float seed ) {
+    return fract( sin( i * 12.9898 + seed * 78.233 ) * 43758.5453 );
+}
+
+// The density of the rings at a fraction of the way from the inner to the outer edge (0.0 to 1.0).
+float ring_density( uint i, float x ) {
+    float edges = smoothstep( 0.0, 0.02, x ) * smoothstep( 1.0, 0.98, x );
+    if ( rings[i].bands <= 0.0 ) {
+        return edges;
+    }
+    float b = x * rings[i].bands;
+    float f = fract( b );
+    float d0 = band_hash( floor( b ), rings[i].seed );
+    float d1 = band_hash( floor( b ) + 1.0, rings[i].seed );
+    // Low densities become gaps between the bands.
+    float d = smoothstep( 0.15, 0.85, mix( d0, d1, smoothstep( 0.0, 1.0, f ) ) );
+    return mix( 1.0, d, rings[i].band_contrast ) * edges;
+}
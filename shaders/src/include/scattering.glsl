@@ -0,0 +1,64 @@
+// The single scattering of light through an atmosphere, in units where the planet's radius is 1.0.
+// This is included after `phase.glsl`, `shadows.glsl` and the `planets` uniform, and the including shader sets `NUM_IN_SCATTER`.
+#include "optics.glsl"
+
+// The extinction coefficient which determines the alpha of the atmosphere.
+const float K_ALPHA = 10.0;
+
+// The light of a star in the direction l which is scattered towards the eye along the segment e of the view ray.
+vec4 in_scatter( vec3 o, vec3 dir, vec2 e, vec3 l, float ar, uint p, uint star ) {
+    vec2 ph = scale_heights( p, ar );
+    vec3 planet_pos = ( view * vec4( planets[p].center, 1 ) ).xyz;
+    float ph_ray = ph.x;
+    float ph_mie = ph.y;
+
+    vec4 k_ray = vec4( planets[p].rayleigh, K_ALPHA );
+    vec4 k_mie = vec4( planets[p].mie );
+    float k_mie_ex = planets[p].mie_extinction;
+    // The absorbing layer doesn't change the coverage of the atmosphere.
+    vec4 k_abs = vec4( planets[p].absorption.coefficients, 0.0 );
+
+    vec4 sum_ray = vec4( 0.0 );
+    vec4 sum_mie = vec4( 0.0 );
+
+    float n_ray0 = 0.0;
+    float n_mie0 = 0.0;
+    float n_abs0 = 0.0;
+
+    float len = ( e.y - e.x ) / float( NUM_IN_SCATTER );
+    vec3 s = dir * len;
+    vec3 v = o + dir * ( e.x + len * 0.5 );
+
+    for ( int i = 0; i < NUM_IN_SCATTER; i++, v += s ) {
+        float d_ray = density( v, ph_ray ) * len;
+        float d_mie = density( v, ph_mie ) * len;
+
+        n_ray0 += d_ray;
+        n_mie0 += d_mie;
+        n_abs0 += absorption_density( planets[p].absorption, v ) * len;
+
+        vec2 f = ray_vs_sphere( v, l, ar );
+        vec3 u = v + l * f.y;
+
+        float n_ray1 = optic( v, u, ph_ray );
+        float n_mie1 = optic( v, u, ph_mie );
+        float n_abs1 = optic_absorption( planets[p].absorption, v, u );
+
+        vec4 att = exp( - ( n_ray0 + n_ray1 ) * k_ray - ( n_mie0 + n_mie1 ) * k_mie * k_mie_ex - ( n_abs0 + n_abs1 ) * k_abs );
+        // The rings and other bodies (including the planet itself) block some of the light before it reaches the air.
+        vec3 q = planet_pos + v * planets[p].radius;
+        att.rgb *= ring_shadow( q, l ) * body_shadow( q, star, vec3( MAX ) );
+
+        sum_ray += d_ray * att;
+        sum_mie += d_mie * att;
+    }
+
+    float c  = dot( dir, -l );
+    float cc = c * c;
+    vec4 scatter =
+    sum_ray * k_ray * phase_ray( cc ) +
+    sum_mie * k_mie * phase_mie( planets[p].mie_anisotropy, c, cc );
+
+
+    return scatter;
+}
@@ -0,0 +1,85 @@
+// The shadows cast by ring systems and by other bodies onto a point in view space.
+// This is included after `rings.glsl`, `star_flux.glsl` and the `occluders` uniform.
+
+// The fraction of the light travelling in the direction l which passes through every ring system before reaching p, in view space.
+float ring_shadow( vec3 p, vec3 l ) {
+    float shadow = 1.0;
+    for ( uint i = 0; i < ring_count; i++ ) {
+        vec3 n = normalize( mat3( view ) * rings[i].normal );
+        vec3 c = ( view * vec4( rings[i].center, 1 ) ).xyz;
+        float mu = dot( n, l );
+        if ( abs( mu ) < 1e-4 ) {
+            continue;
+        }
+        float t = dot( c - p, n ) / mu;
+        if ( t <= 0.0 ) {
+            continue;
+        }
+        float x = ( length( p + l * t - c ) / rings[i].radius - rings[i].inner_radius ) / ( rings[i].outer_radius - rings[i].inner_radius );
+        if ( x < 0.0 || x > 1.0 ) {
+            continue;
+        }
+        float depth = -log( 1.0 - min( rings[i].opacity * ring_density( i, x ), 0.999 ) );
+        shadow *= exp( -depth / max( abs( mu ), MIN_SLANT ) );
+    }
+    return shadow;
+}
+
+// The area of the overlap of two discs with the radii r1 and r2 whose centers are d apart, which is mirrored from `star/eclipse.rs`.
+float disc_overlap( float r1, float r2, float d ) {
+    if ( d >= r1 + r2 ) {
+        return 0.0;
+    }
+    if ( d <= abs( r1 - r2 ) ) {
+        float r = min( r1, r2 );
+        return PI * r * r;
+    }
+    float a1 = acos( clamp( ( d * d + r1 * r1 - r2 * r2 ) / ( 2.0 * d * r1 ), -1.0, 1.0 ) );
+    float a2 = acos( clamp( ( d * d + r2 * r2 - r1 * r1 ) / ( 2.0 * d * r2 ), -1.0, 1.0 ) );
+    float kite = sqrt( max( ( -d + r1 + r2 ) * ( d + r1 - r2 ) * ( d - r1 + r2 ) * ( d + r1 + r2 ), 0.0 ) );
+    return r1 * r1 * a1 + r2 * r2 * a2 - 0.5 * kite;
+}
+
+// Points on the surface of a body aren't shadowed by it, since its own surface is already lit by the renderer.
+const float SURFACE_MARGIN = 1.001;
+
+// The fraction of the disc of star s which is visible from p in view space, past every planet and moon in front of it.
+// The penumbra comes from the part of the star's disc which is covered by each body.
+// The body centered at `own` is skipped, such as a planet whose shadow is already in its lookup tables.
+// Pass `vec3( MAX )` to include every body.
+float body_shadow( vec3 p, uint s, vec3 own ) {
+    vec3 to_star = ( view * vec4( stars[s].center, 1 ) ).xyz - p;
+    float star_distance = length( to_star );
+    if ( star_distance <= stars[s].radius ) {
+        return 1.0;
+    }
+    float star_angle = asin( stars[s].radius / star_distance );
+    float visible = 1.0;
+    for ( uint i = 0; i < occluder_count; i++ ) {
+        vec3 to_occluder = ( view * vec4( occluders[i].center, 1 ) ).xyz - p;
+        float occluder_distance = length( to_occluder );
+        if ( occluder_distance <= occluders[i].radius * SURFACE_MARGIN || occluder_distance >= star_distance || length( to_occluder + p - own ) < occluders[i].radius * 1e-3 ) {
+            continue;
+        }
+        // The angle between the centers is found from both the sine and cosine, since acos is too imprecise for the small discs of stars.
+        float separation = atan( length( cross( to_star, to_occluder ) ), dot( to_star, to_occluder ) );
+        float occluder_angle = asin( occluders[i].radius / occluder_distance );
+        if ( separation >= star_angle + occluder_angle ) {
+            continue;
+        }
+        visible *= 1.0 - min( disc_overlap( star_angle, occluder_angle, separation ) / ( PI * star_angle * star_angle ), 1.0 );
+    }
+    return visible;
+}
+
+// The shadow of the rings and of other bodies at a point in view space, averaged over every star by its brightness there.
+float stars_shadow( vec3 p ) {
+    float sum = 0.0;
+    float total = 0.0;
+    for ( uint s = 0; s < star_count; s++ ) {
+        vec3 starpos = ( view * vec4( stars[s].center, 1 ) ).xyz;
+        sum += stars[s].intensity * star_weight( s, p ) * ring_shadow( p, normalize( starpos - p ) ) * body_shadow( p, s, vec3( MAX ) );
+        total += stars[s].intensity * star_weight( s, p );
+    }
+    return total > 0.0 ? sum / total : 1.0;
+}
@@ -0,0 +1,16 @@
+// The flux of a star at a point in view space, which follows the inverse square law like `StarPhotometry::flux`.
+// This is included after the `stars` uniform and every input, including `view`.
+float star_flux( uint s, vec3 p ) {
+    vec3 d = ( view * vec4( stars[s].center, 1.0 ) ).xyz - p;
+    return stars[s].luminosity / max( dot( d, d ), 1e-6 );
+}
+
+// The weight of the light of a star at a point in view space (such as the center of a planet), which is its flux there
+// relative to the brightest star. The brightest star sets the exposure, so a faint or distant star adds only a little light.
+float star_weight( uint s, vec3 p ) {
+    float brightest = 0.0;
+    for ( uint i = 0; i < star_count; i++ ) {
+        brightest = max( brightest, star_flux( i, p ) );
+    }
+    return brightest > 0.0 ? star_flux( s, p ) / brightest : 1.0;
+}
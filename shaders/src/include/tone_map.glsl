@@ -0,0 +1,4 @@
+// Converts the combined light of every star to the output color.
+vec4 tone_map( vec4 I ) {
+    return pow( clamp( I, 0.0, 1.0 ), vec4( 1.0 / 2.2 ) );
+}
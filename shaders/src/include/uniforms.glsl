@@ -0,0 +1,58 @@
+// The layouts of the planets, rings, occluders and stars which are shared between shaders.
+// These match `PlanetData` in `planet/mod.rs`, `RingData` in `rings/mod.rs`, `OccluderData` and `StarData` in `star/mod.rs`.
+#include "absorption.glsl"
+
+const uint MAX_PLANETS = 8;
+const uint MAX_STARS = 4;
+
+struct PlanetData {
+    vec3 center;
+    float radius;
+    float atmosphere_radius;
+    float atmosphere_density;
+    // The Rayleigh coefficients are tinted by the atmosphere's hue before they are uploaded.
+    vec3 rayleigh;
+    float mie;
+    float mie_extinction;
+    float mie_anisotropy;
+    float rayleigh_scale_height;
+    float mie_scale_height;
+    // This is only used by `atmosphere_lut.frag.glsl`.
+    uint lut_layer;
+    // The absorbing layer (such as ozone), which removes light without scattering it.
+    AbsorptionData absorption;
+};
+
+const uint MAX_RINGS = 8;
+
+struct RingData {
+    vec3 center;
+    float radius;
+    // The normal of the plane of the rings in world space.
+    vec3 normal;
+    float inner_radius;
+    float outer_radius;
+    float opacity;
+    // Zero for textured rings, which have a uniform density in the shadows.
+    float bands;
+    float band_contrast;
+    vec3 color;
+    float seed;
+};
+
+const uint MAX_OCCLUDERS = 8;
+
+struct OccluderData {
+    vec3 center;
+    float radius;
+};
+
+struct StarData {
+    vec3 center;
+    float radius;
+    vec3 color;
+    float luminosity;
+    float visibility;
+    float intensity;
+    vec3 transmittance;
+};
@@ -0,0 +1,161 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "geometry.glsl"
+#include "uniforms.glsl"
+#include "noise.glsl"
+#include "tone_map.glsl"
+
+const uint MAX_NIGHT_LIGHTS = 8;
+struct NightLightData {
+    // Rotates world space directions into the planet's frame, where the lights are defined.
+    mat4 orientation;
+    vec3 center;
+    float radius;
+    vec3 color;
+    float intensity;
+    // The radius of the surface which the lights are drawn on, relative to the planet's radius.
+    float level;
+    float coverage;
+    float scale;
+    float seed;
+    // Whether the texture of the lights is a map of the land which masks the procedural clusters.
+    uint masked;
+    // The index of the planet's atmosphere in the planet list, or past its end if the planet has no atmosphere.
+    uint planet;
+};
+
+layout(std140, set = 1, binding = 0) uniform NightLightList {
+    uint light_count;
+    NightLightData lights[MAX_NIGHT_LIGHTS];
+};
+
+layout(std140, set = 2, binding = 0) uniform Stars {
+    uint star_count;
+    StarData[MAX_STARS] stars;
+};
+
+layout(std140, set = 3, binding = 0) uniform PlanetList {
+    uint planet_count;
+    PlanetData planets[MAX_PLANETS];
+};
+
+// Whether the layer has a texture at set 4, which is specialized by the render group.
+layout(constant_id = 0) const bool TEXTURED = false;
+
+// An equirectangular map of the lights, or of the land if the lights are procedural.
+layout(set = 4, binding = 0) uniform sampler2D light_map;
+
+// The lights of the planet drawn by this draw call.
+layout(push_constant) uniform LightIndex {
+    uint light_index;
+};
+
+layout(location = 0) in vec2 ndc;
+layout(location = 1) flat in mat4 view;
+layout(location = 5) flat in mat4 inv_proj;
+layout(location = 9) flat in mat4 proj;
+
+layout(location = 0) out vec4 target;
+
+#include "star_flux.glsl"
+#include "optics.glsl"
+
+const int NUM_OCTAVES = 4;
+
+// The width of the terminator across which the lights fade in, as the cosine of the angle to the star.
+const float TERMINATOR = 0.1;
+// The lights are drawn in front of the surface of the planet, so that they aren't hidden by the planet's own mesh.
+const float DEPTH_PADDING = 0.9;
+
+// Noise which fades to its average once its features are smaller than a pixel, so that distant lights don't flicker.
+float detail_noise( vec3 q ) {
+    float blur = clamp( length( fwidth( q ) ) - 0.5, 0.0, 1.0 );
+    return mix( value_noise( q ), 0.5, blur );
+}
+
+// The brightness of the procedural lights at a direction in the planet's frame, from 0 (unlit) to 1.
+// Clusters of cities are made of towns, which are made of streets.
+float city_lights( vec3 n ) {
+    NightLightData light = lights[light_index];
+    vec3 offset = vec3( light.seed * 7.31, light.seed * 3.17, light.seed * 5.43 );
+    float c = max( light.coverage, 0.001 );
+    float clusters = clamp( ( fbm( n * light.scale + offset, NUM_OCTAVES ) - ( 1.0 - c ) ) / c, 0.0, 1.0 );
+    if ( clusters <= 0.0 ) {
+        return 0.0;
+    }
+    float towns = smoothstep( 0.5, 0.85, detail_noise( n * light.scale * 16.0 + offset ) );
+    float streets = detail_noise( n * light.scale * 96.0 + offset );
+    // The centers of the clusters are lit everywhere, and the lights thin out to scattered towns at their edges.
+    return clusters * mix( clusters * clusters, 1.0, towns ) * mix( 0.5, 1.0, streets );
+}
+
+// The color of the lights at a direction in the planet's frame.
+// A map of the land keeps the procedural lights off the oceans, and any other map replaces them.
+vec3 light_color( vec3 n ) {
+    if ( !TEXTURED ) {
+        return vec3( city_lights( n ) );
+    }
+    vec2 uv = vec2( atan( n.x, -n.z ) / ( 2.0 * PI ) + 0.5, acos( clamp( n.y, -1.0, 1.0 ) ) / PI );
+    vec3 map = texture(light_map, uv).rgb;
+    if ( lights[light_index].masked != 0u ) {
+        return vec3( map.r * city_lights( n ) );
+    }
+    return map;
+}
+
+void main()
+{
+    target = vec4( 0.0 );
+    gl_FragDepth = 1.0;
+
+    if ( light_index >= light_count ) {
+        discard;
+    }
+    NightLightData light = lights[light_index];
+
+    vec4 csp = inv_proj * vec4( ndc, 0.5, 1.0 );
+    vec3 dir = normalize( csp.xyz );
+
+    // The factor which scales down to 'normalized' scale (planet radius of 1.0).
+    float gk = 1.0 / light.radius;
+    // Without an atmosphere the lights reach the camera unfiltered.
+    bool has_atmosphere = light.planet < planet_count;
+    float ar = has_atmosphere ? planets[light.planet].atmosphere_radius / planets[light.planet].radius : 1.0;
+    vec3 planet_pos = ( view * vec4( light.center, 1 ) ).xyz;
+    vec3 eye = -planet_pos * gk;
+
+    vec2 e = ray_vs_sphere( eye, dir, light.level );
+    if ( e.x > e.y || e.x <= 0.0 ) {
+        discard;
+    }
+    vec3 p = eye + dir * e.x;
+    vec3 n = normalize( p );
+
+    // The lights are only visible on the side of the planet which faces away from every star.
+    // A star which is faint at the planet only dims the lights on its side by its share of the flux.
+    float night = 1.0;
+    for ( uint s = 0; s < star_count; s++ ) {
+        vec3 starpos = ( view * vec4( stars[s].center, 1 ) ).xyz;
+        vec3 l = normalize( starpos - planet_pos );
+        night *= 1.0 - star_weight( s, planet_pos ) * smoothstep( -TERMINATOR, TERMINATOR, dot( n, l ) );
+    }
+    if ( night <= 0.0 ) {
+        discard;
+    }
+
+    // View space directions are turned into world space directions (the view matrix has no scale) and then into the planet's frame.
+    vec3 local = mat3( light.orientation ) * ( transpose( mat3( view ) ) * n );
+    // The light from the surface is dimmed by the atmosphere between where the view ray enters it and the surface.
+    vec3 transmittance = has_atmosphere ? view_transmittance( eye, dir, vec2( ray_vs_sphere( eye, dir, ar ).x, e.x ), ar, light.planet ) : vec3( 1.0 );
+    vec3 emission = light_color( local ) * light.color * light.intensity * night * transmittance;
+    if ( max( emission.r, max( emission.g, emission.b ) ) <= 0.0 ) {
+        discard;
+    }
+
+    vec4 world_ndc = proj * vec4( dir * e.x * light.radius * DEPTH_PADDING, 1.0 );
+    gl_FragDepth = world_ndc.z / world_ndc.w;
+
+    // The lights are added to the surface, so they don't cover it.
+    target = tone_map( vec4( emission, 0.0 ) );
+}
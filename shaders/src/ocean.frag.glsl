@@ -0,0 +1,205 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "geometry.glsl"
+#include "uniforms.glsl"
+#include "phase.glsl"
+#include "tone_map.glsl"
+
+const uint MAX_OCEANS = 8;
+struct OceanData {
+    // Rotates world space directions into the planet's frame, where the depth map is defined.
+    mat4 orientation;
+    vec3 center;
+    float radius;
+    vec3 shallow_color;
+    // The radius of the surface of the water, relative to the planet's radius.
+    float level;
+    vec3 deep_color;
+    float roughness;
+    // The index of the planet's atmosphere in the planet list, or past its end if the planet has no atmosphere.
+    uint planet;
+};
+
+layout(std140, set = 1, binding = 0) uniform OceanList {
+    uint ocean_count;
+    OceanData oceans[MAX_OCEANS];
+};
+
+layout(std140, set = 2, binding = 0) uniform Stars {
+    uint star_count;
+    StarData[MAX_STARS] stars;
+};
+
+layout(std140, set = 3, binding = 0) uniform PlanetList {
+    uint planet_count;
+    PlanetData planets[MAX_PLANETS];
+};
+
+// Whether the layer has a texture at set 4, which is specialized by the render group.
+layout(constant_id = 0) const bool TEXTURED = false;
+
+// An equirectangular map of the depth of the water, which is generated from the terrain.
+layout(set = 4, binding = 0) uniform sampler2D depth_map;
+
+// The ocean drawn by this draw call.
+layout(push_constant) uniform OceanIndex {
+    uint ocean_index;
+};
+
+layout(location = 0) in vec2 ndc;
+layout(location = 1) flat in mat4 view;
+layout(location = 5) flat in mat4 inv_proj;
+layout(location = 9) flat in mat4 proj;
+
+layout(location = 0) out vec4 target;
+
+#include "star_flux.glsl"
+#include "optics.glsl"
+
+// The reflected sky is blurred by the waves, so it needs far fewer steps than the sky itself.
+const int NUM_IN_SCATTER = 8;
+
+// The reflectance of water when it is seen straight on.
+const float WATER_F0 = 0.02;
+// The opacity of the water at the shore, where the sea floor shows through.
+const float SHORE_ALPHA = 0.35;
+
+// The light of a star scattered by the sky towards p from the direction -dir, which is what the water reflects.
+vec3 sky_light( vec3 p, vec3 dir, vec3 l, float ar, uint planet ) {
+    PlanetData atmosphere = planets[planet];
+    vec2 ph = scale_heights( planet, ar );
+
+    float len = max( ray_vs_sphere( p, dir, ar ).y, 0.0 ) / float( NUM_IN_SCATTER );
+    vec3 s = dir * len;
+    vec3 v = p + s * 0.5;
+
+    vec3 sum_ray = vec3( 0.0 );
+    vec3 sum_mie = vec3( 0.0 );
+    float n_ray0 = 0.0;
+    float n_mie0 = 0.0;
+    float n_abs0 = 0.0;
+    for ( int i = 0; i < NUM_IN_SCATTER; i++, v += s ) {
+        float d_ray = density( v, ph.x ) * len;
+        float d_mie = density( v, ph.y ) * len;
+        n_ray0 += d_ray;
+        n_mie0 += d_mie;
+        n_abs0 += absorption_density( atmosphere.absorption, v ) * len;
+
+        // The sky on the night side isn't lit.
+        vec2 f = ray_vs_sphere( v, l, 1.0 );
+        if ( f.x > 0.0 && f.x <= f.y ) {
+            continue;
+        }
+        vec3 u = v + l * ray_vs_sphere( v, l, ar ).y;
+        float n_ray1 = optic( v, u, ph.x );
+        float n_mie1 = optic( v, u, ph.y );
+        float n_abs1 = optic_absorption( atmosphere.absorption, v, u );
+
+        vec3 att = exp( -( n_ray0 + n_ray1 ) * atmosphere.rayleigh - ( n_mie0 + n_mie1 ) * atmosphere.mie * atmosphere.mie_extinction - ( n_abs0 + n_abs1 ) * atmosphere.absorption.coefficients );
+        sum_ray += d_ray * att;
+        sum_mie += d_mie * att;
+    }
+
+    float c = dot( dir, -l );
+    float cc = c * c;
+    return ( sum_ray * atmosphere.rayleigh * phase_ray( cc ) + sum_mie * atmosphere.mie * phase_mie( atmosphere.mie_anisotropy, c, cc ) ) * atmosphere.atmosphere_density;
+}
+
+// The GGX distribution of the normals of the waves.
+float distribution_ggx( float n_h, float roughness ) {
+    float a = roughness * roughness;
+    float aa = a * a;
+    float d = n_h * n_h * ( aa - 1.0 ) + 1.0;
+    return aa / ( PI * d * d );
+}
+
+// The Schlick approximation of the Fresnel reflectance.
+float fresnel( float c ) {
+    return WATER_F0 + ( 1.0 - WATER_F0 ) * pow( 1.0 - clamp( c, 0.0, 1.0 ), 5.0 );
+}
+
+// The depth of the water at a point on the surface (in the planet's frame), from 0.0 at the shore to 1.0 where it is deep.
+// Planets without terrain (and so without a depth map) are covered by deep water.
+// This matches the layout of the map in `planet/terrain_map.rs`.
+float water_depth( vec3 n ) {
+    if ( !TEXTURED ) {
+        return 1.0;
+    }
+    vec2 uv = vec2( atan( n.x, -n.z ) / ( 2.0 * PI ) + 0.5, acos( clamp( n.y, -1.0, 1.0 ) ) / PI );
+    return texture(depth_map, uv).r;
+}
+
+void main()
+{
+    target = vec4( 0.0 );
+    gl_FragDepth = 1.0;
+
+    if ( ocean_index >= ocean_count ) {
+        discard;
+    }
+    OceanData ocean = oceans[ocean_index];
+
+    vec4 csp = inv_proj * vec4( ndc, 0.5, 1.0 );
+    vec3 dir = normalize( csp.xyz );
+
+    // The factor which scales down to 'normalized' scale (planet radius of 1.0).
+    float gk = 1.0 / ocean.radius;
+    // Planets without an atmosphere have nothing to reflect, and the light reaches the water unfiltered.
+    bool has_atmosphere = ocean.planet < planet_count;
+    float ar = has_atmosphere ? planets[ocean.planet].atmosphere_radius / planets[ocean.planet].radius : 1.0;
+    vec3 planet_pos = ( view * vec4( ocean.center, 1 ) ).xyz;
+    vec3 eye = -planet_pos * gk;
+
+    // Only the surface of the water is drawn, so nothing is drawn when the camera is under water.
+    vec2 e = ray_vs_sphere( eye, dir, ocean.level );
+    if ( e.x > e.y || e.x <= 0.0 ) {
+        discard;
+    }
+    vec3 p = eye + dir * e.x;
+    vec3 n = normalize( p );
+
+    // The water is hidden by the terrain above the sea, since it is depth tested against it.
+    vec4 world_ndc = proj * vec4( dir * e.x * ocean.radius, 1.0 );
+    gl_FragDepth = world_ndc.z / world_ndc.w;
+
+    // View space directions are turned into world space directions (the view matrix has no scale) and then into the planet's frame.
+    float depth = water_depth( mat3( ocean.orientation ) * ( transpose( mat3( view ) ) * n ) );
+    vec3 albedo = mix( ocean.shallow_color, ocean.deep_color, depth );
+
+    float n_v = max( dot( n, -dir ), 1e-3 );
+    // Rough water reflects less of the sky at grazing angles, since the waves face the camera.
+    float reflectance = mix( fresnel( n_v ), WATER_F0, ocean.roughness * 0.5 );
+    vec3 r = reflect( dir, n );
+    // The reflected ray is bent up by the waves, so that it doesn't go under the horizon.
+    r = normalize( r + n * max( -dot( r, n ), 0.0 ) * 1.01 );
+
+    vec3 color = vec3( 0.0 );
+    for ( uint s = 0; s < star_count; s++ ) {
+        vec3 starpos = ( view * vec4( stars[s].center, 1 ) ).xyz;
+        vec3 l = normalize( starpos - planet_pos );
+        // Each star is weighted by its flux at the planet.
+        vec3 star = stars[s].color * stars[s].intensity * star_weight( s, planet_pos );
+
+        vec3 sky = has_atmosphere ? sky_light( p, r, l, ar, ocean.planet ) : vec3( 0.0 );
+        color += reflectance * sky * star;
+
+        float n_l = dot( n, l );
+        if ( n_l <= 0.0 ) {
+            continue;
+        }
+        vec3 sun = has_atmosphere ? transmittance_to_top( p, l, ar, ocean.planet ) : vec3( 1.0 );
+
+        // The light which enters the water is scattered back out by it.
+        color += ( 1.0 - reflectance ) * albedo * n_l * sun * star;
+
+        // The glint of the star is spread out by the roughness of the waves.
+        vec3 h = normalize( l - dir );
+        float glint = distribution_ggx( max( dot( n, h ), 0.0 ), ocean.roughness ) * fresnel( dot( h, l ) ) / ( 4.0 * n_v );
+        color += glint * sun * star;
+    }
+
+    // The sea floor shows through the shallow water near the shore, but the reflections are always visible.
+    float alpha = max( mix( SHORE_ALPHA, 1.0, depth ), reflectance );
+    target = tone_map( vec4( color, alpha ) );
+}
@@ -0,0 +1,162 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "geometry.glsl"
+#include "uniforms.glsl"
+#include "phase.glsl"
+#include "tone_map.glsl"
+
+layout(std140, set = 1, binding = 0) uniform RingList {
+    uint ring_count;
+    RingData rings[MAX_RINGS];
+};
+
+layout(std140, set = 2, binding = 0) uniform Stars {
+    uint star_count;
+    StarData[MAX_STARS] stars;
+};
+
+// Whether the layer has a texture at set 4, which is specialized by the render group.
+layout(constant_id = 0) const bool TEXTURED = false;
+
+// The radial profile of the rings, where the left edge is the inner edge of the rings.
+layout(set = 4, binding = 0) uniform sampler2D profile;
+
+// The ring system drawn by this draw call, and whether this is the half in front of the planet.
+layout(push_constant) uniform RingIndex {
+    uint ring_index;
+    uint front;
+};
+
+layout(location = 0) in vec2 ndc;
+layout(location = 1) flat in mat4 view;
+layout(location = 5) flat in mat4 inv_proj;
+layout(location = 9) flat in mat4 proj;
+
+layout(location = 0) out vec4 target;
+
+#include "star_flux.glsl"
+
+// Icy ring particles mostly scatter light back towards the star, with a weaker forward lobe from the dust between them.
+const float BACKWARD_G = -0.4;
+const float FORWARD_G = 0.7;
+const float FORWARD_WEIGHT = 0.25;
+
+float phase_rings( float c ) {
+    return mix( phase_hg( BACKWARD_G, c ), phase_hg( FORWARD_G, c ), FORWARD_WEIGHT );
+}
+
+// The number of ringlets within each band.
+const float RINGLETS = 23.0;
+// The width of the planet's penumbra on the rings, relative to the planet's radius.
+const float PENUMBRA = 0.02;
+
+#include "rings.glsl"
+
+// The optical depth of the rings straight through the plane.
+float ring_depth( uint i, float density ) {
+    return -log( 1.0 - min( rings[i].opacity * density, 0.999 ) );
+}
+
+// The light of a star which is reflected towards the camera from the lit side of a layer of particles, or transmitted through it from the other side.
+// mu_s and mu_v are the cosines of the angles between the normal and the directions to the star and the camera.
+float layer_light( float tau, float mu_s, float mu_v ) {
+    float a = abs( mu_s );
+    float b = abs( mu_v );
+    if ( mu_s * mu_v > 0.0 ) {
+        return a / ( a + b ) * ( 1.0 - exp( -tau / a - tau / b ) );
+    }
+    if ( abs( a - b ) < 1e-3 ) {
+        return tau / b * exp( -tau / b );
+    }
+    return a / ( a - b ) * ( exp( -tau / a ) - exp( -tau / b ) );
+}
+
+void main()
+{
+    target = vec4( 0.0 );
+    gl_FragDepth = 1.0;
+
+    if ( ring_index >= ring_count ) {
+        discard;
+    }
+    RingData ring = rings[ring_index];
+
+    vec4 csp = inv_proj * vec4( ndc, 0.5, 1.0 );
+    vec3 dir = normalize( csp.xyz );
+
+    vec3 planet_pos = ( view * vec4( ring.center, 1 ) ).xyz;
+    vec3 n = normalize( mat3( view ) * ring.normal );
+
+    // Find where the view ray crosses the plane of the rings.
+    float mu_v = dot( n, -dir );
+    if ( abs( mu_v ) < 1e-5 ) {
+        discard;
+    }
+    float t = dot( planet_pos, n ) / dot( dir, n );
+    if ( t <= 0.0 ) {
+        discard;
+    }
+    // The half behind the planet is drawn before the atmosphere, and the half in front of it afterwards.
+    if ( ( t < length( planet_pos ) ) != ( front != 0u ) ) {
+        discard;
+    }
+    vec3 hit = dir * t;
+    // The position of the hit relative to the planet, in normalized units (planet radius of 1.0).
+    vec3 p = ( hit - planet_pos ) / ring.radius;
+    float x = ( length( p ) - ring.inner_radius ) / ( ring.outer_radius - ring.inner_radius );
+    if ( x < 0.0 || x > 1.0 ) {
+        discard;
+    }
+    // The planet hides the rings behind it.
+    vec2 ground = ray_vs_sphere( -planet_pos / ring.radius, dir, 1.0 );
+    if ( ground.x <= ground.y && ground.x > 0.0 && ground.x < t / ring.radius ) {
+        discard;
+    }
+
+    float density;
+    vec3 color;
+    if ( TEXTURED ) {
+        // The profile tints the rings and scales their density.
+        vec4 texel = texture(profile, vec2( x, 0.5 ));
+        density = texel.a * smoothstep( 0.0, 0.02, x ) * smoothstep( 1.0, 0.98, x );
+        color = ring.color * texel.rgb;
+    } else {
+        density = ring_density( ring_index, x );
+        // Each band is made of many fine ringlets, which fade out once they are smaller than a pixel.
+        float r = x * ring.bands * RINGLETS;
+        float ringlets = 0.5 + 0.5 * sin( r + ring.seed );
+        density *= mix( 1.0, mix( ringlets, 0.5, clamp( fwidth( r ), 0.0, 1.0 ) ), ring.band_contrast * 0.3 );
+        color = ring.color;
+    }
+    float tau = ring_depth( ring_index, density );
+
+    vec3 light = vec3( 0.0 );
+    for ( uint s = 0; s < star_count; s++ ) {
+        vec3 starpos = ( view * vec4( stars[s].center, 1 ) ).xyz;
+        vec3 l = normalize( starpos - hit );
+
+        // The shadow of the planet has a soft edge, as the star is partially hidden behind the limb.
+        float shadow = 1.0;
+        float along = dot( p, l );
+        if ( along < 0.0 ) {
+            float closest = length( p - l * along );
+            shadow = smoothstep( 1.0 - PENUMBRA, 1.0 + PENUMBRA, closest );
+        }
+
+        float mu_s = dot( n, l );
+        float scattered = layer_light( tau, sign( mu_s ) * max( abs( mu_s ), MIN_SLANT ), sign( mu_v ) * max( abs( mu_v ), MIN_SLANT ) );
+        light += shadow * scattered * phase_rings( dot( dir, l ) ) * 4.0 * PI * stars[s].color * stars[s].intensity * star_weight( s, planet_pos );
+    }
+
+    // The rings are more opaque when seen at a shallow angle.
+    float alpha = 1.0 - exp( -tau / max( abs( mu_v ), MIN_SLANT ) );
+    if ( alpha < 0.003 ) {
+        discard;
+    }
+    vec4 world_ndc = proj * vec4( hit, 1.0 );
+    gl_FragDepth = world_ndc.z / world_ndc.w;
+
+    // The color is divided by the coverage since it is alpha blended.
+    target = tone_map( vec4( light * color / alpha, alpha ) );
+}
@@ -1,28 +1,23 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+#include "uniforms.glsl"
+
 const float PI = 3.14159265359;
-const uint MAX_STARS = 4;
 const float GLOW_FACTOR = 0.2;
 const float OPAQUE_MARGIN_FACTOR = 1.7;
 const float RAD = 0.5;
-struct StarData {
-    vec3 center;
-    float radius;
-    vec3 color;
-};
-
 layout(std140, set = 1, binding = 0) uniform Stars {
     uint star_count;
     StarData[MAX_STARS] stars;
 };
 
-
 layout(set = 2, binding = 0) uniform sampler2D glow_tex;
 
 layout(location = 0) flat in uint idx;
 layout(location = 1) in vec2 uv;
 layout(location = 2) in vec2 norm_pos;
+layout(location = 3) flat in float brightness;
 
 layout(location = 0) out vec4 target;
 
@@ -37,6 +32,10 @@ void main() {
     vec4 tex_c = texture(glow_tex, uv);
     float tex_factor = tex_c.x;
     float glow = ni_factor * GLOW_FACTOR;
-    float alpha = (tex_factor + glow) * margin_factor;
-    target = vec4(stars[idx].color * tex_factor, alpha);
+    float alpha = (tex_factor + glow) * margin_factor * brightness;
+    // The air between the camera and the star reddens the color and dims the glow.
+    vec3 transmittance = stars[idx].transmittance;
+    float attenuation = max(transmittance.r, max(transmittance.g, transmittance.b));
+    vec3 tint = transmittance / max(attenuation, 0.0001);
+    target = vec4(stars[idx].color * tint * tex_factor, alpha * attenuation);
 }
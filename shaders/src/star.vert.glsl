@@ -1,56 +1,60 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
-const float PI = 3.14159265359;
-const float OUTER_SCALE_FACTOR = 5;
-const float DISTANCE_FACTOR = 0.0001;
+#include "uniforms.glsl"
+
+// The angular radius (in radians) of the glow of the sun at 1 AU with an exposure of 1.0.
+const float GLOW_ANGULAR_RADIUS = 0.05;
+// The glow always covers the star's disc by at least this factor, so close up stars are not cut off.
+const float MIN_DISC_SCALE = 1.5;
+// Must match `BRIGHTNESS_SCALE` in the star module.
+const float BRIGHTNESS_SCALE = 2.0;
+
 layout(std140, set = 0, binding = 0) uniform ViewArgs {
     uniform mat4 proj;
     uniform mat4 view;
     uniform mat4 proj_view;
 };
 
-const uint MAX_STARS = 4;
-struct StarData {
-    vec3 center;
-    float radius;
-    vec3 color;
-};
-
 layout(std140, set = 1, binding = 0) uniform Stars {
     uint star_count;
     StarData[MAX_STARS] stars;
 };
 
+layout(std140, set = 3, binding = 0) uniform Photometry {
+    float astronomical_unit;
+    float exposure;
+};
+
 layout(location = 0) in vec3 pos;
 layout(location = 1) in vec2 uv;
 
 layout(location = 0) flat out uint idx;
 layout(location = 1) out vec2 _uv;
 layout(location = 2) out vec2 norm_pos;
-
-float calc_glow_size(float r, float temp, float dist) {
-    const float DSUN = 1392684.0;
-    const float TSUN = 5778.0;
-
-    float d = dist; 
-    float D = (r * 2) * DSUN;
-    float L = (D * D) * pow(temp / TSUN, 4.0);
-    return 0.016 * pow(L, 0.25) / pow(d, 0.5);
-}
+layout(location = 3) flat out float brightness;
 
 void main() {
     StarData star = stars[gl_InstanceIndex];
     vec4 c_worldspace = view * vec4(star.center, 1);
-    float dist = length(star.center - (-view[3].xyz));
-    float dist_scale_factor =  1.0 + (dist * DISTANCE_FACTOR);
-    //float glow_scale = calc_glow_size(star.radius, star.temperature, dist);
-    float diagonal_factor = star.radius / cos(PI/4);
-    vec3 scaled_offset = pos * diagonal_factor * dist_scale_factor * OUTER_SCALE_FACTOR;
+    vec3 camera_pos = inverse(view)[3].xyz;
+    float dist = max(length(star.center - camera_pos), star.radius);
+
+    // The flux relative to the sun at 1 AU follows the inverse square law.
+    float d = dist / astronomical_unit;
+    // Variable stars change their luminosity over time.
+    float flux = star.luminosity * star.intensity / (d * d);
+    // Both the apparent size and brightness of the glow follow the fourth root of the exposed flux.
+    float response = pow(flux * exposure, 0.25);
+    float glow_radius = max(GLOW_ANGULAR_RADIUS * response * dist, star.radius * MIN_DISC_SCALE);
+
+    vec3 scaled_offset = pos * glow_radius;
     vec3 cameraspace = c_worldspace.xyz + scaled_offset;
     vec4 screenspace = proj * vec4(cameraspace, 1);
     idx = gl_InstanceIndex;
     norm_pos = (pos.xy + vec2(1.0)) / 2.0;
     _uv = uv;
+    // Planets in front of the star block part of its disc, which dims the whole glow.
+    brightness = (1.0 - exp(-response * BRIGHTNESS_SCALE)) * star.visibility;
     gl_Position = screenspace;
-}
\ No newline at end of file
+}
@@ -0,0 +1,57 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "uniforms.glsl"
+
+const float PI = 3.14159265359;
+const float CORE_FALLOFF = 12.0;
+const float GLOW_FACTOR = 0.2;
+const float RAD = 0.5;
+layout(std140, set = 1, binding = 0) uniform Stars {
+    uint star_count;
+    StarData[MAX_STARS] stars;
+};
+
+layout(std140, set = 2, binding = 0) uniform Glow {
+    uint spikes;
+    float rotation;
+    float width;
+    float spike_intensity;
+};
+
+layout(location = 0) flat in uint idx;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec2 norm_pos;
+layout(location = 3) flat in float brightness;
+
+layout(location = 0) out vec4 target;
+
+// The brightness of the diffraction spikes at the specified position relative to the center of the star.
+float diffraction_spikes(vec2 p, float r) {
+    if (spikes == 0u || r <= 0.0) {
+        return 0.0;
+    }
+    float sector = 2.0 * PI / float(spikes);
+    float angle = atan(p.y, p.x) - rotation;
+    // The angle to the nearest spike.
+    float d = abs(mod(angle + sector * 0.5, sector) - sector * 0.5);
+    float perpendicular = r * sin(min(d, PI * 0.5)) / max(width, 0.0001);
+    return exp(-perpendicular * perpendicular) * (1.0 - r);
+}
+
+void main() {
+    vec2 p = (norm_pos - vec2(RAD, RAD)) / RAD;
+    float r = length(p);
+    if (r > 1.0) {
+        discard;
+    }
+    float base_factor = 1.0 - r;
+    float core = exp(-r * CORE_FALLOFF);
+    float glow = pow(base_factor, 3) * GLOW_FACTOR;
+    float value = core + glow + diffraction_spikes(p, r) * spike_intensity;
+    // The air between the camera and the star reddens the color and dims the glow.
+    vec3 transmittance = stars[idx].transmittance;
+    float attenuation = max(transmittance.r, max(transmittance.g, transmittance.b));
+    vec3 tint = transmittance / max(attenuation, 0.0001);
+    target = vec4(stars[idx].color * tint, clamp(value, 0.0, 1.0) * brightness * attenuation);
+}
@@ -0,0 +1,8 @@
+pub mod pass;
+pub(crate) mod sub;
+
+pub use pass::AerialPerspectiveRender;
+
+/// The name of the custom render target which the scene is drawn to, so that the aerial perspective pass can read its depth.
+/// Render plugins which draw scene geometry (such as `RenderPbr3D`) should use `with_target(Target::Custom(SCENE_TARGET))`.
+pub const SCENE_TARGET: &str = "aerial_scene";
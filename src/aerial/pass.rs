@@ -0,0 +1,337 @@
+use std::ops::Range;
+
+use amethyst::{
+    core::ecs::{
+        DispatcherBuilder, World,
+    },
+    error::Error,
+    renderer::{
+        bundle::{ImageOptions, OutputColor, RenderOrder, RenderPlan, RenderPlugin, Target, TargetImage, TargetPlanOutputs},
+        pipeline::{PipelineDescBuilder, PipelinesBuilder},
+        rendy::{
+            command::{QueueId, RenderPassEncoder},
+            factory::Factory,
+            graph::{
+                GraphContext, ImageAccess,
+                NodeBuffer, NodeImage, render::{PrepareResult, RenderGroup, RenderGroupDesc},
+            },
+            hal::{self, device::Device, pso, pso::ShaderStageFlags},
+            mesh::{AsVertex, Position},
+            shader::{Shader, SpirvShader},
+        },
+        submodules::FlatEnvironmentSub,
+        types::Backend, util,
+    },
+    window::ScreenDimensions,
+};
+
+use super::{sub::SceneSub, SCENE_TARGET};
+use crate::{
+    planet::sub::*,
+    rings::sub::RingSub,
+    star::sub::*,
+};
+
+use crate::renderutils::*;
+
+use amethyst::prelude::WorldExt;
+
+const STATIC_DEPTH: f32 = 0.0;
+
+const STATIC_VERTEX_DATA: [Position; 4] = [
+    Position([-1.0, -1.0, STATIC_DEPTH]),
+    Position([-1.0, 1.0, STATIC_DEPTH]),
+    Position([1.0, 1.0, STATIC_DEPTH]),
+    Position([1.0, -1.0, STATIC_DEPTH]),
+];
+
+const STATIC_INSTANCE_DATA: [u32; 6] = [0, 1, 2, 0, 3, 2];
+
+lazy_static::lazy_static! {
+    // The fullscreen quad is the same as the one used by the atmosphere.
+    static ref VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/atmosphere.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/aerial.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+/// Copies the scene target to the main target, adding the haze of the atmospheres between the camera and each pixel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawAerialPerspectiveDesc;
+
+impl DrawAerialPerspectiveDesc {
+    /// Create instance of `DrawAerialPerspectiveDesc` render group
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawAerialPerspectiveDesc {
+    fn images(&self) -> Vec<ImageAccess> {
+        // The scene color and depth are both sampled in the fragment shader.
+        let access = ImageAccess {
+            access: hal::image::Access::SHADER_READ,
+            usage: hal::image::Usage::SAMPLED,
+            layout: hal::image::Layout::ShaderReadOnlyOptimal,
+            stages: pso::PipelineStage::FRAGMENT_SHADER,
+        };
+        vec![access, access]
+    }
+
+    fn build(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        let env = FlatEnvironmentSub::new(factory)?;
+        let planets = PlanetSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        let stars = StarSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        let rings = RingSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        let occluders = OccluderSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        let scene = SceneSub::new(ctx, factory, images.as_slice(), pso::ShaderStageFlags::FRAGMENT)?;
+        let vertex = StaticVertexBuffer::new();
+        let (pipeline, pipeline_layout) = build_custom_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            vec![env.raw_layout(), planets.raw_layout(), stars.raw_layout(), scene.raw_layout(), rings.raw_layout(), occluders.raw_layout()],
+            None,
+        )?;
+
+        Ok(Box::new(DrawAerialPerspective::<B> {
+            pipeline,
+            pipeline_layout,
+            env,
+            vertex,
+            planets,
+            stars,
+            scene,
+            rings,
+            occluders,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct DrawAerialPerspective<B: Backend> {
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    env: FlatEnvironmentSub<B>,
+    vertex: StaticVertexBuffer<B, Position>,
+    planets: PlanetSub<B>,
+    stars: StarSub<B>,
+    scene: SceneSub<B>,
+    /// The rings cast shadows on the scene geometry.
+    rings: RingSub<B>,
+    /// Planets and moons cast shadows on the scene geometry and the haze.
+    occluders: OccluderSub<B>,
+}
+
+impl<B: Backend> RenderGroup<B, World> for DrawAerialPerspective<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) -> PrepareResult {
+
+        self.env.process(factory, index, world);
+        self.planets.process(factory, index, world);
+        self.stars.process(factory, index, world);
+        self.rings.process(factory, index, world);
+        self.occluders.process(factory, index, world);
+
+        self.vertex.prepare(
+            factory,
+            queue,
+            &STATIC_VERTEX_DATA,
+            Some(&STATIC_INSTANCE_DATA),
+            index
+        ).expect("Failed to prepare static vertex buffer!");
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _world: &World,
+    ) {
+        // The scene is always copied to the main target, even when there are no atmospheres to add haze.
+        encoder.bind_graphics_pipeline(&self.pipeline);
+        self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
+        self.planets.bind(index, &self.pipeline_layout, 1, &mut encoder);
+        self.stars.bind(index, &self.pipeline_layout, 2, &mut encoder);
+        self.scene.bind(&self.pipeline_layout, 3, &mut encoder);
+        self.rings.bind(index, &self.pipeline_layout, 4, &mut encoder);
+        self.occluders.bind(index, &self.pipeline_layout, 5, &mut encoder);
+        unsafe {
+            self.vertex.draw(&mut encoder, 0..1, index);
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+        }
+    }
+}
+
+fn build_custom_pipeline<B: Backend>(
+    factory: &Factory<B>,
+    subpass: hal::pass::Subpass<'_, B>,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    layouts: Vec<&B::DescriptorSetLayout>,
+    push_constant: Option<(hal::pso::ShaderStageFlags, Range<u32>)>,
+) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+    let pipeline_layout = unsafe {
+        factory
+            .device()
+            .create_pipeline_layout(layouts, push_constant)
+    }?;
+    // Load the shaders
+    let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
+    let shader_fragment = unsafe { FRAGMENT.module(factory).unwrap() };
+
+    // Build the pipeline
+    let pipes = PipelinesBuilder::new()
+        .with_pipeline(
+            PipelineDescBuilder::new()
+                .with_vertex_desc(&[(Position::vertex(), pso::VertexInputRate::Vertex)])
+                .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleList))
+                // Add the shaders
+                .with_shaders(util::simple_shader_set(
+                    &shader_vertex,
+                    Some(&shader_fragment),
+                ))
+                .with_layout(&pipeline_layout)
+                .with_subpass(subpass)
+                .with_framebuffer_size(framebuffer_width, framebuffer_height)
+                // The scene depth is written to the main target so that later passes are hidden behind the scene geometry.
+                .with_depth_test(pso::DepthTest {
+                    fun: pso::Comparison::Less,
+                    write: true,
+                })
+                .with_blend_targets(vec![pso::ColorBlendDesc { blend: None, mask: pso::ColorMask::ALL }]),
+        )
+        .build(factory, None);
+
+    // Destoy the shaders once loaded
+    unsafe {
+        factory.destroy_shader_module(shader_vertex);
+        factory.destroy_shader_module(shader_fragment);
+    }
+
+    // Handle the Errors
+    match pipes {
+        Err(e) => {
+            unsafe {
+                factory.device().destroy_pipeline_layout(pipeline_layout);
+            }
+            Err(e)
+        }
+        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+    }
+}
+
+/// A [RenderPlugin] which adds atmospheric haze to the scene geometry.
+/// The scene must be drawn to the `SCENE_TARGET` target instead of the main target, since the depth of each pixel is needed.
+#[derive(Debug, Default)]
+pub struct AerialPerspectiveRender {
+    /// The size of the scene target, which follows the size of the screen.
+    dimensions: Option<ScreenDimensions>,
+    dirty: bool,
+}
+
+impl AerialPerspectiveRender {
+    /// Creates the aerial perspective plugin.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for AerialPerspectiveRender {
+    fn should_rebuild(&mut self, world: &World) -> bool {
+        let new_dimensions = world.try_fetch::<ScreenDimensions>();
+        if self.dimensions.as_ref() != new_dimensions.as_deref() {
+            self.dirty = true;
+            self.dimensions = new_dimensions.map(|d| (*d).clone());
+            return false;
+        }
+        self.dirty
+    }
+
+    fn on_build<'a, 'b>(
+        &mut self,
+        world: &mut World,
+        _builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        world.register::<crate::Planet>();
+        world.register::<crate::Atmosphere>();
+        world.register::<crate::Rings>();
+        world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        world: &World,
+    ) -> Result<(), Error> {
+        self.dirty = false;
+        let dimensions = self.dimensions.clone().unwrap_or_else(|| (*world.read_resource::<ScreenDimensions>()).clone());
+        let kind = hal::image::Kind::D2(dimensions.width() as u32, dimensions.height() as u32, 1, 1);
+
+        plan.define_pass(
+            Target::Custom(SCENE_TARGET),
+            TargetPlanOutputs {
+                colors: vec![OutputColor::Image(ImageOptions {
+                    kind,
+                    levels: 1,
+                    format: hal::format::Format::Rgba16Sfloat,
+                    clear: Some(hal::command::ClearValue::Color(hal::command::ClearColor::Sfloat([0.0; 4]))),
+                })],
+                depth: Some(ImageOptions {
+                    kind,
+                    levels: 1,
+                    format: hal::format::Format::D32Sfloat,
+                    clear: Some(hal::command::ClearValue::DepthStencil(hal::command::ClearDepthStencil(1.0, 0))),
+                }),
+            },
+        )?;
+
+        plan.extend_target(Target::Main, |ctx| {
+            let color = ctx.get_image(TargetImage::Color(Target::Custom(SCENE_TARGET), 0))?;
+            let depth = ctx.get_image(TargetImage::Depth(Target::Custom(SCENE_TARGET)))?;
+            // This is drawn before everything else so that the scene depth is in the main target for the later passes.
+            ctx.add(RenderOrder::BeforeOpaque, DrawAerialPerspectiveDesc::new().builder().with_image(color).with_image(depth))?;
+            Ok(())
+        });
+        Ok(())
+    }
+}
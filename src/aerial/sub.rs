@@ -0,0 +1,85 @@
+use amethyst::renderer::{
+    rendy::{
+        command::RenderPassEncoder,
+        factory::Factory,
+        graph::{GraphContext, NodeImage},
+        hal::{self, Device},
+        resource::{
+            DescriptorSet,
+            DescriptorSetLayout,
+            Escape,
+            Handle,
+            ImageView,
+            ImageViewInfo,
+            Sampler,
+        },
+    },
+    types::Backend,
+    util,
+};
+
+/// The color and depth images of the scene target, bound as two combined image samplers.
+#[derive(Debug)]
+pub(crate) struct SceneSub<B: Backend> {
+    layout: Handle<DescriptorSetLayout<B>>,
+    set: Escape<DescriptorSet<B>>,
+    // The views and sampler must outlive the descriptor set which refers to them.
+    _views: Vec<Escape<ImageView<B>>>,
+    _sampler: Handle<Sampler<B>>,
+}
+
+impl<B: Backend> SceneSub<B> {
+    /// Creates the descriptor set for the node images, which must be the scene color followed by the scene depth.
+    pub fn new(ctx: &GraphContext<B>, factory: &Factory<B>, images: &[NodeImage], flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
+        let layout: Handle<DescriptorSetLayout<B>> = factory
+            .create_descriptor_set_layout(util::set_layout_bindings(Some((
+                images.len() as u32,
+                hal::pso::DescriptorType::CombinedImageSampler,
+                flags,
+            ))))?
+            .into();
+        let set = factory.create_descriptor_set(layout.clone())?;
+        // The images are read with `texelFetch`, so the filter doesn't matter.
+        let sampler = factory.get_sampler(hal::image::SamplerInfo::new(hal::image::Filter::Nearest, hal::image::WrapMode::Clamp))?;
+
+        let mut views = Vec::with_capacity(images.len());
+        for (binding, node_image) in images.iter().enumerate() {
+            let image = ctx
+                .get_image(node_image.id)
+                .ok_or_else(|| failure::format_err!("The scene target image {} does not exist.", binding))?;
+            let view = factory.create_image_view(
+                image.clone(),
+                ImageViewInfo {
+                    view_kind: hal::image::ViewKind::D2,
+                    format: image.format(),
+                    swizzle: hal::format::Swizzle::NO,
+                    range: node_image.range.clone(),
+                },
+            )?;
+            let desc = hal::pso::Descriptor::CombinedImageSampler(view.raw(), hal::image::Layout::ShaderReadOnlyOptimal, sampler.raw());
+            unsafe {
+                factory.write_descriptor_sets(Some(util::desc_write(set.raw(), binding as u32, desc)));
+            }
+            views.push(view);
+        }
+
+        Ok(Self { layout, set, _views: views, _sampler: sampler })
+    }
+
+    /// Returns the `DescriptorSetLayout` for this set.
+    #[inline]
+    pub fn raw_layout(&self) -> &B::DescriptorSetLayout {
+        self.layout.raw()
+    }
+
+    pub fn bind(&self, pipeline_layout: &B::PipelineLayout, set_id: u32, encoder: &mut RenderPassEncoder<'_, B>) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                pipeline_layout,
+                set_id,
+                Some(self.set.raw()),
+                std::iter::empty(),
+            );
+        }
+    }
+}
@@ -0,0 +1,171 @@
+pub mod pass;
+pub(crate) mod sub;
+
+use amethyst::{
+    assets::{Handle, PrefabData},
+    derive::PrefabData,
+    ecs::prelude::*,
+    error::Error,
+    renderer::{palette::Srgb, Texture},
+    core::math::{Matrix4, Vector3},
+};
+
+use glsl_layout::*;
+
+use serde::{Serialize, Deserialize};
+
+use crate::planet::MAX_PLANETS;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// A layer of clouds around a planet, which is a spherical shell at a fixed altitude.
+/// The clouds are lit by every `Star`, and the light reaching them is filtered by the `Atmosphere` on the same entity.
+pub struct Clouds {
+    /// The height of the bottom of the clouds above the ground, relative to the planet's radius.
+    pub altitude: f32,
+
+    /// The thickness of the clouds, relative to the planet's radius.
+    pub thickness: f32,
+
+    /// The fraction of the sky which is covered by clouds (0.0 to 1.0).
+    pub coverage: f32,
+
+    /// The optical depth straight through the thickest part of the clouds. Larger values make the clouds more opaque.
+    pub density: f32,
+
+    /// The number of cloud formations across a planet with a radius of 1.0.
+    pub scale: f32,
+
+    /// The speed (in radians per second) at which the clouds are carried around the planet's axis by the wind.
+    pub wind_speed: f32,
+
+    /// The rate at which the shapes of the clouds change.
+    pub turbulence: f32,
+
+    /// The seed of the procedural coverage.
+    pub seed: u32,
+
+    /// The color of the clouds when they are fully lit.
+    #[serde(with = "amethyst::renderer::serde_shim::srgb")]
+    pub color: Srgb,
+
+    /// An equirectangular map of the coverage (in the red channel), which replaces the procedural coverage.
+    /// The longitude runs around the planet's Y axis and the top of the map is the north (+Y) pole.
+    /// Noise is still used for the detail of the clouds.
+    #[serde(skip)]
+    pub texture: Option<Handle<Texture>>,
+}
+
+impl Default for Clouds {
+    fn default() -> Self {
+        Self {
+            altitude: 0.003,
+            thickness: 0.002,
+            coverage: 0.45,
+            density: 12.0,
+            scale: 6.0,
+            wind_speed: 0.002,
+            turbulence: 0.01,
+            seed: 0,
+            color: Srgb::new(1.0, 1.0, 1.0),
+            texture: None,
+        }
+    }
+}
+
+impl Clouds {
+    /// Create a new cloud layer with the specified altitude, thickness and coverage (relative to the planet's radius).
+    pub fn new(altitude: f32, thickness: f32, coverage: f32) -> Self {
+        Self { altitude, thickness, coverage, ..Default::default() }
+    }
+
+    /// Changes the opacity and color of the clouds.
+    pub fn with_density(mut self, density: f32, color: Srgb) -> Self {
+        self.density = density;
+        self.color = color;
+        self
+    }
+
+    /// Changes the size and seed of the procedural coverage.
+    pub fn with_noise(mut self, scale: f32, seed: u32) -> Self {
+        self.scale = scale;
+        self.seed = seed;
+        self
+    }
+
+    /// Changes the speed of the wind and the rate at which the clouds change shape.
+    pub fn with_wind(mut self, wind_speed: f32, turbulence: f32) -> Self {
+        self.wind_speed = wind_speed;
+        self.turbulence = turbulence;
+        self
+    }
+
+    /// Uses a texture for the coverage instead of noise.
+    pub fn with_texture(mut self, texture: Handle<Texture>) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+}
+
+impl Component for Clouds {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub const MAX_CLOUDS: usize = MAX_PLANETS;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct CloudData {
+    /// Rotates world space directions into the planet's frame, including the rotation of the wind.
+    pub orientation: mat4,
+    pub center: vec3,
+    pub radius: float,
+    pub altitude: float,
+    pub thickness: float,
+    pub coverage: float,
+    pub density: float,
+    pub color: vec3,
+    pub scale: float,
+    pub evolution: float,
+    pub seed: float,
+    /// The index of the planet's atmosphere in the `PlanetList`, or `NO_PLANET` if it doesn't have one.
+    pub planet: uint,
+}
+
+impl CloudData {
+    pub(crate) fn new(clouds: &Clouds, planet: u32, orientation: Matrix4<f32>, center: Vector3<f32>, radius: f32, time: f64) -> Self {
+        let wind = Matrix4::from_axis_angle(&Vector3::y_axis(), (time * f64::from(clouds.wind_speed) % (2.0 * std::f64::consts::PI)) as f32);
+        Self {
+            orientation: Into::<[[f32; 4]; 4]>::into(wind * orientation).into(),
+            center: Into::<[f32; 3]>::into(center).into(),
+            radius,
+            altitude: clouds.altitude,
+            thickness: clouds.thickness,
+            coverage: clouds.coverage,
+            density: clouds.density,
+            color: [clouds.color.red, clouds.color.green, clouds.color.blue].into(),
+            scale: clouds.scale,
+            evolution: (time * f64::from(clouds.turbulence) % 1000.0) as f32,
+            seed: (clouds.seed % 1024) as f32,
+            planet,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct CloudList {
+    pub(crate) count: uint,
+    clouds: [CloudData; MAX_CLOUDS],
+}
+
+impl CloudList {
+    pub(crate) fn new(cloud_data: &[CloudData]) -> Self {
+        assert!(cloud_data.len() <= MAX_CLOUDS);
+        let mut clouds: [CloudData; MAX_CLOUDS] = Default::default();
+        for (i, data) in cloud_data.iter().enumerate() {
+            clouds[i] = *data;
+        }
+        Self { clouds, count: cloud_data.len() as u32 }
+    }
+}
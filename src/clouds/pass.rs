@@ -0,0 +1,111 @@
+use amethyst::{
+    core::ecs::{
+        DispatcherBuilder, World,
+    },
+    error::Error,
+    renderer::{
+        bundle::{RenderOrder, RenderPlan, RenderPlugin, Target},
+        rendy::{
+            command::QueueId,
+            factory::Factory,
+            graph::{
+                GraphContext,
+                NodeBuffer, NodeImage, render::{RenderGroup, RenderGroupDesc},
+            },
+            hal::{self, pso, pso::ShaderStageFlags},
+            shader::SpirvShader,
+        },
+        types::Backend,
+    },
+};
+
+use super::sub::CloudSub;
+
+use crate::renderutils::LayerPass;
+
+use amethyst::prelude::WorldExt;
+
+lazy_static::lazy_static! {
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/clouds.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawCloudsDesc;
+
+impl DrawCloudsDesc {
+    /// Create instance of `DrawCloudsDesc` render group
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawCloudsDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        // Cloud layers with a coverage texture read it instead of generating the coverage.
+        LayerPass {
+            fragment: &FRAGMENT,
+            // The clouds are hidden by nearer geometry, but they don't hide the atmosphere which is drawn over them.
+            depth_test: pso::DepthTest {
+                fun: pso::Comparison::Less,
+                write: false,
+            },
+            blend: pso::BlendState::ALPHA,
+            push: None,
+        }.build::<B, CloudSub<B>>(factory, subpass, framebuffer_width, framebuffer_height)
+    }
+}
+
+/// A [RenderPlugin] which draws every `Clouds` layer.
+/// The clouds are drawn before the atmospheres, so the haze of the atmosphere covers distant clouds.
+#[derive(Debug, Default)]
+pub struct CloudRender;
+
+impl CloudRender {
+    /// Creates the cloud plugin.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for CloudRender {
+    fn on_build<'a, 'b>(
+        &mut self,
+        world: &mut World,
+        _builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        world.register::<super::Clouds>();
+        world.register::<crate::Planet>();
+        world.register::<crate::Atmosphere>();
+        world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _world: &World,
+    ) -> Result<(), Error> {
+        plan.extend_target(Target::Main, |ctx| {
+            ctx.add(RenderOrder::AfterTransparent, DrawCloudsDesc::new().builder())?;
+            Ok(())
+        });
+        Ok(())
+    }
+}
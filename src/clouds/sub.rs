@@ -0,0 +1,95 @@
+use amethyst::{
+    assets::Handle,
+    core::{
+        timing::Time,
+        transform::Transform,
+        math::{
+            Matrix4,
+            Vector4,
+            U3,
+        }
+    },
+    renderer::{
+        submodules::DynamicUniform,
+        rendy::{
+            command::RenderPassEncoder,
+            factory::Factory,
+            hal,
+        },
+        types::Backend,
+        Texture,
+    },
+    ecs::prelude::*,
+};
+
+use super::*;
+use crate::{
+    planet::{sub::planet_index, Atmosphere, Planet},
+    renderutils::{camera_position, uniform_scale, LayerSub},
+};
+
+#[derive(Debug)]
+pub(crate) struct CloudSub<B: Backend> {
+    uniform: DynamicUniform<B, CloudList>,
+    data: CloudList,
+    /// The coverage texture of each cloud layer, in the same order as the list.
+    textures: Vec<Option<Handle<Texture>>>,
+}
+
+impl<B: Backend> LayerSub<B> for CloudSub<B> {
+    fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
+        let uniform = DynamicUniform::new(factory, flags)?;
+        Ok(Self { uniform, data: CloudList::default(), textures: Vec::new() })
+    }
+
+    fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
+        let time = world.try_fetch::<Time>().map(|time| time.absolute_time_seconds()).unwrap_or(0.0);
+        let atmospheres = world.read_storage::<Atmosphere>();
+        let planets = world.read_storage::<Planet>();
+        let camera = camera_position(world);
+        let mut layers: Vec<(f32, CloudData, Option<Handle<Texture>>)> = Vec::new();
+        for (entity, clouds, atmosphere, planet, transform) in (&world.entities(), &world.read_storage::<Clouds>(), atmospheres.maybe(), planets.maybe(), &world.read_storage::<Transform>()).join() {
+            let matrix: Matrix4<f32> = *transform.global_matrix();
+            let translation: Vector4<f32> = matrix.column(3).into();
+            let scale = uniform_scale(&matrix);
+            // The clouds follow the radius of the atmosphere, so that they line up with the scattering.
+            let radius = match (atmosphere, planet) {
+                (Some(atmosphere), _) => atmosphere.base_planet_radius * scale,
+                (None, Some(planet)) => planet.radius * scale,
+                (None, None) => continue,
+            };
+            if layers.len() == MAX_CLOUDS {
+                break;
+            }
+            // The inverse of the planet's rotation turns world space directions into the planet's frame.
+            let rotation = matrix.fixed_slice::<U3, U3>(0, 0) / uniform_scale(&matrix);
+            let orientation = rotation.transpose().to_homogeneous();
+            let distance = camera.map_or(0.0, |camera| (camera - translation.xyz()).norm());
+            layers.push((distance, CloudData::new(clouds, planet_index(world, entity), orientation, translation.xyz(), radius, time), clouds.texture.clone()));
+        }
+        // The layers are drawn from back to front, so that nearer clouds are blended over the ones behind them.
+        layers.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let cloud_list: Vec<CloudData> = layers.iter().map(|(_, data, _)| *data).collect();
+        self.textures = layers.into_iter().map(|(_, _, texture)| texture).collect();
+        self.data = CloudList::new(cloud_list.as_slice());
+        self.uniform.write(factory, index, self.data.std140());
+    }
+
+    /// Returns the raw `DescriptorSetLayout` for this environment
+    fn raw_layout(&self) -> &B::DescriptorSetLayout {
+        self.uniform.raw_layout()
+    }
+
+    fn bind(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        self.uniform.bind(index,  pipeline_layout, binding_id, encoder);
+    }
+
+    /// Gets the coverage texture of each cloud layer, in the order of the list.
+    fn textures(&self) -> &[Option<Handle<Texture>>] {
+        self.textures.as_slice()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.count == 0
+    }
+}
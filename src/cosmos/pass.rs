@@ -175,10 +175,10 @@ impl<B: Backend> RenderGroup<B, World> for DrawCosmos<B> {
         _subpass: hal::pass::Subpass<'_, B>,
         _world: &World,
     ) {
-        if self.star_list.len() != 0 {
+        if !self.star_list.is_empty() {
             encoder.bind_graphics_pipeline(&self.pipeline);
             self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
-            if let Ok(_) = self.star_buffer.bind(&self.pipeline_layout, 1, &mut encoder) {
+            if self.star_buffer.bind(&self.pipeline_layout, 1, &mut encoder).is_ok() {
                 unsafe {
                     self.vertex.draw(&mut encoder, 0..self.star_list.len() as u32, index);
                 }
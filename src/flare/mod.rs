@@ -0,0 +1,244 @@
+pub mod sub;
+pub mod pass;
+mod readback;
+
+pub use pass::FlareRender;
+
+use std::collections::HashMap;
+
+use amethyst::{
+    ecs::prelude::*,
+    renderer::palette::Srgb,
+};
+
+use serde::{Serialize, Deserialize};
+
+use glsl_layout::*;
+
+use crate::star::MAX_STARS;
+
+pub const MAX_FLARE_ELEMENTS: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// The shape of a single flare sprite.
+pub enum FlareElementKind {
+    /// A soft edged disc, like the reflection of the aperture.
+    Ghost,
+    /// A thin ring around the sprite's center.
+    Halo,
+}
+
+impl FlareElementKind {
+    fn id(self) -> u32 {
+        match self {
+            FlareElementKind::Ghost => 0,
+            FlareElementKind::Halo => 1,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// A single sprite in the chain of a lens flare.
+pub struct FlareElement {
+    /// The shape of the sprite.
+    pub kind: FlareElementKind,
+
+    /// The position along the flare axis.
+    /// 0.0 is on the star, 1.0 is the center of the screen and 2.0 is the star mirrored about the center.
+    pub position: f32,
+
+    /// The radius of the sprite relative to the height of the screen.
+    pub scale: f32,
+
+    /// The tint of the sprite (this is multiplied by the star's color).
+    #[serde(with = "amethyst::renderer::serde_shim::srgb")]
+    pub color: Srgb,
+
+    /// The brightness of the sprite.
+    pub intensity: f32,
+}
+
+impl FlareElement {
+    /// Creates a new flare element with the specified data.
+    pub fn new(kind: FlareElementKind, position: f32, scale: f32, color: Srgb, intensity: f32) -> Self {
+        Self { kind, position, scale, color, intensity }
+    }
+
+    /// Creates a new ghost sprite.
+    pub fn ghost(position: f32, scale: f32, color: Srgb, intensity: f32) -> Self {
+        Self::new(FlareElementKind::Ghost, position, scale, color, intensity)
+    }
+
+    /// Creates a new halo sprite.
+    pub fn halo(position: f32, scale: f32, color: Srgb, intensity: f32) -> Self {
+        Self::new(FlareElementKind::Halo, position, scale, color, intensity)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// The configuration of the lens flare drawn for every visible `Star`.
+/// This is stored as a resource and can be changed at runtime.
+pub struct LensFlare {
+    /// The chain of sprites which make up the flare.
+    elements: Vec<FlareElement>,
+
+    /// The overall brightness of the flare.
+    pub intensity: f32,
+}
+
+impl LensFlare {
+    /// Creates a new lens flare with the specified chain of sprites.
+    pub fn new(elements: Vec<FlareElement>, intensity: f32) -> Self {
+        assert!(elements.len() <= MAX_FLARE_ELEMENTS);
+        Self { elements, intensity }
+    }
+
+    /// Adds an element to the end of the chain.
+    pub fn with_element(mut self, element: FlareElement) -> Self {
+        assert!(self.elements.len() < MAX_FLARE_ELEMENTS);
+        self.elements.push(element);
+        self
+    }
+
+    /// Gets the chain of sprites which make up the flare.
+    pub fn elements(&self) -> &[FlareElement] {
+        self.elements.as_slice()
+    }
+
+    /// Changes the chain of sprites which make up the flare.
+    pub fn set_elements(&mut self, elements: Vec<FlareElement>) {
+        assert!(elements.len() <= MAX_FLARE_ELEMENTS);
+        self.elements = elements;
+    }
+}
+
+impl Default for LensFlare {
+    /// A subtle flare made up of a halo around the star and a few ghosts across the screen.
+    fn default() -> Self {
+        Self::new(
+            vec![
+                FlareElement::halo(0.0, 0.25, Srgb::new(1.0, 0.9, 0.8), 0.15),
+                FlareElement::ghost(0.4, 0.04, Srgb::new(0.6, 0.8, 1.0), 0.2),
+                FlareElement::ghost(0.7, 0.08, Srgb::new(0.4, 1.0, 0.6), 0.12),
+                FlareElement::ghost(1.2, 0.03, Srgb::new(1.0, 0.6, 0.4), 0.25),
+                FlareElement::halo(1.4, 0.12, Srgb::new(0.6, 0.6, 1.0), 0.1),
+                FlareElement::ghost(1.7, 0.15, Srgb::new(0.8, 0.5, 1.0), 0.08),
+                FlareElement::ghost(2.0, 0.06, Srgb::new(1.0, 0.8, 0.5), 0.15),
+            ],
+            1.0,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// The fraction of each `Star` which is visible to the camera, as measured by the flare pass.
+/// The values lag a few frames behind since they are read back from the GPU.
+/// On devices which can't store to buffers from fragment shaders this falls back to the CPU `StarOcclusion`.
+pub struct StarVisibility {
+    visibility: HashMap<Entity, f32>,
+}
+
+impl StarVisibility {
+    /// Gets the visible fraction (0.0 to 1.0) of the specified star entity.
+    pub fn get(&self, entity: Entity) -> Option<f32> {
+        self.visibility.get(&entity).copied()
+    }
+
+    /// Iterates over every measured star and its visible fraction.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, f32)> + '_ {
+        self.visibility.iter().map(|(entity, visibility)| (*entity, *visibility))
+    }
+
+    pub(crate) fn set(&mut self, entity: Entity, visibility: f32) {
+        self.visibility.insert(entity, visibility);
+    }
+
+    pub(crate) fn retain(&mut self, entities: &[Entity]) {
+        self.visibility.retain(|entity, _| entities.contains(entity));
+    }
+}
+
+/// The star entities in the order that the last frame probed them, which is used to match up the read back sample counts.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FlareProbes {
+    probed: Vec<Entity>,
+}
+
+impl FlareProbes {
+    pub(crate) fn set(&mut self, probed: Vec<Entity>) {
+        self.probed = probed;
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<Entity> {
+        std::mem::take(&mut self.probed)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct FlareElementData {
+    pub color: vec3,
+    pub position: float,
+    pub scale: float,
+    pub intensity: float,
+    pub kind: uint,
+}
+
+impl From<FlareElement> for FlareElementData {
+    fn from(element: FlareElement) -> Self {
+        Self {
+            color: [element.color.red, element.color.green, element.color.blue].into(),
+            position: element.position,
+            scale: element.scale,
+            intensity: element.intensity,
+            kind: element.kind.id(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct FlareElementList {
+    pub(crate) count: uint,
+    intensity: float,
+    elements: [FlareElementData; MAX_FLARE_ELEMENTS],
+}
+
+impl FlareElementList {
+    pub(crate) fn new(lens_flare: &LensFlare) -> Self {
+        let mut elements: [FlareElementData; MAX_FLARE_ELEMENTS] = Default::default();
+        for (i, element) in lens_flare.elements().iter().enumerate() {
+            elements[i] = FlareElementData::from(*element);
+        }
+        Self { elements, intensity: lens_flare.intensity, count: lens_flare.elements().len() as u32 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct FlareStarData {
+    pub center: vec3,
+    pub radius: float,
+    pub color: vec3,
+    pub visibility: float,
+    pub brightness: float,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct FlareStarList {
+    viewport: vec2,
+    pub(crate) count: uint,
+    stars: [FlareStarData; MAX_STARS],
+}
+
+impl FlareStarList {
+    pub(crate) fn new(viewport: [f32; 2], star_data: &[FlareStarData]) -> Self {
+        assert!(star_data.len() <= MAX_STARS);
+        let mut stars: [FlareStarData; MAX_STARS] = Default::default();
+        for (i, data) in star_data.iter().enumerate() {
+            stars[i] = *data;
+        }
+        Self { viewport: viewport.into(), stars, count: star_data.len() as u32 }
+    }
+}
@@ -0,0 +1,377 @@
+use std::ops::Range;
+
+use amethyst::{
+    core::ecs::{
+        DispatcherBuilder, World,
+    },
+    error::Error,
+    renderer::{
+        bundle::{RenderOrder, RenderPlan, RenderPlugin, Target},
+        pipeline::{PipelineDescBuilder, PipelinesBuilder},
+        rendy::{
+            command::{QueueId, RenderPassEncoder},
+            factory::Factory,
+            graph::{
+                BufferAccess, GraphContext, NodeDesc,
+                NodeBuffer, NodeImage, render::{PrepareResult, RenderGroup, RenderGroupDesc},
+            },
+            hal::{self, adapter::PhysicalDevice, device::Device,  pso, pso::ShaderStageFlags},
+            mesh::{AsVertex, Position},
+            shader::{Shader, SpirvShader},
+        },
+        submodules::FlatEnvironmentSub,
+        types::Backend, util,
+    },
+};
+
+use super::*;
+use crate::{
+    flare::{readback::SampleReadbackDesc, sub::*},
+};
+
+use crate::renderutils::*;
+
+use amethyst::prelude::WorldExt;
+
+const STATIC_DEPTH: f32 = 0.0;
+
+const STATIC_VERTEX_DATA: [Position; 4] = [
+    Position([-1.0, -1.0, STATIC_DEPTH]),
+    Position([-1.0, 1.0, STATIC_DEPTH]),
+    Position([1.0, 1.0, STATIC_DEPTH]),
+    Position([1.0, -1.0, STATIC_DEPTH]),
+];
+
+const STATIC_INSTANCE_DATA: [u32; 6] = [0, 1, 2, 0, 3, 2];
+
+lazy_static::lazy_static! {
+    static ref PROBE_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/flare_probe.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+
+    static ref PROBE_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/flare_probe.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+
+    static ref FLARE_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/flare.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+
+    static ref FLARE_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/flare.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawFlareDesc {
+    probe: bool,
+}
+
+impl DrawFlareDesc {
+    /// Create instance of `DrawFlareDesc` render group
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Measures the visibility of each star with the probe, which counts its samples into the group's buffer.
+    /// The device must support `FRAGMENT_STORES_AND_ATOMICS`.
+    pub fn with_probe(mut self) -> Self {
+        self.probe = true;
+        self
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawFlareDesc {
+    fn buffers(&self) -> Vec<BufferAccess> {
+        if self.probe {
+            vec![BufferAccess {
+                access: hal::buffer::Access::SHADER_WRITE,
+                usage: hal::buffer::Usage::STORAGE,
+                stages: pso::PipelineStage::FRAGMENT_SHADER,
+            }]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn build(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        let samples = match buffers.first() {
+            Some(buffer) => Some(
+                ctx.get_buffer(buffer.id)
+                    .ok_or_else(|| failure::format_err!("The flare sample buffer was not allocated by the graph."))?
+                    .raw(),
+            ),
+            None => None,
+        };
+        let env = FlatEnvironmentSub::new(factory)?;
+        let flare = FlareSub::new(factory, framebuffer_width, framebuffer_height, samples)?;
+        let vertex = StaticVertexBuffer::new();
+
+        // The probe pipeline only counts the samples which pass the depth test, so it writes nothing to the color target.
+        let probe = match flare.samples_layout() {
+            Some(samples_layout) => Some(build_custom_pipeline(
+                factory,
+                subpass,
+                framebuffer_width,
+                framebuffer_height,
+                vec![env.raw_layout(), flare.stars_layout(), samples_layout],
+                None,
+                (&*PROBE_VERTEX, &*PROBE_FRAGMENT),
+                Some(pso::DepthTest {
+                    fun: pso::Comparison::LessEqual,
+                    write: false,
+                }),
+                pso::ColorBlendDesc { blend: None, mask: pso::ColorMask::empty() },
+            )?),
+            None => None,
+        };
+
+        let (flare_pipeline, flare_pipeline_layout) = build_custom_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            vec![env.raw_layout(), flare.stars_layout(), flare.elements_layout()],
+            None,
+            (&*FLARE_VERTEX, &*FLARE_FRAGMENT),
+            None,
+            pso::ColorBlendDesc { blend: Some(pso::BlendState::ADD), mask: pso::ColorMask::ALL },
+        )?;
+
+        Ok(Box::new(DrawFlare::<B> {
+            probe,
+            flare_pipeline,
+            flare_pipeline_layout,
+            env,
+            vertex,
+            flare,
+        }))
+    }
+}
+
+/// Measures the visibility of each star and draws its lens flare.
+#[derive(Debug)]
+pub struct DrawFlare<B: Backend> {
+    /// The pipeline which probes the visibility of each star, if the device supports it.
+    probe: Option<(B::GraphicsPipeline, B::PipelineLayout)>,
+    flare_pipeline: B::GraphicsPipeline,
+    flare_pipeline_layout: B::PipelineLayout,
+    env: FlatEnvironmentSub<B>,
+    vertex: StaticVertexBuffer<B, Position>,
+    flare: FlareSub<B>,
+}
+
+impl<B: Backend> RenderGroup<B, World> for DrawFlare<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) -> PrepareResult {
+
+        self.env.process(factory, index, world);
+        self.flare.process(factory, index, world);
+
+        self.vertex.prepare(
+            factory,
+            queue,
+            &STATIC_VERTEX_DATA,
+            Some(&STATIC_INSTANCE_DATA),
+            index
+        ).expect("Failed to prepare static vertex buffer!");
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _world: &World,
+    ) {
+        let star_count = self.flare.star_count() as u32;
+        if star_count == 0 {
+            return;
+        }
+
+        // Probe the visibility of each star against the depth buffer.
+        if let Some((probe_pipeline, probe_pipeline_layout)) = &self.probe {
+            encoder.bind_graphics_pipeline(probe_pipeline);
+            self.env.bind(index, probe_pipeline_layout, 0, &mut encoder);
+            self.flare.bind_stars(index, probe_pipeline_layout, 1, &mut encoder);
+            self.flare.bind_samples(probe_pipeline_layout, 2, &mut encoder);
+            unsafe {
+                self.vertex.draw(&mut encoder, 0..star_count, index);
+            }
+        }
+
+        // Draw every element of the flare for every star.
+        let element_count = self.flare.element_count() as u32;
+        if element_count != 0 {
+            encoder.bind_graphics_pipeline(&self.flare_pipeline);
+            self.env.bind(index, &self.flare_pipeline_layout, 0, &mut encoder);
+            self.flare.bind_stars(index, &self.flare_pipeline_layout, 1, &mut encoder);
+            self.flare.bind_elements(index, &self.flare_pipeline_layout, 2, &mut encoder);
+            unsafe {
+                self.vertex.draw(&mut encoder, 0..star_count * element_count, index);
+            }
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            if let Some((probe_pipeline, probe_pipeline_layout)) = self.probe {
+                factory.device().destroy_graphics_pipeline(probe_pipeline);
+                factory
+                    .device()
+                    .destroy_pipeline_layout(probe_pipeline_layout);
+            }
+            factory.device().destroy_graphics_pipeline(self.flare_pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.flare_pipeline_layout);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_custom_pipeline<B: Backend>(
+    factory: &Factory<B>,
+    subpass: hal::pass::Subpass<'_, B>,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    layouts: Vec<&B::DescriptorSetLayout>,
+    push_constant: Option<(hal::pso::ShaderStageFlags, Range<u32>)>,
+    shaders: (&SpirvShader, &SpirvShader),
+    depth_test: Option<pso::DepthTest>,
+    blend: pso::ColorBlendDesc,
+) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+    let pipeline_layout = unsafe {
+        factory
+            .device()
+            .create_pipeline_layout(layouts, push_constant)
+    }?;
+    // Load the shaders
+    let shader_vertex = unsafe { shaders.0.module(factory).unwrap() };
+    let shader_fragment = unsafe { shaders.1.module(factory).unwrap() };
+
+    let mut desc = PipelineDescBuilder::new()
+        .with_vertex_desc(&[(Position::vertex(), pso::VertexInputRate::Vertex)])
+        .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleList))
+        .with_shaders(util::simple_shader_set(
+            &shader_vertex,
+            Some(&shader_fragment),
+        ))
+        .with_layout(&pipeline_layout)
+        .with_subpass(subpass)
+        .with_framebuffer_size(framebuffer_width, framebuffer_height)
+        .with_blend_targets(vec![blend]);
+    if let Some(depth_test) = depth_test {
+        desc = desc.with_depth_test(depth_test);
+    }
+
+    // Build the pipeline
+    let pipes = PipelinesBuilder::new()
+        .with_pipeline(desc)
+        .build(factory, None);
+
+    // Destoy the shaders once loaded
+    unsafe {
+        factory.destroy_shader_module(shader_vertex);
+        factory.destroy_shader_module(shader_fragment);
+    }
+
+    // Handle the Errors
+    match pipes {
+        Err(e) => {
+            unsafe {
+                factory.device().destroy_pipeline_layout(pipeline_layout);
+            }
+            Err(e)
+        }
+        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+    }
+}
+
+/// A [RenderPlugin] which draws lens flares for every visible `Star`.
+/// It also measures how much of each star is visible, which is exposed through the `StarVisibility` resource.
+#[derive(Debug)]
+pub struct FlareRender {
+    lens_flare: Option<LensFlare>,
+}
+
+impl FlareRender {
+    pub fn new(lens_flare: Option<LensFlare>) -> Self {
+        Self { lens_flare }
+    }
+}
+
+impl Default for FlareRender {
+    fn default() -> Self {
+        Self::new(Some(LensFlare::default()))
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for FlareRender {
+    fn on_build<'a, 'b>(
+        &mut self,
+        world: &mut World,
+        _builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        // We need to move the object out of the option to obtain it validly.
+        if let Some(lens_flare) = self.lens_flare.take() {
+            world.insert(lens_flare);
+        }
+        world.insert(StarVisibility::default());
+        world.insert(FlareProbes::default());
+        world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        factory: &mut Factory<B>,
+        _world: &World,
+    ) -> Result<(), Error> {
+        // The probe counts samples with atomics in a fragment shader, which not every device supports.
+        let probe = factory.physical().features().contains(hal::Features::FRAGMENT_STORES_AND_ATOMICS);
+        plan.extend_target(Target::Main, move |ctx| {
+            // The flare must be drawn after everything that could occlude a star.
+            if probe {
+                let samples = ctx.graph().create_buffer(SAMPLE_BUFFER_SIZE);
+                // The readback runs before the main pass, so it collects the counts that the previous frame's probes wrote.
+                let readback = ctx.graph().add_node(SampleReadbackDesc.builder().with_buffer(samples));
+                ctx.add_dep(readback);
+                ctx.add(RenderOrder::Overlay, DrawFlareDesc::new().with_probe().builder().with_buffer(samples))?;
+            } else {
+                ctx.add(RenderOrder::Overlay, DrawFlareDesc::new().builder())?;
+            }
+            Ok(())
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use amethyst::{
+    ecs::prelude::*,
+    renderer::{
+        rendy::{
+            command::{
+                CommandPool,
+                Family,
+                Graphics,
+                IndividualReset,
+                MultiShot,
+                NoSimultaneousUse,
+                Submit,
+            },
+            factory::Factory,
+            frame::{Frames, cirque::CommandCirque},
+            graph::{
+                gfx_acquire_barriers,
+                gfx_release_barriers,
+                BufferAccess,
+                GraphContext,
+                Node,
+                NodeBuffer,
+                NodeDesc,
+                NodeImage,
+                NodeSubmittable,
+            },
+            hal,
+            memory::{self, Write},
+            resource::{Buffer, BufferInfo, Escape},
+        },
+        types::Backend,
+    },
+};
+
+use super::*;
+use crate::{
+    Star,
+    flare::sub::{PROBE_SIZE, SAMPLE_BUFFER_SIZE},
+};
+
+/// A host visible copy of the sample counts, along with the stars that they belong to.
+#[derive(Debug)]
+struct ReadbackSlot<B: Backend> {
+    buffer: Escape<Buffer<B>>,
+    /// The star entities in the order that they were probed in the frame whose counts were copied into this slot.
+    probed: Vec<Entity>,
+}
+
+impl<B: Backend> ReadbackSlot<B> {
+    fn new(factory: &Factory<B>) -> Result<Self, failure::Error> {
+        let buffer = factory.create_buffer(
+            BufferInfo {
+                size: SAMPLE_BUFFER_SIZE,
+                usage: hal::buffer::Usage::TRANSFER_DST,
+            },
+            memory::Download,
+        )?;
+        Ok(Self { buffer, probed: Vec::new() })
+    }
+
+    fn read(&mut self, factory: &Factory<B>) -> Result<[u32; MAX_STARS], failure::Error> {
+        let mut counts = [0; MAX_STARS];
+        unsafe {
+            let mut mapped = self.buffer.map(factory.device(), 0..SAMPLE_BUFFER_SIZE)?;
+            let samples = mapped.read::<u32>(factory.device(), 0..SAMPLE_BUFFER_SIZE)?;
+            counts.copy_from_slice(&samples[..MAX_STARS]);
+        }
+        Ok(counts)
+    }
+
+    /// Publishes the counts in this slot to the `StarVisibility` resource.
+    /// This must only be called once the frame that copied the counts has finished.
+    fn publish(&mut self, factory: &Factory<B>, world: &World) {
+        if self.probed.is_empty() {
+            return;
+        }
+        let counts = match self.read(factory) {
+            Ok(counts) => counts,
+            Err(err) => {
+                log::error!("Failed to read back star visibility samples: {}", err);
+                return;
+            }
+        };
+        let mut star_visibility = match world.try_fetch_mut::<StarVisibility>() {
+            Some(star_visibility) => star_visibility,
+            None => return,
+        };
+        for (entity, count) in self.probed.iter().zip(counts.iter()) {
+            let visibility = *count as f32 / (PROBE_SIZE * PROBE_SIZE);
+            star_visibility.set(*entity, visibility.min(1.0));
+        }
+        let entities: Vec<Entity> = (&world.entities(), &world.read_storage::<Star>()).join().map(|(entity, _)| entity).collect();
+        star_visibility.retain(entities.as_slice());
+    }
+}
+
+/// Copies the sample counts written by the flare probes back to the host, and clears them for the next frame.
+/// It runs before the main pass, so every frame it collects the counts which the previous frame's probes wrote.
+#[derive(Debug, Default)]
+pub(crate) struct SampleReadbackDesc;
+
+impl<B: Backend> NodeDesc<B, World> for SampleReadbackDesc {
+    type Node = SampleReadback<B>;
+
+    fn buffers(&self) -> Vec<BufferAccess> {
+        vec![BufferAccess {
+            access: hal::buffer::Access::TRANSFER_READ | hal::buffer::Access::TRANSFER_WRITE,
+            usage: hal::buffer::Usage::TRANSFER_SRC | hal::buffer::Usage::TRANSFER_DST,
+            stages: hal::pso::PipelineStage::TRANSFER,
+        }]
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        family: &mut Family<B>,
+        _queue: usize,
+        _world: &World,
+        mut buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Self::Node, failure::Error> {
+        let samples = buffers.remove(0);
+
+        let mut zeros = factory.create_buffer(
+            BufferInfo {
+                size: SAMPLE_BUFFER_SIZE,
+                usage: hal::buffer::Usage::TRANSFER_SRC,
+            },
+            memory::Upload,
+        )?;
+        unsafe {
+            let mut mapped = zeros.map(factory.device(), 0..SAMPLE_BUFFER_SIZE)?;
+            let mut writer = mapped.write::<u32>(factory.device(), 0..SAMPLE_BUFFER_SIZE)?;
+            writer.slice().copy_from_slice(&[0; MAX_STARS]);
+        }
+
+        // The graph never has more frames in flight than this, so every slot that the cirque hands out has a buffer.
+        let slots = (0..ctx.frames_in_flight)
+            .map(|_| ReadbackSlot::new(factory))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pool = factory
+            .create_command_pool(family)?
+            .with_capability()
+            .map_err(|_| failure::format_err!("The flare readback requires a queue family which supports graphics."))?;
+
+        Ok(SampleReadback {
+            pool,
+            cirque: CommandCirque::new(),
+            zeros,
+            slots,
+            samples,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SampleReadback<B: Backend> {
+    pool: CommandPool<B, Graphics, IndividualReset>,
+    cirque: CommandCirque<B, Graphics>,
+    zeros: Escape<Buffer<B>>,
+    slots: Vec<ReadbackSlot<B>>,
+    samples: NodeBuffer,
+}
+
+impl<'a, B: Backend> NodeSubmittable<'a, B> for SampleReadback<B> {
+    type Submittable = Submit<B, NoSimultaneousUse>;
+    type Submittables = Option<Submit<B, NoSimultaneousUse>>;
+}
+
+impl<B: Backend> Node<B, World> for SampleReadback<B> {
+    type Capability = Graphics;
+    type Desc = SampleReadbackDesc;
+
+    fn run<'a>(
+        &'a mut self,
+        ctx: &GraphContext<B>,
+        factory: &Factory<B>,
+        world: &World,
+        frames: &'a Frames<B>,
+    ) -> Option<Submit<B, NoSimultaneousUse>> {
+        let samples_buffer = ctx.get_buffer(self.samples.id)?;
+
+        // These are the stars which were probed by the previous frame, whose counts are copied by this frame.
+        let probed = world
+            .try_fetch_mut::<FlareProbes>()
+            .map(|mut probes| probes.take())
+            .unwrap_or_default();
+
+        let Self { pool, cirque, zeros, slots, samples } = self;
+        let submit = cirque.encode(frames, pool, |cbuf| {
+            let slot = slots.get_mut(cbuf.index());
+            let host_buffer = slot.map(|slot| {
+                // The cirque only hands out a slot again once the fence of the frame that last used it has signalled,
+                // so the copy recorded below has landed in the host buffer.
+                slot.publish(factory, world);
+                slot.probed = probed;
+                slot.buffer.raw()
+            });
+
+            cbuf.or_init(|cbuf| {
+                let mut cbuf = cbuf.begin(MultiShot(NoSimultaneousUse), ());
+                let mut encoder = cbuf.encoder();
+                unsafe {
+                    let (stages, barriers) = gfx_acquire_barriers(ctx, Some(&*samples), None);
+                    if !barriers.is_empty() {
+                        encoder.pipeline_barrier(stages, hal::memory::Dependencies::empty(), barriers);
+                    }
+
+                    let region = hal::command::BufferCopy { src: 0, dst: 0, size: SAMPLE_BUFFER_SIZE };
+                    if let Some(host_buffer) = host_buffer {
+                        encoder.copy_buffer(samples_buffer.raw(), host_buffer, Some(region));
+                    }
+
+                    // The counts must be copied out before they are cleared, and the copy must be visible to the host once the fence signals.
+                    let mut barriers = vec![hal::memory::Barrier::Buffer {
+                        states: hal::buffer::Access::TRANSFER_READ..hal::buffer::Access::TRANSFER_WRITE,
+                        target: samples_buffer.raw(),
+                        families: None,
+                        range: None..None,
+                    }];
+                    if let Some(host_buffer) = host_buffer {
+                        barriers.push(hal::memory::Barrier::Buffer {
+                            states: hal::buffer::Access::TRANSFER_WRITE..hal::buffer::Access::HOST_READ,
+                            target: host_buffer,
+                            families: None,
+                            range: None..None,
+                        });
+                    }
+                    encoder.pipeline_barrier(
+                        hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::TRANSFER | hal::pso::PipelineStage::HOST,
+                        hal::memory::Dependencies::empty(),
+                        barriers,
+                    );
+
+                    encoder.copy_buffer(zeros.raw(), samples_buffer.raw(), Some(region));
+
+                    let (stages, barriers) = gfx_release_barriers(ctx, Some(&*samples), None);
+                    if !barriers.is_empty() {
+                        encoder.pipeline_barrier(stages, hal::memory::Dependencies::empty(), barriers);
+                    }
+                }
+                cbuf.finish()
+            })
+        });
+
+        Some(submit)
+    }
+
+    unsafe fn dispose(mut self, factory: &mut Factory<B>, _world: &World) {
+        let pool = &mut self.pool;
+        self.cirque.dispose(|buffer| {
+            buffer.either_with(
+                &mut *pool,
+                |pool, executable| pool.free_buffers(Some(executable)),
+                |pool, pending| {
+                    let executable = pending.mark_complete();
+                    pool.free_buffers(Some(executable))
+                },
+            );
+        });
+        factory.destroy_command_pool(self.pool.with_queue_type());
+    }
+}
@@ -0,0 +1,216 @@
+use std::mem;
+
+use amethyst::{
+    core::{
+        timing::Time,
+        transform::Transform,
+        math::{
+            Matrix4,
+            Vector4,
+        }
+    },
+    renderer::{
+        submodules::DynamicUniform,
+        rendy::{
+            command::RenderPassEncoder,
+            factory::Factory,
+            hal::{self, Device},
+            resource::{
+                DescriptorSet,
+                DescriptorSetLayout,
+                Escape,
+                Handle,
+            },
+        },
+        types::Backend,
+        util,
+    },
+    ecs::prelude::*,
+};
+
+use super::*;
+use crate::{
+    Star,
+    star::{StarPhotometry, eclipse::StarOcclusion, variability::Variability},
+    renderutils::{camera_position, uniform_scale},
+};
+
+/// The width and height of the square (in pixels) which is used to probe the visibility of a star.
+pub(crate) const PROBE_SIZE: f32 = 8.0;
+
+pub(crate) const SAMPLE_BUFFER_SIZE: u64 = (mem::size_of::<u32>() * MAX_STARS) as u64;
+
+/// The descriptor set of the storage buffer that the probe shader counts the visible samples of each star into.
+/// The buffer itself belongs to the render graph, which copies the counts back to the host once the frame has finished.
+#[derive(Debug)]
+pub(crate) struct SampleBuffer<B: Backend> {
+    layout: Handle<DescriptorSetLayout<B>>,
+    set: Escape<DescriptorSet<B>>,
+}
+
+impl<B: Backend> SampleBuffer<B> {
+    pub fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags, buffer: &B::Buffer) -> Result<Self, failure::Error> {
+        let layout: Handle<DescriptorSetLayout<B>> = factory
+            .create_descriptor_set_layout(util::set_layout_bindings(Some((
+                1,
+                hal::pso::DescriptorType::StorageBuffer,
+                flags,
+            ))))?
+            .into();
+        let set = factory.create_descriptor_set(layout.clone())?;
+        let desc = hal::pso::Descriptor::Buffer(buffer, Some(0)..Some(SAMPLE_BUFFER_SIZE));
+        unsafe {
+            factory.write_descriptor_sets(Some(util::desc_write(set.raw(), 0, desc)));
+        }
+        Ok(Self { layout, set })
+    }
+
+    /// Returns the `DescriptorSetLayout` for this set.
+    #[inline]
+    pub fn raw_layout(&self) -> &B::DescriptorSetLayout {
+        self.layout.raw()
+    }
+
+    pub fn bind(&self, pipeline_layout: &B::PipelineLayout, set_id: u32, encoder: &mut RenderPassEncoder<'_, B>) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                pipeline_layout,
+                set_id,
+                Some(self.set.raw()),
+                std::iter::empty(),
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FlareSub<B: Backend> {
+    stars: DynamicUniform<B, FlareStarList>,
+    elements: DynamicUniform<B, FlareElementList>,
+    /// This is `None` when the device can't run the probe, in which case the visibility comes from `StarOcclusion`.
+    samples: Option<SampleBuffer<B>>,
+    viewport: [f32; 2],
+    star_count: usize,
+    element_count: usize,
+}
+
+impl<B: Backend> FlareSub<B> {
+    pub fn new(factory: &Factory<B>, framebuffer_width: u32, framebuffer_height: u32, samples: Option<&B::Buffer>) -> Result<Self, failure::Error> {
+        let flags = hal::pso::ShaderStageFlags::VERTEX | hal::pso::ShaderStageFlags::FRAGMENT;
+        Ok(Self {
+            stars: DynamicUniform::new(factory, flags)?,
+            elements: DynamicUniform::new(factory, flags)?,
+            samples: match samples {
+                Some(buffer) => Some(SampleBuffer::new(factory, hal::pso::ShaderStageFlags::FRAGMENT, buffer)?),
+                None => None,
+            },
+            viewport: [framebuffer_width as f32, framebuffer_height as f32],
+            star_count: 0,
+            element_count: 0,
+        })
+    }
+
+    pub fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
+        let camera = camera_position(world);
+        let photometry = world.try_fetch::<StarPhotometry>().map(|photometry| *photometry).unwrap_or_default();
+        let star_visibility = world.try_fetch::<StarVisibility>();
+        let occlusion = world.try_fetch::<StarOcclusion>();
+        let time = world.try_fetch::<Time>().map(|time| time.absolute_time_seconds()).unwrap_or(0.0);
+        let variability = world.read_storage::<Variability>();
+        let mut probed: Vec<Entity> = Vec::new();
+        let mut star_list: Vec<FlareStarData> = Vec::new();
+        for (entity, star, transform) in (&world.entities(), &world.read_storage::<Star>(), &world.read_storage::<Transform>()).join() {
+            if star_list.len() >= MAX_STARS {
+                break;
+            }
+            let matrix: Matrix4<f32> = *transform.global_matrix();
+            let translation: Vector4<f32> = matrix.column(3).into();
+            let center = translation.xyz();
+            let radius = uniform_scale(&matrix);
+
+            let sample = variability.get(entity).map(|variability| variability.sample(time)).unwrap_or_default();
+            let color = sample.color(star.color);
+
+            // The brightness follows the same photometric model as the star's glow, so that distant stars produce faint flares.
+            let brightness = match camera {
+                Some(camera) => {
+                    let luminosity = photometry.luminosity(star, radius) * sample.intensity;
+                    photometry.brightness(photometry.flux(luminosity, (center - camera).norm().max(radius)))
+                }
+                None => 1.0,
+            };
+
+            star_list.push(FlareStarData {
+                center: Into::<[f32; 3]>::into(center).into(),
+                radius,
+                color: [color.red, color.green, color.blue].into(),
+                visibility: if self.samples.is_some() {
+                    star_visibility.as_ref().and_then(|star_visibility| star_visibility.get(entity)).unwrap_or(0.0)
+                } else {
+                    occlusion.as_ref().map(|occlusion| occlusion.visible_fraction(entity)).unwrap_or(1.0)
+                },
+                brightness,
+            });
+            probed.push(entity);
+        }
+        drop(star_visibility);
+
+        if self.samples.is_some() {
+            // The readback matches the sample counts to stars using the order that they were probed in.
+            if let Some(mut probes) = world.try_fetch_mut::<FlareProbes>() {
+                probes.set(probed);
+            }
+        } else if let Some(mut star_visibility) = world.try_fetch_mut::<StarVisibility>() {
+            // Nothing is measured without the probe, so the visibility used by the flare is exposed instead.
+            for (entity, star) in probed.iter().zip(star_list.iter()) {
+                star_visibility.set(*entity, star.visibility);
+            }
+            star_visibility.retain(probed.as_slice());
+        }
+
+        let stars = FlareStarList::new(self.viewport, star_list.as_slice());
+        self.star_count = stars.count as usize;
+        self.stars.write(factory, index, stars.std140());
+
+        let elements = match world.try_fetch::<LensFlare>() {
+            Some(lens_flare) => FlareElementList::new(&lens_flare),
+            None => FlareElementList::default(),
+        };
+        self.element_count = elements.count as usize;
+        self.elements.write(factory, index, elements.std140());
+    }
+
+    pub fn stars_layout(&self) -> &B::DescriptorSetLayout {
+        self.stars.raw_layout()
+    }
+
+    pub fn elements_layout(&self) -> &B::DescriptorSetLayout {
+        self.elements.raw_layout()
+    }
+
+    pub fn samples_layout(&self) -> Option<&B::DescriptorSetLayout> {
+        self.samples.as_ref().map(SampleBuffer::raw_layout)
+    }
+
+    pub fn bind_stars(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        self.stars.bind(index, pipeline_layout, binding_id, encoder);
+    }
+
+    pub fn bind_elements(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        self.elements.bind(index, pipeline_layout, binding_id, encoder);
+    }
+
+    pub fn bind_samples(&self, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        if let Some(samples) = &self.samples {
+            samples.bind(pipeline_layout, binding_id, encoder);
+        }
+    }
+
+    pub fn star_count(&self) -> usize {
+        self.star_count
+    }
+
+    pub fn element_count(&self) -> usize {
+        self.element_count
+    }
+}
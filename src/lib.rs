@@ -1,6 +1,14 @@
 pub mod planet;
 pub mod star;
 pub mod cosmos;
+pub mod flare;
+pub mod orbit;
+pub mod aerial;
+pub mod surface;
+pub mod clouds;
+pub mod rings;
+pub mod ocean;
+pub mod night;
 
 mod renderutils;
 
@@ -9,10 +17,26 @@ pub use renderutils::set_camera_far;
 pub use planet::{
     Planet,
     Atmosphere,
+    terrain::Terrain,
+    preset::{AtmospherePreset, AtmospherePresetBundle},
 };
 
 pub use star::Star;
+pub use star::light::StarLight;
+pub use star::variability::Variability;
+pub use orbit::{Orbit, OrbitBundle};
+pub use surface::{PlanetSurface, PlanetSurfaceBundle};
+pub use clouds::Clouds;
+pub use rings::Rings;
+pub use ocean::Ocean;
+pub use night::NightLights;
 
 pub use planet::pass::AtmosphereRender;
 pub use cosmos::pass::CosmosRender;
-pub use star::pass::StarRender;
\ No newline at end of file
+pub use star::pass::StarRender;
+pub use flare::pass::FlareRender;
+pub use aerial::pass::AerialPerspectiveRender;
+pub use clouds::pass::CloudRender;
+pub use rings::pass::RingRender;
+pub use ocean::pass::OceanRender;
+pub use night::pass::NightLightRender;
\ No newline at end of file
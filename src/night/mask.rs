@@ -0,0 +1,36 @@
+use amethyst::renderer::types::TextureData;
+
+use super::NightLights;
+use crate::planet::{
+    terrain::Terrain,
+    terrain_map::{equirectangular_map, TerrainMap, TerrainMapSystem, TerrainMaps},
+};
+
+/// Generates an equirectangular map of the land of the terrain, which is twice as wide as it is high.
+/// Each texel is 1.0 above the sea level and 0.0 below it.
+pub fn land_mask(terrain: &Terrain, resolution: u32) -> TextureData {
+    equirectangular_map(resolution, |direction| if terrain.height(direction) > terrain.sea_level { 1.0 } else { 0.0 })
+}
+
+impl TerrainMap for NightLights {
+    /// The resolution of the land mask.
+    type Settings = u32;
+
+    /// Lights with their own texture don't need a mask.
+    fn map_settings(&self) -> Option<Self::Settings> {
+        match self.texture {
+            Some(_) => None,
+            None => Some(self.mask_resolution),
+        }
+    }
+
+    fn build_map(terrain: &Terrain, resolution: &Self::Settings) -> TextureData {
+        land_mask(terrain, *resolution)
+    }
+}
+
+/// The land masks of every `NightLights` on a planet with terrain, which keep the procedural lights off the oceans.
+pub type LandMasks = TerrainMaps<NightLights>;
+
+/// Generates the land mask of every `NightLights` with a `Terrain`, whenever the terrain or the resolution changes.
+pub type LandMaskSystem = TerrainMapSystem<NightLights>;
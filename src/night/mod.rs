@@ -0,0 +1,156 @@
+pub mod pass;
+pub mod mask;
+pub(crate) mod sub;
+
+use amethyst::{
+    assets::{Handle, PrefabData},
+    derive::PrefabData,
+    ecs::prelude::*,
+    error::Error,
+    renderer::{palette::Srgb, Texture},
+    core::math::{Matrix4, Vector3},
+};
+
+use glsl_layout::*;
+
+use serde::{Serialize, Deserialize};
+
+use crate::planet::MAX_PLANETS;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// The lights of cities on the surface of the `Planet` (or `Atmosphere`) on the same entity, which glow on its night side.
+/// The lights fade in across the terminator, and are only visible where the surface faces away from every `Star`.
+/// They are dimmed by the `Atmosphere` between them and the camera, and covered by the `Clouds` on the same entity.
+pub struct NightLights {
+    /// The color of the lights.
+    #[serde(with = "amethyst::renderer::serde_shim::srgb")]
+    pub color: Srgb,
+
+    /// The brightness of the lights.
+    pub intensity: f32,
+
+    /// The fraction of the land which is covered by clusters of cities (0.0 to 1.0).
+    pub coverage: f32,
+
+    /// The number of city clusters across a planet with a radius of 1.0.
+    pub scale: f32,
+
+    /// The seed of the procedural clusters.
+    pub seed: u32,
+
+    /// The number of texels from pole to pole of the map of the land, which is generated from the planet's `Terrain`
+    /// so that the procedural lights stay off the oceans.
+    pub mask_resolution: u32,
+
+    /// An equirectangular map of the lights, which replaces the procedural clusters.
+    /// The longitude runs around the planet's Y axis and the top of the map is the north (+Y) pole.
+    /// The color of the map is multiplied by the color of the lights.
+    #[serde(skip)]
+    pub texture: Option<Handle<Texture>>,
+}
+
+impl Default for NightLights {
+    fn default() -> Self {
+        Self {
+            color: Srgb::new(1.0, 0.75, 0.4),
+            intensity: 1.0,
+            coverage: 0.35,
+            scale: 4.0,
+            seed: 0,
+            mask_resolution: 256,
+            texture: None,
+        }
+    }
+}
+
+impl NightLights {
+    /// Create new procedural night lights with the specified color and brightness.
+    pub fn new(color: Srgb, intensity: f32) -> Self {
+        Self { color, intensity, ..Default::default() }
+    }
+
+    /// Changes the coverage, size and seed of the procedural clusters.
+    pub fn with_clusters(mut self, coverage: f32, scale: f32, seed: u32) -> Self {
+        self.coverage = coverage;
+        self.scale = scale;
+        self.seed = seed;
+        self
+    }
+
+    /// Changes the resolution of the map of the land.
+    pub fn with_mask_resolution(mut self, mask_resolution: u32) -> Self {
+        assert!(mask_resolution > 0, "The land map needs at least one texel!");
+        self.mask_resolution = mask_resolution;
+        self
+    }
+
+    /// Uses a texture for the lights instead of procedural clusters.
+    pub fn with_texture(mut self, texture: Handle<Texture>) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+}
+
+impl Component for NightLights {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub const MAX_NIGHT_LIGHTS: usize = MAX_PLANETS;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct NightLightData {
+    /// Rotates world space directions into the planet's frame, where the lights are defined.
+    pub orientation: mat4,
+    pub center: vec3,
+    pub radius: float,
+    pub color: vec3,
+    pub intensity: float,
+    /// The radius of the surface which the lights are drawn on, relative to the planet's radius.
+    pub level: float,
+    pub coverage: float,
+    pub scale: float,
+    pub seed: float,
+    /// Whether the texture of the lights is a map of the land which masks the procedural clusters.
+    pub masked: uint,
+    /// The index of the planet's atmosphere in the `PlanetList`, or `NO_PLANET` if it doesn't have one.
+    pub planet: uint,
+}
+
+impl NightLightData {
+    pub(crate) fn new(lights: &NightLights, sea_level: f32, masked: bool, planet: u32, orientation: Matrix4<f32>, center: Vector3<f32>, radius: f32) -> Self {
+        Self {
+            orientation: Into::<[[f32; 4]; 4]>::into(orientation).into(),
+            center: Into::<[f32; 3]>::into(center).into(),
+            radius,
+            color: [lights.color.red, lights.color.green, lights.color.blue].into(),
+            intensity: lights.intensity,
+            // The cities are built on the land, which is above the sea.
+            level: 1.0 + sea_level.max(0.0),
+            coverage: lights.coverage,
+            scale: lights.scale,
+            seed: (lights.seed % 1024) as f32,
+            masked: masked as u32,
+            planet,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct NightLightList {
+    pub(crate) count: uint,
+    lights: [NightLightData; MAX_NIGHT_LIGHTS],
+}
+
+impl NightLightList {
+    pub(crate) fn new(light_data: &[NightLightData]) -> Self {
+        assert!(light_data.len() <= MAX_NIGHT_LIGHTS);
+        let mut lights: [NightLightData; MAX_NIGHT_LIGHTS] = Default::default();
+        for (i, data) in light_data.iter().enumerate() {
+            lights[i] = *data;
+        }
+        Self { lights, count: light_data.len() as u32 }
+    }
+}
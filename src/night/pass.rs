@@ -0,0 +1,119 @@
+use amethyst::{
+    core::ecs::{
+        DispatcherBuilder, World,
+    },
+    error::Error,
+    renderer::{
+        bundle::{RenderOrder, RenderPlan, RenderPlugin, Target},
+        rendy::{
+            command::QueueId,
+            factory::Factory,
+            graph::{
+                GraphContext,
+                NodeBuffer, NodeImage, render::{RenderGroup, RenderGroupDesc},
+            },
+            hal::{self, pso, pso::ShaderStageFlags},
+            shader::SpirvShader,
+        },
+        types::Backend,
+    },
+};
+
+use super::{mask::{LandMasks, LandMaskSystem}, sub::NightLightSub};
+
+use crate::renderutils::LayerPass;
+
+use amethyst::prelude::WorldExt;
+
+lazy_static::lazy_static! {
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/night_lights.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawNightLightsDesc;
+
+impl DrawNightLightsDesc {
+    /// Create instance of `DrawNightLightsDesc` render group
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawNightLightsDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        // Lights with a texture (or a land mask) read it instead of covering the whole planet with clusters.
+        LayerPass {
+            fragment: &FRAGMENT,
+            // The lights are drawn over the surface of the planet, but they are hidden by nearer geometry.
+            depth_test: pso::DepthTest {
+                fun: pso::Comparison::Less,
+                write: false,
+            },
+            // The light of the cities is added to the night side of the surface.
+            blend: pso::BlendState::ADD,
+            push: None,
+        }.build::<B, NightLightSub<B>>(factory, subpass, framebuffer_width, framebuffer_height)
+    }
+}
+
+/// The order of the night lights, which are drawn just before the groups at `RenderOrder::AfterTransparent`.
+/// This puts them under the clouds, however the plugins are ordered.
+const NIGHT_LIGHT_ORDER: i32 = RenderOrder::AfterTransparent as i32 - 1;
+
+/// A [RenderPlugin] which draws the `NightLights` of every planet.
+/// The lights are drawn before the clouds, so that the clouds cover them.
+#[derive(Debug, Default)]
+pub struct NightLightRender;
+
+impl NightLightRender {
+    /// Creates the night lights plugin.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for NightLightRender {
+    fn on_build<'a, 'b>(
+        &mut self,
+        world: &mut World,
+        builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        world.register::<super::NightLights>();
+        world.register::<crate::Planet>();
+        world.register::<crate::Terrain>();
+        world.register::<crate::Atmosphere>();
+        world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        world.insert(LandMasks::default());
+        builder.add(LandMaskSystem::default(), "land_mask_system", &[]);
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _world: &World,
+    ) -> Result<(), Error> {
+        plan.extend_target(Target::Main, |ctx| {
+            ctx.add(NIGHT_LIGHT_ORDER, DrawNightLightsDesc::new().builder())?;
+            Ok(())
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use amethyst::{
+    assets::Handle,
+    core::{
+        transform::Transform,
+        math::{
+            Matrix4,
+            Vector4,
+            U3,
+        }
+    },
+    renderer::{
+        submodules::DynamicUniform,
+        rendy::{
+            command::RenderPassEncoder,
+            factory::Factory,
+            hal,
+        },
+        types::Backend,
+        Texture,
+    },
+    ecs::prelude::*,
+};
+
+use super::{mask::LandMasks, *};
+use crate::{
+    planet::{sub::planet_index, terrain::Terrain, Atmosphere, Planet},
+    renderutils::{camera_position, uniform_scale, LayerSub},
+};
+
+#[derive(Debug)]
+pub(crate) struct NightLightSub<B: Backend> {
+    uniform: DynamicUniform<B, NightLightList>,
+    data: NightLightList,
+    /// The texture (or land mask) of each planet's lights, in the same order as the list.
+    textures: Vec<Option<Handle<Texture>>>,
+}
+
+impl<B: Backend> LayerSub<B> for NightLightSub<B> {
+    fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
+        let uniform = DynamicUniform::new(factory, flags)?;
+        Ok(Self { uniform, data: NightLightList::default(), textures: Vec::new() })
+    }
+
+    fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
+        let atmospheres = world.read_storage::<Atmosphere>();
+        let planets = world.read_storage::<Planet>();
+        let terrains = world.read_storage::<Terrain>();
+        let masks = world.try_fetch::<LandMasks>();
+        let camera = camera_position(world);
+        let mut layers: Vec<(f32, NightLightData, Option<Handle<Texture>>)> = Vec::new();
+        for (entity, lights, atmosphere, planet, transform) in (&world.entities(), &world.read_storage::<NightLights>(), atmospheres.maybe(), planets.maybe(), &world.read_storage::<Transform>()).join() {
+            let matrix: Matrix4<f32> = *transform.global_matrix();
+            let translation: Vector4<f32> = matrix.column(3).into();
+            let scale = uniform_scale(&matrix);
+            // The lights follow the radius of the atmosphere, so that they line up with the scattering.
+            let radius = match (atmosphere, planet) {
+                (Some(atmosphere), _) => atmosphere.base_planet_radius * scale,
+                (None, Some(planet)) => planet.radius * scale,
+                (None, None) => continue,
+            };
+            if layers.len() == MAX_NIGHT_LIGHTS {
+                break;
+            }
+            // The inverse of the planet's rotation turns world space directions into the planet's frame.
+            let rotation = matrix.fixed_slice::<U3, U3>(0, 0) / uniform_scale(&matrix);
+            let orientation = rotation.transpose().to_homogeneous();
+            let sea_level = terrains.get(entity).map_or(0.0, |terrain| terrain.sea_level);
+            // The procedural lights are masked by the land until the lights have a texture of their own.
+            let (texture, masked) = match &lights.texture {
+                Some(texture) => (Some(texture.clone()), false),
+                None => {
+                    let mask = masks.as_ref().and_then(|masks| masks.get(entity)).cloned();
+                    let masked = mask.is_some();
+                    (mask, masked)
+                }
+            };
+            let distance = camera.map_or(0.0, |camera| (camera - translation.xyz()).norm());
+            layers.push((distance, NightLightData::new(lights, sea_level, masked, planet_index(world, entity), orientation, translation.xyz(), radius), texture));
+        }
+        // The lights are drawn from back to front, matching the order of the clouds which cover them.
+        layers.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let light_list: Vec<NightLightData> = layers.iter().map(|(_, data, _)| *data).collect();
+        self.textures = layers.into_iter().map(|(_, _, texture)| texture).collect();
+        self.data = NightLightList::new(light_list.as_slice());
+        self.uniform.write(factory, index, self.data.std140());
+    }
+
+    /// Returns the raw `DescriptorSetLayout` for this environment
+    fn raw_layout(&self) -> &B::DescriptorSetLayout {
+        self.uniform.raw_layout()
+    }
+
+    fn bind(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        self.uniform.bind(index,  pipeline_layout, binding_id, encoder);
+    }
+
+    /// Gets the texture (or land mask) of each planet's lights, in the order of the list.
+    fn textures(&self) -> &[Option<Handle<Texture>>] {
+        self.textures.as_slice()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.count == 0
+    }
+}
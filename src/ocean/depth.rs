@@ -0,0 +1,36 @@
+use amethyst::renderer::types::TextureData;
+
+use super::Ocean;
+use crate::planet::{
+    terrain::Terrain,
+    terrain_map::{equirectangular_map, TerrainMap, TerrainMapSystem, TerrainMaps},
+};
+
+/// Generates an equirectangular map of the depth of the water over the terrain, which is twice as wide as it is high.
+/// Each texel is 0.0 on land and approaches 1.0 as the water becomes deeper than the depth scale.
+pub fn ocean_depth_map(terrain: &Terrain, depth_scale: f32, resolution: u32) -> TextureData {
+    let depth_scale = depth_scale.max(1e-6);
+    equirectangular_map(resolution, |direction| {
+        let depth = (terrain.sea_level - terrain.height(direction)).max(0.0);
+        1.0 - (-depth / depth_scale).exp()
+    })
+}
+
+impl TerrainMap for Ocean {
+    /// The depth scale and the resolution of the depth map.
+    type Settings = (f32, u32);
+
+    fn map_settings(&self) -> Option<Self::Settings> {
+        Some((self.depth_scale, self.depth_resolution))
+    }
+
+    fn build_map(terrain: &Terrain, (depth_scale, resolution): &Self::Settings) -> TextureData {
+        ocean_depth_map(terrain, *depth_scale, *resolution)
+    }
+}
+
+/// The depth maps of every `Ocean` on a planet with terrain, which are used by the ocean pass.
+pub type OceanDepthMaps = TerrainMaps<Ocean>;
+
+/// Generates the depth map of every `Ocean` with a `Terrain`, whenever the ocean or the terrain changes.
+pub type OceanDepthSystem = TerrainMapSystem<Ocean>;
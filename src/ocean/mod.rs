@@ -0,0 +1,137 @@
+pub mod pass;
+pub mod depth;
+pub(crate) mod sub;
+
+use amethyst::{
+    assets::PrefabData,
+    derive::PrefabData,
+    ecs::prelude::*,
+    error::Error,
+    renderer::palette::Srgb,
+    core::math::{Matrix4, Vector3},
+};
+
+use glsl_layout::*;
+
+use serde::{Serialize, Deserialize};
+
+use crate::planet::MAX_PLANETS;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// An ocean which fills the `Planet` on the same entity up to the sea level of its `Terrain`.
+/// A planet without terrain is covered by a deep ocean at its radius.
+/// The water reflects the sky of the `Atmosphere` on the same entity, and the light of every `Star` is reflected as a glint.
+pub struct Ocean {
+    /// The color of the water where it is shallow, which lets the sea floor show through.
+    #[serde(with = "amethyst::renderer::serde_shim::srgb")]
+    pub shallow_color: Srgb,
+
+    /// The color of the water where it is deep.
+    #[serde(with = "amethyst::renderer::serde_shim::srgb")]
+    pub deep_color: Srgb,
+
+    /// The depth (relative to the planet's radius) over which the water changes from the shallow color to the deep color.
+    pub depth_scale: f32,
+
+    /// The roughness of the surface of the water, from 0.0 (a mirror) to 1.0. Rougher water spreads the glint of each star.
+    pub roughness: f32,
+
+    /// The number of texels from pole to pole of the map of the depth of the water, which is generated from the terrain.
+    pub depth_resolution: u32,
+}
+
+impl Default for Ocean {
+    fn default() -> Self {
+        Self {
+            shallow_color: Srgb::new(0.1, 0.45, 0.5),
+            deep_color: Srgb::new(0.01, 0.05, 0.12),
+            depth_scale: 0.002,
+            roughness: 0.15,
+            depth_resolution: 128,
+        }
+    }
+}
+
+impl Ocean {
+    /// Create a new ocean with the colors of the earth's oceans.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Changes the colors of the water, and the depth over which they change.
+    pub fn with_colors(mut self, shallow_color: Srgb, deep_color: Srgb, depth_scale: f32) -> Self {
+        self.shallow_color = shallow_color;
+        self.deep_color = deep_color;
+        self.depth_scale = depth_scale;
+        self
+    }
+
+    /// Changes the roughness of the surface of the water.
+    pub fn with_roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    /// Changes the resolution of the map of the depth of the water.
+    pub fn with_depth_resolution(mut self, depth_resolution: u32) -> Self {
+        assert!(depth_resolution > 0, "The depth map needs at least one texel!");
+        self.depth_resolution = depth_resolution;
+        self
+    }
+}
+
+impl Component for Ocean {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub const MAX_OCEANS: usize = MAX_PLANETS;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct OceanData {
+    /// Rotates world space directions into the planet's frame, where the depth map is defined.
+    pub orientation: mat4,
+    pub center: vec3,
+    pub radius: float,
+    pub shallow_color: vec3,
+    /// The radius of the surface of the water, relative to the planet's radius.
+    pub level: float,
+    pub deep_color: vec3,
+    pub roughness: float,
+    /// The index of the planet's atmosphere in the `PlanetList`, or `NO_PLANET` if it doesn't have one.
+    pub planet: uint,
+}
+
+impl OceanData {
+    pub(crate) fn new(ocean: &Ocean, sea_level: f32, planet: u32, orientation: Matrix4<f32>, center: Vector3<f32>, radius: f32) -> Self {
+        Self {
+            orientation: Into::<[[f32; 4]; 4]>::into(orientation).into(),
+            center: Into::<[f32; 3]>::into(center).into(),
+            radius,
+            shallow_color: [ocean.shallow_color.red, ocean.shallow_color.green, ocean.shallow_color.blue].into(),
+            level: 1.0 + sea_level,
+            deep_color: [ocean.deep_color.red, ocean.deep_color.green, ocean.deep_color.blue].into(),
+            roughness: ocean.roughness.clamp(0.01, 1.0),
+            planet,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct OceanList {
+    pub(crate) count: uint,
+    oceans: [OceanData; MAX_OCEANS],
+}
+
+impl OceanList {
+    pub(crate) fn new(ocean_data: &[OceanData]) -> Self {
+        assert!(ocean_data.len() <= MAX_OCEANS);
+        let mut oceans: [OceanData; MAX_OCEANS] = Default::default();
+        for (i, data) in ocean_data.iter().enumerate() {
+            oceans[i] = *data;
+        }
+        Self { oceans, count: ocean_data.len() as u32 }
+    }
+}
@@ -0,0 +1,114 @@
+use amethyst::{
+    core::ecs::{
+        DispatcherBuilder, World,
+    },
+    error::Error,
+    renderer::{
+        bundle::{RenderOrder, RenderPlan, RenderPlugin, Target},
+        rendy::{
+            command::QueueId,
+            factory::Factory,
+            graph::{
+                GraphContext,
+                NodeBuffer, NodeImage, render::{RenderGroup, RenderGroupDesc},
+            },
+            hal::{self, pso, pso::ShaderStageFlags},
+            shader::SpirvShader,
+        },
+        types::Backend,
+    },
+};
+
+use super::{depth::{OceanDepthMaps, OceanDepthSystem}, sub::OceanSub};
+
+use crate::renderutils::LayerPass;
+
+use amethyst::prelude::WorldExt;
+
+lazy_static::lazy_static! {
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/ocean.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawOceanDesc;
+
+impl DrawOceanDesc {
+    /// Create instance of `DrawOceanDesc` render group
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawOceanDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        // Oceans on planets with terrain read the depth of the water from their depth map.
+        LayerPass {
+            fragment: &FRAGMENT,
+            // The water is hidden by the terrain above the sea, and hides the sea floor and anything else below it.
+            depth_test: pso::DepthTest {
+                fun: pso::Comparison::Less,
+                write: true,
+            },
+            blend: pso::BlendState::ALPHA,
+            push: None,
+        }.build::<B, OceanSub<B>>(factory, subpass, framebuffer_width, framebuffer_height)
+    }
+}
+
+/// A [RenderPlugin] which draws every `Ocean`.
+/// The oceans are drawn after the opaque geometry, so the terrain hides the water above the sea and shows through shallow water.
+#[derive(Debug, Default)]
+pub struct OceanRender;
+
+impl OceanRender {
+    /// Creates the ocean plugin.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for OceanRender {
+    fn on_build<'a, 'b>(
+        &mut self,
+        world: &mut World,
+        builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        world.register::<super::Ocean>();
+        world.register::<crate::Planet>();
+        world.register::<crate::Terrain>();
+        world.register::<crate::Atmosphere>();
+        world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        world.insert(OceanDepthMaps::default());
+        builder.add(OceanDepthSystem::default(), "ocean_depth_system", &[]);
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _world: &World,
+    ) -> Result<(), Error> {
+        plan.extend_target(Target::Main, |ctx| {
+            ctx.add(RenderOrder::AfterOpaque, DrawOceanDesc::new().builder())?;
+            Ok(())
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use amethyst::{
+    assets::Handle,
+    core::{
+        transform::Transform,
+        math::{
+            Matrix4,
+            Vector4,
+            U3,
+        }
+    },
+    renderer::{
+        submodules::DynamicUniform,
+        rendy::{
+            command::RenderPassEncoder,
+            factory::Factory,
+            hal,
+        },
+        types::Backend,
+        Texture,
+    },
+    ecs::prelude::*,
+};
+
+use super::{depth::OceanDepthMaps, *};
+use crate::{
+    planet::{sub::planet_index, terrain::Terrain, Planet},
+    renderutils::{camera_position, uniform_scale, LayerSub},
+};
+
+#[derive(Debug)]
+pub(crate) struct OceanSub<B: Backend> {
+    uniform: DynamicUniform<B, OceanList>,
+    data: OceanList,
+    /// The depth map of each ocean, in the same order as the list.
+    textures: Vec<Option<Handle<Texture>>>,
+}
+
+impl<B: Backend> LayerSub<B> for OceanSub<B> {
+    fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
+        let uniform = DynamicUniform::new(factory, flags)?;
+        Ok(Self { uniform, data: OceanList::default(), textures: Vec::new() })
+    }
+
+    fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
+        let terrains = world.read_storage::<Terrain>();
+        let depth_maps = world.try_fetch::<OceanDepthMaps>();
+        let camera = camera_position(world);
+        let mut oceans: Vec<(f32, OceanData, Option<Handle<Texture>>)> = Vec::new();
+        for (entity, ocean, planet, transform) in (&world.entities(), &world.read_storage::<Ocean>(), &world.read_storage::<Planet>(), &world.read_storage::<Transform>()).join() {
+            if oceans.len() == MAX_OCEANS {
+                break;
+            }
+            let matrix: Matrix4<f32> = *transform.global_matrix();
+            let translation: Vector4<f32> = matrix.column(3).into();
+            let scale = uniform_scale(&matrix);
+            // The inverse of the planet's rotation turns world space directions into the planet's frame.
+            let rotation = matrix.fixed_slice::<U3, U3>(0, 0) / uniform_scale(&matrix);
+            let orientation = rotation.transpose().to_homogeneous();
+            let sea_level = terrains.get(entity).map_or(0.0, |terrain| terrain.sea_level);
+            let distance = camera.map_or(0.0, |camera| (camera - translation.xyz()).norm());
+            let depth_map = depth_maps.as_ref().and_then(|depth_maps| depth_maps.get(entity)).cloned();
+            oceans.push((distance, OceanData::new(ocean, sea_level, planet_index(world, entity), orientation, translation.xyz(), planet.radius * scale), depth_map));
+        }
+        // The oceans are drawn from back to front, so that the shallow water of nearer planets is blended over the ones behind them.
+        oceans.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let ocean_list: Vec<OceanData> = oceans.iter().map(|(_, data, _)| *data).collect();
+        self.textures = oceans.into_iter().map(|(_, _, texture)| texture).collect();
+        self.data = OceanList::new(ocean_list.as_slice());
+        self.uniform.write(factory, index, self.data.std140());
+    }
+
+    /// Returns the raw `DescriptorSetLayout` for this environment
+    fn raw_layout(&self) -> &B::DescriptorSetLayout {
+        self.uniform.raw_layout()
+    }
+
+    fn bind(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        self.uniform.bind(index,  pipeline_layout, binding_id, encoder);
+    }
+
+    /// Gets the depth map of each ocean, in the order of the list.
+    fn textures(&self) -> &[Option<Handle<Texture>>] {
+        self.textures.as_slice()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.count == 0
+    }
+}
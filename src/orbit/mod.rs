@@ -0,0 +1,204 @@
+pub mod system;
+
+pub use system::{OrbitBundle, OrbitSystem};
+
+use std::f64::consts::PI;
+
+use amethyst::{
+    assets::PrefabData,
+    derive::PrefabData,
+    core::math::Vector3,
+    ecs::prelude::*,
+    error::Error,
+};
+
+use serde::{Serialize, Deserialize};
+
+/// The maximum number of iterations used when solving Kepler's equation.
+const MAX_KEPLER_ITERATIONS: usize = 32;
+
+/// The error in the eccentric anomaly (in radians) at which the solution to Kepler's equation is accepted.
+const KEPLER_TOLERANCE: f64 = 1e-10;
+
+/// The largest eccentricity which is used, since Kepler's equation has no elliptical solution at 1.0 and above.
+pub const MAX_ECCENTRICITY: f32 = 0.999;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// Moves an entity along a Keplerian orbit around its parent, by updating the translation of its `Transform`.
+/// The reference plane of the orbit is the XZ plane, and the node is measured from the X axis.
+pub struct Orbit {
+    /// Half of the longest diameter of the ellipse, in world units.
+    pub semi_major_axis: f32,
+
+    /// The shape of the ellipse, from 0.0 (a circle) to just below 1.0.
+    /// Values outside of this range (such as from a prefab) are clamped to between 0.0 and `MAX_ECCENTRICITY`.
+    pub eccentricity: f32,
+
+    /// The angle between the orbital plane and the reference plane in radians.
+    pub inclination: f32,
+
+    /// The longitude of the ascending node in radians.
+    pub node: f32,
+
+    /// The argument of periapsis in radians, measured from the ascending node.
+    pub periapsis: f32,
+
+    /// The time (in seconds) at which the body passes through periapsis.
+    pub epoch: f64,
+
+    /// The time (in seconds) taken to complete one orbit.
+    pub period: f64,
+
+    /// The entity that is being orbited.
+    /// Moons can orbit entities which are orbiting themselves.
+    /// If there is no parent, the orbit is around the origin.
+    #[serde(skip)]
+    pub parent: Option<Entity>,
+}
+
+impl Orbit {
+    /// Create a new circular orbit in the reference plane with the specified radius and period.
+    pub fn circular(radius: f32, period: f64) -> Self {
+        Self {
+            semi_major_axis: radius,
+            eccentricity: 0.0,
+            inclination: 0.0,
+            node: 0.0,
+            periapsis: 0.0,
+            epoch: 0.0,
+            period,
+            parent: None,
+        }
+    }
+
+    /// Changes the shape of the orbit.
+    pub fn with_eccentricity(mut self, eccentricity: f32) -> Self {
+        assert!((0.0..1.0).contains(&eccentricity), "Only elliptical orbits are supported!");
+        self.eccentricity = eccentricity;
+        self
+    }
+
+    /// Changes the orientation of the orbit (all angles are in radians).
+    pub fn with_orientation(mut self, inclination: f32, node: f32, periapsis: f32) -> Self {
+        self.inclination = inclination;
+        self.node = node;
+        self.periapsis = periapsis;
+        self
+    }
+
+    /// Changes the time (in seconds) at which the body passes through periapsis.
+    pub fn with_epoch(mut self, epoch: f64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Changes the entity that is being orbited.
+    pub fn with_parent(mut self, parent: Entity) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Calculates the mean anomaly (in radians, from 0.0 to 2π) at the specified time.
+    pub fn mean_anomaly(&self, time: f64) -> f64 {
+        if self.period <= 0.0 {
+            return 0.0;
+        }
+        // The fraction of the orbit is calculated first so that large times don't lose precision in the angle.
+        ((time - self.epoch) / self.period).rem_euclid(1.0) * 2.0 * PI
+    }
+
+    /// Calculates the position relative to the parent at the specified time (in seconds).
+    pub fn position(&self, time: f64) -> Vector3<f32> {
+        let e = f64::from(self.eccentricity.clamp(0.0, MAX_ECCENTRICITY));
+        let a = f64::from(self.semi_major_axis);
+        let eccentric = eccentric_anomaly(self.mean_anomaly(time), e);
+        let true_anomaly = 2.0 * ((1.0 + e).sqrt() * (eccentric / 2.0).sin()).atan2((1.0 - e).sqrt() * (eccentric / 2.0).cos());
+        let r = a * (1.0 - e * eccentric.cos());
+
+        let (sin_node, cos_node) = f64::from(self.node).sin_cos();
+        let (sin_inc, cos_inc) = f64::from(self.inclination).sin_cos();
+        let (sin_arg, cos_arg) = (f64::from(self.periapsis) + true_anomaly).sin_cos();
+        let x = r * (cos_node * cos_arg - sin_node * sin_arg * cos_inc);
+        let y = r * (sin_node * cos_arg + cos_node * sin_arg * cos_inc);
+        let z = r * (sin_arg * sin_inc);
+        // The orbital elements are defined with Z up, but Y is up in the world.
+        Vector3::new(x as f32, z as f32, -y as f32)
+    }
+}
+
+impl Component for Orbit {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Solves Kepler's equation (M = E - e sin E) for the eccentric anomaly E, using Newton's method.
+/// The eccentricity is clamped to between 0.0 and `MAX_ECCENTRICITY`, since only elliptical orbits have a solution.
+pub fn eccentric_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let eccentricity = eccentricity.max(0.0).min(f64::from(MAX_ECCENTRICITY));
+    // Starting at π converges for every eccentricity, while M is closer for near circular orbits.
+    let mut eccentric = if eccentricity > 0.8 { PI } else { mean_anomaly };
+    for _ in 0..MAX_KEPLER_ITERATIONS {
+        let delta = (eccentric - eccentricity * eccentric.sin() - mean_anomaly) / (1.0 - eccentricity * eccentric.cos());
+        eccentric -= delta;
+        if delta.abs() < KEPLER_TOLERANCE {
+            break;
+        }
+    }
+    eccentric
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eccentric_anomaly_solves_keplers_equation() {
+        for &e in &[0.0, 0.5, 0.95] {
+            for i in 0..=64 {
+                let mean_anomaly = 2.0 * PI * f64::from(i) / 64.0;
+                let eccentric = eccentric_anomaly(mean_anomaly, e);
+                let residual = eccentric - e * eccentric.sin() - mean_anomaly;
+                assert!(residual.abs() < 1e-8, "e = {}, M = {}, residual = {}", e, mean_anomaly, residual);
+            }
+        }
+    }
+
+    #[test]
+    fn circular_orbit_has_equal_anomalies() {
+        assert!((eccentric_anomaly(1.234, 0.0) - 1.234).abs() < 1e-12);
+    }
+
+    #[test]
+    fn unbound_eccentricities_are_clamped() {
+        for &e in &[1.0, 1.5] {
+            let eccentric = eccentric_anomaly(2.0, e);
+            assert!(eccentric.is_finite());
+        }
+        let orbit = Orbit { eccentricity: 2.0, ..Orbit::circular(10.0, 100.0) };
+        assert!(orbit.position(25.0).iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn position_at_periapsis_and_apoapsis() {
+        for &e in &[0.0f32, 0.5, 0.95] {
+            let orbit = Orbit::circular(10.0, 100.0).with_eccentricity(e).with_epoch(5.0);
+            let periapsis = orbit.position(5.0);
+            let apoapsis = orbit.position(55.0);
+            assert!((periapsis.norm() - 10.0 * (1.0 - e)).abs() < 1e-3);
+            assert!((apoapsis.norm() - 10.0 * (1.0 + e)).abs() < 1e-3);
+            // Without any rotation the periapsis is on the X axis, and the apoapsis is opposite it.
+            assert!(periapsis.x > 0.0 && periapsis.y.abs() < 1e-4 && periapsis.z.abs() < 1e-4);
+            assert!(apoapsis.x < 0.0 && apoapsis.y.abs() < 1e-4 && apoapsis.z.abs() < 1e-3);
+            // A whole period later the body is back at periapsis.
+            assert!((orbit.position(105.0) - periapsis).norm() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn inclined_orbit_leaves_the_reference_plane() {
+        let orbit = Orbit::circular(10.0, 100.0).with_orientation(PI as f32 / 2.0, 0.0, 0.0);
+        let quarter = orbit.position(25.0);
+        assert!((quarter.norm() - 10.0).abs() < 1e-3);
+        assert!((quarter.y - 10.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use amethyst::{
+    core::{
+        bundle::SystemBundle,
+        math::Vector3,
+        timing::Time,
+        transform::Transform,
+    },
+    ecs::prelude::*,
+    error::Error,
+    prelude::WorldExt,
+};
+
+use super::Orbit;
+
+/// The maximum depth of the orbit hierarchy, which stops cycles of parents from looping forever.
+const MAX_ORBIT_DEPTH: usize = 16;
+
+/// Updates the `Transform` of every entity with an `Orbit`.
+/// Orbiting entities are positioned in world space, so they should not have a `Parent` component.
+#[derive(Debug)]
+pub struct OrbitSystem {
+    time_scale: f64,
+}
+
+impl OrbitSystem {
+    /// Create a new orbit system where the orbits advance `time_scale` seconds for every real second.
+    pub fn new(time_scale: f64) -> Self {
+        Self { time_scale }
+    }
+}
+
+impl Default for OrbitSystem {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+/// Calculates the world position of an entity, first resolving the orbits of its parents.
+fn resolve_position(
+    entity: Entity,
+    time: f64,
+    orbits: &ReadStorage<'_, Orbit>,
+    transforms: &WriteStorage<'_, Transform>,
+    positions: &mut HashMap<Entity, Vector3<f32>>,
+    depth: usize,
+) -> Vector3<f32> {
+    if let Some(position) = positions.get(&entity) {
+        return *position;
+    }
+    let position = match orbits.get(entity) {
+        Some(orbit) => {
+            let origin = match orbit.parent {
+                Some(parent) if depth < MAX_ORBIT_DEPTH => resolve_position(parent, time, orbits, transforms, positions, depth + 1),
+                _ => Vector3::zeros(),
+            };
+            origin + orbit.position(time)
+        }
+        // Entities which aren't orbiting stay where they are, such as the star at the center of a system.
+        // Their global position is used, since they may be part of a transform hierarchy of their own.
+        // This is the position from the last frame, because the `TransformBundle` runs after the orbits.
+        None => transforms.get(entity).map(|transform| transform.global_matrix().column(3).xyz()).unwrap_or_else(Vector3::zeros),
+    };
+    positions.insert(entity, position);
+    position
+}
+
+impl<'a> System<'a> for OrbitSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Orbit>,
+        WriteStorage<'a, Transform>,
+        Read<'a, Time>,
+    );
+
+    fn run(&mut self, (entities, orbits, mut transforms, time): Self::SystemData) {
+        let time = time.absolute_time_seconds() * self.time_scale;
+        let mut positions: HashMap<Entity, Vector3<f32>> = HashMap::new();
+        for (entity, _) in (&entities, &orbits).join() {
+            resolve_position(entity, time, &orbits, &transforms, &mut positions, 0);
+        }
+        for (entity, _, transform) in (&entities, &orbits, &mut transforms).join() {
+            if let Some(position) = positions.get(&entity) {
+                transform.set_translation(*position);
+            }
+        }
+    }
+}
+
+/// Adds the `OrbitSystem` to the dispatcher.
+/// This should be added before the `TransformBundle`, so that the new positions are used in the same frame.
+#[derive(Debug)]
+pub struct OrbitBundle {
+    time_scale: f64,
+}
+
+impl OrbitBundle {
+    pub fn new() -> Self {
+        Self { time_scale: 1.0 }
+    }
+
+    /// Changes how many seconds the orbits advance for every real second.
+    pub fn with_time_scale(mut self, time_scale: f64) -> Self {
+        self.time_scale = time_scale;
+        self
+    }
+}
+
+impl Default for OrbitBundle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for OrbitBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        world.register::<Orbit>();
+        builder.add(OrbitSystem::new(self.time_scale), "orbit_system", &[]);
+        Ok(())
+    }
+}
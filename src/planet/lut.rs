@@ -0,0 +1,857 @@
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+};
+
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader},
+    core::{
+        math::{Vector3, Vector4},
+        ArcThreadPool,
+    },
+    ecs::prelude::*,
+    renderer::{
+        rendy::{
+            hal,
+            texture::TextureBuilder,
+        },
+        types::TextureData,
+        Texture,
+    },
+};
+
+use super::{Atmosphere, MAX_PLANETS};
+
+// The sizes of the lookup tables, which are mirrored in `atmosphere_lut.frag.glsl`.
+/// The number of altitudes in the scattering table.
+pub const LUT_R_SIZE: usize = 16;
+/// The number of view zenith angles in the scattering table (half of which hit the ground).
+pub const LUT_MU_SIZE: usize = 32;
+/// The number of sun zenith angles in the scattering table.
+pub const LUT_MU_S_SIZE: usize = 32;
+/// The number of angles between the view and the sun in the scattering table.
+pub const LUT_NU_SIZE: usize = 8;
+/// The width of the texture, which is also the number of view zenith angles in the transmittance table.
+pub const LUT_WIDTH: usize = LUT_NU_SIZE * LUT_MU_S_SIZE;
+/// The number of altitudes in the transmittance table.
+pub const TRANSMITTANCE_HEIGHT: usize = 32;
+/// The height of each layer of the texture, which holds the tables of a single atmosphere.
+/// These are the Rayleigh scattering table, then the Mie scattering table and then the transmittance table.
+pub const LUT_LAYER_HEIGHT: usize = 2 * LUT_R_SIZE * LUT_MU_SIZE + TRANSMITTANCE_HEIGHT;
+
+/// The cosine of the lowest sun zenith angle in the scattering table, since the sky is dark when the sun is far below the horizon.
+const MU_S_MIN: f32 = -0.2;
+
+/// The extinction coefficient which determines the alpha of the atmosphere, matching `K_ALPHA` in the shaders.
+const K_ALPHA: f32 = 10.0;
+
+/// The width of the band (in the cosine of the sun zenith angle) over which the sun sets at a point.
+const SUN_HORIZON_BAND: f32 = 0.01;
+
+const TRANSMITTANCE_STEPS: usize = 40;
+const SCATTERING_STEPS: usize = 24;
+
+/// The size of the (CPU only) multiple scattering table, along both the sun zenith angle and the altitude.
+const MS_SIZE: usize = 16;
+const MS_DIRECTIONS: usize = 64;
+const MS_STEPS: usize = 16;
+
+/// The number of tables which are computed at once, so that atmospheres which change every frame don't flood the thread pool.
+const MAX_PENDING: usize = 2;
+
+/// The parameters of an `Atmosphere` which affect its lookup tables, for a planet with a radius of 1.0.
+/// The density and Mie anisotropy are applied when the tables are sampled, so they can change without recomputing the tables.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LutParameters {
+    /// The radius of the top of the atmosphere.
+    pub atmosphere_radius: f32,
+
+    /// The Rayleigh scattering coefficients (red, green, blue and alpha).
+    pub rayleigh: Vector4<f32>,
+
+    /// The Mie scattering coefficient.
+    pub mie: f32,
+
+    /// The ratio of Mie extinction to Mie scattering.
+    pub mie_extinction: f32,
+
+    /// The Rayleigh scale height.
+    pub rayleigh_scale_height: f32,
+
+    /// The Mie scale height.
+    pub mie_scale_height: f32,
+
+    /// The absorption coefficients of the absorbing layer (red, green, blue and alpha), which don't scatter any light.
+    pub absorption: Vector4<f32>,
+
+    /// The radius of the densest part of the absorbing layer.
+    pub absorption_center: f32,
+
+    /// The distance over which the density of the absorbing layer falls to zero.
+    pub absorption_width: f32,
+}
+
+/// Identifies a unique set of `LutParameters`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LutKey([u32; 14]);
+
+impl LutParameters {
+    /// Gets the lookup table parameters of an atmosphere, using the same scale heights as the shader.
+    pub fn new(atmosphere: &Atmosphere) -> Self {
+        let ar = atmosphere.height();
+        let rf = ar * 0.85;
+        let rayleigh = atmosphere.tinted_rayleigh();
+        let (absorption_center, absorption_width) = atmosphere.absorption_layer();
+        Self {
+            atmosphere_radius: ar,
+            rayleigh: Vector4::new(rayleigh[0], rayleigh[1], rayleigh[2], K_ALPHA),
+            mie: atmosphere.mie,
+            mie_extinction: atmosphere.mie_extinction,
+            rayleigh_scale_height: atmosphere.rayleigh_scale_height * rf,
+            mie_scale_height: atmosphere.mie_scale_height * rf,
+            // The absorbing layer doesn't change the coverage of the atmosphere.
+            absorption: Vector4::new(atmosphere.absorption[0], atmosphere.absorption[1], atmosphere.absorption[2], 0.0),
+            absorption_center,
+            absorption_width,
+        }
+    }
+
+    /// Gets the key which identifies these parameters.
+    pub fn key(&self) -> LutKey {
+        LutKey([
+            self.atmosphere_radius.to_bits(),
+            self.rayleigh.x.to_bits(),
+            self.rayleigh.y.to_bits(),
+            self.rayleigh.z.to_bits(),
+            self.rayleigh.w.to_bits(),
+            self.mie.to_bits(),
+            self.mie_extinction.to_bits(),
+            self.rayleigh_scale_height.to_bits(),
+            self.mie_scale_height.to_bits(),
+            self.absorption.x.to_bits(),
+            self.absorption.y.to_bits(),
+            self.absorption.z.to_bits(),
+            self.absorption_center.to_bits(),
+            self.absorption_width.to_bits(),
+        ])
+    }
+
+    /// The distance from the ground to the top of the atmosphere along the horizon.
+    fn horizon(&self) -> f32 {
+        (self.atmosphere_radius * self.atmosphere_radius - 1.0).max(0.0).sqrt()
+    }
+
+    /// The Rayleigh and Mie densities at the specified radius.
+    fn densities(&self, r: f32) -> (f32, f32) {
+        let h = (r - 1.0).max(0.0);
+        ((-h / self.rayleigh_scale_height).exp(), (-h / self.mie_scale_height).exp())
+    }
+
+    /// The density of the absorbing layer at the specified radius, which has a tent shaped profile.
+    fn absorption_density(&self, r: f32) -> f32 {
+        (1.0 - (r - self.absorption_center).abs() / self.absorption_width).max(0.0)
+    }
+
+    fn extinction(&self, r: f32) -> Vector4<f32> {
+        let (ray, mie) = self.densities(r);
+        self.rayleigh * ray + Vector4::repeat(self.mie * self.mie_extinction * mie) + self.absorption * self.absorption_density(r)
+    }
+
+    fn scattering(&self, r: f32) -> Vector4<f32> {
+        let (ray, mie) = self.densities(r);
+        self.rayleigh * ray + Vector4::repeat(self.mie * mie)
+    }
+
+    fn distance_to_top(&self, r: f32, mu: f32) -> f32 {
+        let discriminant = r * r * (mu * mu - 1.0) + self.atmosphere_radius * self.atmosphere_radius;
+        (-r * mu + discriminant.max(0.0).sqrt()).max(0.0)
+    }
+}
+
+fn distance_to_ground(r: f32, mu: f32) -> f32 {
+    let discriminant = r * r * (mu * mu - 1.0) + 1.0;
+    (-r * mu - discriminant.max(0.0).sqrt()).max(0.0)
+}
+
+fn intersects_ground(r: f32, mu: f32) -> bool {
+    mu < 0.0 && r * r * (mu * mu - 1.0) + 1.0 >= 0.0
+}
+
+fn coord_from_unit(x: f32, size: usize) -> f32 {
+    0.5 / size as f32 + x * (1.0 - 1.0 / size as f32)
+}
+
+fn unit_from_coord(u: f32, size: usize) -> f32 {
+    (u - 0.5 / size as f32) / (1.0 - 1.0 / size as f32)
+}
+
+fn phase_ray(cc: f32) -> f32 {
+    (3.0 / 16.0 / PI) * (1.0 + cc)
+}
+
+#[cfg(test)]
+fn phase_mie(g: f32, c: f32, cc: f32) -> f32 {
+    let gg = g * g;
+    let a = (1.0 - gg) * (1.0 + cc);
+    let b = (1.0 + gg - 2.0 * g * c).powf(1.5) * (2.0 + gg);
+    (3.0 / 8.0 / PI) * a / b
+}
+
+fn exp4(v: Vector4<f32>) -> Vector4<f32> {
+    v.map(f32::exp)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Samples a table of the specified size with bilinear filtering, where the coordinates are from 0.0 to 1.0 and texels are centered.
+fn sample_bilinear(table: &[Vector4<f32>], width: usize, height: usize, u: f32, v: f32) -> Vector4<f32> {
+    let x = (u * width as f32 - 0.5).max(0.0).min((width - 1) as f32);
+    let y = (v * height as f32 - 0.5).max(0.0).min((height - 1) as f32);
+    let x0 = (x as usize).min(width - 2);
+    let y0 = (y as usize).min(height - 2);
+    let fx = x - x0 as f32;
+    let fy = y - y0 as f32;
+    let at = |x: usize, y: usize| table[y * width + x];
+    let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+    let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// The precomputed transmittance and scattering of a single atmosphere.
+/// This follows "Precomputed Atmospheric Scattering" (Bruneton and Neyret), with the multiple scattering approximated by
+/// an isotropic term as in "A Scalable and Production Ready Sky and Atmosphere Rendering Technique" (Hillaire).
+#[derive(Debug, Clone)]
+pub struct AtmosphereLut {
+    parameters: LutParameters,
+    /// The transmittance to the top of the atmosphere, by view zenith angle and altitude.
+    transmittance: Vec<Vector4<f32>>,
+    /// The Rayleigh scattering (including the multiple scattering), without the scattering coefficients and phase function.
+    rayleigh: Vec<Vector4<f32>>,
+    /// The Mie scattering, without the scattering coefficient and phase function.
+    mie: Vec<Vector4<f32>>,
+}
+
+impl AtmosphereLut {
+    /// Computes the lookup tables for the specified parameters.
+    pub fn compute(parameters: LutParameters) -> Self {
+        let mut lut = Self::with_transmittance(parameters);
+        let multiple = lut.compute_multiple_scattering();
+        lut.compute_scattering(multiple.as_slice());
+        lut
+    }
+
+    /// Computes only the transmittance table, which the scattering tables are computed from.
+    fn with_transmittance(parameters: LutParameters) -> Self {
+        let mut lut = Self {
+            parameters,
+            transmittance: Vec::with_capacity(LUT_WIDTH * TRANSMITTANCE_HEIGHT),
+            rayleigh: Vec::with_capacity(LUT_WIDTH * LUT_R_SIZE * LUT_MU_SIZE),
+            mie: Vec::with_capacity(LUT_WIDTH * LUT_R_SIZE * LUT_MU_SIZE),
+        };
+        lut.compute_transmittance();
+        lut
+    }
+
+    /// Gets the parameters which were used to compute the tables.
+    pub fn parameters(&self) -> &LutParameters {
+        &self.parameters
+    }
+
+    fn compute_transmittance(&mut self) {
+        let p = self.parameters;
+        let h = p.horizon();
+        for y in 0..TRANSMITTANCE_HEIGHT {
+            for x in 0..LUT_WIDTH {
+                let x_mu = unit_from_coord((x as f32 + 0.5) / LUT_WIDTH as f32, LUT_WIDTH);
+                let x_r = unit_from_coord((y as f32 + 0.5) / TRANSMITTANCE_HEIGHT as f32, TRANSMITTANCE_HEIGHT);
+                let rho = h * x_r;
+                let r = (rho * rho + 1.0).sqrt();
+                let d_min = p.atmosphere_radius - r;
+                let d_max = rho + h;
+                let d = d_min + x_mu * (d_max - d_min);
+                let mu = if d == 0.0 { 1.0 } else { ((h * h - rho * rho - d * d) / (2.0 * r * d)).clamp(-1.0, 1.0) };
+
+                let dx = p.distance_to_top(r, mu) / TRANSMITTANCE_STEPS as f32;
+                let mut depth = Vector4::zeros();
+                for i in 0..=TRANSMITTANCE_STEPS {
+                    let d_i = i as f32 * dx;
+                    let r_i = (d_i * d_i + 2.0 * r * mu * d_i + r * r).sqrt();
+                    let weight = if i == 0 || i == TRANSMITTANCE_STEPS { 0.5 } else { 1.0 };
+                    depth += p.extinction(r_i) * weight;
+                }
+                self.transmittance.push(exp4(-depth * dx));
+            }
+        }
+    }
+
+    /// Gets the transmittance from a point at radius `r` to the top of the atmosphere, along a ray which doesn't hit the ground.
+    pub fn transmittance_to_top(&self, r: f32, mu: f32) -> Vector4<f32> {
+        let p = self.parameters;
+        let h = p.horizon();
+        let rho = (r * r - 1.0).max(0.0).sqrt();
+        let d = p.distance_to_top(r, mu);
+        let d_min = p.atmosphere_radius - r;
+        let d_max = rho + h;
+        let x_mu = if d_max > d_min { (d - d_min) / (d_max - d_min) } else { 0.0 };
+        let x_r = if h > 0.0 { rho / h } else { 0.0 };
+        sample_bilinear(
+            self.transmittance.as_slice(),
+            LUT_WIDTH,
+            TRANSMITTANCE_HEIGHT,
+            coord_from_unit(x_mu, LUT_WIDTH),
+            coord_from_unit(x_r, TRANSMITTANCE_HEIGHT),
+        )
+    }
+
+    /// Gets the transmittance between a point at radius `r` and the point `d` along the ray in the direction `mu`.
+    pub fn transmittance(&self, r: f32, mu: f32, d: f32, ground: bool) -> Vector4<f32> {
+        let ar = self.parameters.atmosphere_radius;
+        let r_d = (d * d + 2.0 * r * mu * d + r * r).sqrt().max(1.0).min(ar);
+        let mu_d = ((r * mu + d) / r_d).clamp(-1.0, 1.0);
+        let (near, far) = if ground {
+            (self.transmittance_to_top(r_d, -mu_d), self.transmittance_to_top(r, -mu))
+        } else {
+            (self.transmittance_to_top(r, mu), self.transmittance_to_top(r_d, mu_d))
+        };
+        near.component_div(&far.map(|t| t.max(1e-6))).map(|t| t.min(1.0))
+    }
+
+    /// Gets the transmittance of sunlight to a point at radius `r`, which fades out as the sun sets at that point.
+    pub fn transmittance_to_sun(&self, r: f32, mu_s: f32) -> Vector4<f32> {
+        let sin_h = 1.0 / r;
+        let cos_h = -(1.0 - sin_h * sin_h).max(0.0).sqrt();
+        self.transmittance_to_top(r, mu_s) * smoothstep(-SUN_HORIZON_BAND, SUN_HORIZON_BAND, mu_s - cos_h)
+    }
+
+    /// Computes the isotropic multiple scattering at each altitude and sun zenith angle.
+    fn compute_multiple_scattering(&self) -> Vec<Vector4<f32>> {
+        let p = self.parameters;
+        let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+        let mut table = Vec::with_capacity(MS_SIZE * MS_SIZE);
+        for j in 0..MS_SIZE {
+            for i in 0..MS_SIZE {
+                let mu_s = 2.0 * i as f32 / (MS_SIZE - 1) as f32 - 1.0;
+                let r = 1.0 + (p.atmosphere_radius - 1.0) * j as f32 / (MS_SIZE - 1) as f32;
+                let origin = Vector3::new(0.0, r, 0.0);
+                let sun = Vector3::new((1.0 - mu_s * mu_s).max(0.0).sqrt(), mu_s, 0.0);
+
+                // The second order scattering and the transfer function, averaged over every direction.
+                let mut second = Vector4::zeros();
+                let mut transfer = Vector4::zeros();
+                for k in 0..MS_DIRECTIONS {
+                    let y = 1.0 - 2.0 * (k as f32 + 0.5) / MS_DIRECTIONS as f32;
+                    let ring = (1.0 - y * y).max(0.0).sqrt();
+                    let phi = k as f32 * golden_angle;
+                    let dir = Vector3::new(ring * phi.cos(), y, ring * phi.sin());
+                    let distance = if intersects_ground(r, y) { distance_to_ground(r, y) } else { p.distance_to_top(r, y) };
+                    let dt = distance / MS_STEPS as f32;
+
+                    let mut depth = Vector4::zeros();
+                    for s in 0..MS_STEPS {
+                        let point = origin + dir * ((s as f32 + 0.5) * dt);
+                        let r_s = point.norm();
+                        let extinction = p.extinction(r_s);
+                        let scattering = p.scattering(r_s);
+                        let t = exp4(-(depth + extinction * (dt * 0.5)));
+                        let mu_s_s = point.dot(&sun) / r_s;
+                        second += t.component_mul(&scattering).component_mul(&self.transmittance_to_sun(r_s, mu_s_s)) * (dt / (4.0 * PI));
+                        transfer += t.component_mul(&scattering) * dt;
+                        depth += extinction * dt;
+                    }
+                }
+                second /= MS_DIRECTIONS as f32;
+                transfer /= MS_DIRECTIONS as f32;
+                // The sum of every order of scattering is a geometric series.
+                table.push(second.component_div(&transfer.map(|f| 1.0 - f.min(0.99))));
+            }
+        }
+        table
+    }
+
+    fn compute_scattering(&mut self, multiple: &[Vector4<f32>]) {
+        let p = self.parameters;
+        let ar = p.atmosphere_radius;
+        let h = p.horizon();
+        let multiple_at = |r: f32, mu_s: f32| {
+            let u = (mu_s + 1.0) * 0.5;
+            let v = (r - 1.0) / (ar - 1.0).max(f32::EPSILON);
+            sample_bilinear(multiple, MS_SIZE, MS_SIZE, coord_from_unit(u, MS_SIZE), coord_from_unit(v, MS_SIZE))
+        };
+        let d_top_min = ar - 1.0;
+        let big_a = (p.distance_to_top(1.0, MU_S_MIN) - d_top_min) / (h - d_top_min).max(f32::EPSILON);
+
+        for i_r in 0..LUT_R_SIZE {
+            for j_mu in 0..LUT_MU_SIZE {
+                let rho = h * i_r as f32 / (LUT_R_SIZE - 1) as f32;
+                let r = (rho * rho + 1.0).sqrt();
+                let u_mu = (j_mu as f32 + 0.5) / LUT_MU_SIZE as f32;
+                let (mu, ground) = if u_mu < 0.5 {
+                    let d_min = r - 1.0;
+                    let d_max = rho;
+                    let d = d_min + (d_max - d_min) * unit_from_coord(1.0 - 2.0 * u_mu, LUT_MU_SIZE / 2);
+                    let mu = if d == 0.0 { -1.0 } else { (-(rho * rho + d * d) / (2.0 * r * d)).clamp(-1.0, 1.0) };
+                    (mu, true)
+                } else {
+                    let d_min = ar - r;
+                    let d_max = rho + h;
+                    let d = d_min + (d_max - d_min) * unit_from_coord(2.0 * u_mu - 1.0, LUT_MU_SIZE / 2);
+                    let mu = if d == 0.0 { 1.0 } else { ((h * h - rho * rho - d * d) / (2.0 * r * d)).clamp(-1.0, 1.0) };
+                    (mu, false)
+                };
+                for n in 0..LUT_NU_SIZE {
+                    for k in 0..LUT_MU_S_SIZE {
+                        let x_mu_s = unit_from_coord((k as f32 + 0.5) / LUT_MU_S_SIZE as f32, LUT_MU_S_SIZE);
+                        let a = (big_a - x_mu_s * big_a) / (1.0 + x_mu_s * big_a);
+                        let d = d_top_min + a.min(big_a) * (h - d_top_min);
+                        let mu_s = if d == 0.0 { 1.0 } else { ((h * h - d * d) / (2.0 * d)).clamp(-1.0, 1.0) };
+                        // Not every angle between the view and the sun is possible for the zenith angles.
+                        let spread = ((1.0 - mu * mu) * (1.0 - mu_s * mu_s)).max(0.0).sqrt();
+                        let nu = (2.0 * n as f32 / (LUT_NU_SIZE - 1) as f32 - 1.0)
+                            .max(mu * mu_s - spread)
+                            .min(mu * mu_s + spread);
+
+                        let (rayleigh, mie) = self.integrate_scattering(r, mu, mu_s, nu, ground, Some(&multiple_at));
+                        self.rayleigh.push(rayleigh);
+                        self.mie.push(mie);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Integrates the Rayleigh and Mie scattering towards a point at radius `r` along the view ray in the direction `mu`,
+    /// for a sun in the direction `mu_s` where `nu` is the cosine of the angle between the view and the sun.
+    /// The multiple scattering is added to the Rayleigh scattering if its table is given.
+    /// These are the entries of the scattering tables, without the scattering coefficients and phase functions.
+    fn integrate_scattering(
+        &self,
+        r: f32,
+        mu: f32,
+        mu_s: f32,
+        nu: f32,
+        ground: bool,
+        multiple_at: Option<&dyn Fn(f32, f32) -> Vector4<f32>>,
+    ) -> (Vector4<f32>, Vector4<f32>) {
+        let p = self.parameters;
+        let ar = p.atmosphere_radius;
+        let distance = if ground { distance_to_ground(r, mu) } else { p.distance_to_top(r, mu) };
+        let dx = distance / SCATTERING_STEPS as f32;
+
+        let mut rayleigh = Vector4::zeros();
+        let mut mie = Vector4::zeros();
+        let mut multiple_sum = Vector4::zeros();
+        for s in 0..=SCATTERING_STEPS {
+            let d_s = s as f32 * dx;
+            let r_s = (d_s * d_s + 2.0 * r * mu * d_s + r * r).sqrt().max(1.0).min(ar);
+            let mu_s_s = ((r * mu_s + d_s * nu) / r_s).clamp(-1.0, 1.0);
+            let view = self.transmittance(r, mu, d_s, ground);
+            let t = view.component_mul(&self.transmittance_to_sun(r_s, mu_s_s));
+            let (density_ray, density_mie) = p.densities(r_s);
+            let weight = if s == 0 || s == SCATTERING_STEPS { 0.5 } else { 1.0 };
+            rayleigh += t * (density_ray * weight);
+            mie += t * (density_mie * weight);
+            if let Some(multiple_at) = multiple_at {
+                multiple_sum += view.component_mul(&p.scattering(r_s)).component_mul(&multiple_at(r_s, mu_s_s)) * weight;
+            }
+        }
+        // The multiple scattering is isotropic, so it is stored with the Rayleigh scattering after
+        // dividing out the terms which are applied when the table is sampled.
+        let divisor = (p.rayleigh * phase_ray(nu * nu)).map(|k| k.max(1e-6));
+        rayleigh += multiple_sum.component_div(&divisor);
+        (rayleigh * dx, mie * dx)
+    }
+
+    /// Computes the light of a sun with an intensity of 1.0 which is scattered once towards a point at radius `r` from the direction `mu`,
+    /// before it is scaled by the density of the atmosphere. This is the sky which the tables describe without the multiple scattering,
+    /// and since it only needs the transmittance table it is much cheaper than computing every table.
+    #[cfg(test)]
+    pub(crate) fn single_scattering(parameters: LutParameters, r: f32, mu: f32, mu_s: f32, nu: f32, mie_anisotropy: f32) -> Vector4<f32> {
+        let lut = Self::with_transmittance(parameters);
+        let (rayleigh, mie) = lut.integrate_scattering(r, mu, mu_s, nu, intersects_ground(r, mu), None);
+        rayleigh.component_mul(&parameters.rayleigh) * phase_ray(nu * nu) + mie * (parameters.mie * phase_mie(mie_anisotropy, nu, nu * nu))
+    }
+
+    /// Writes the tables to a layer of the texture as half floats, which are `LUT_WIDTH` texels wide.
+    fn write_layer(&self, layer: &mut [u8]) {
+        let texels = self.rayleigh.iter().chain(self.mie.iter()).chain(self.transmittance.iter());
+        for (bytes, texel) in layer.chunks_exact_mut(8).zip(texels) {
+            for (c, value) in texel.iter().enumerate() {
+                bytes[2 * c..2 * c + 2].copy_from_slice(&half_bits(*value).to_ne_bytes());
+            }
+        }
+    }
+}
+
+/// The number of bytes in each layer of the texture, which has four half floats in each texel.
+const LAYER_BYTES: usize = LUT_WIDTH * LUT_LAYER_HEIGHT * 8;
+
+/// Converts a float to the bits of the nearest half float.
+fn half_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+    if exponent == 0xff {
+        // Infinity stays infinite, and NaN stays NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x0200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // Small values become subnormal half floats, or zero.
+        if half_exponent < -10 {
+            return sign;
+        }
+        let m = mantissa | 0x0080_0000;
+        let shift = (14 - half_exponent) as u32;
+        let rounded = (m + (1 << (shift - 1)) - 1 + ((m >> shift) & 1)) >> shift;
+        return sign | rounded as u16;
+    }
+    // Round to nearest even, which may carry into the exponent.
+    let m = mantissa >> 13;
+    let rest = mantissa & 0x1fff;
+    let mut half = ((half_exponent as u32) << 10) | m;
+    if rest > 0x1000 || (rest == 0x1000 && m & 1 == 1) {
+        half += 1;
+    }
+    sign | half as u16
+}
+
+/// The layers of the lookup table texture, which keeps the tables of each set of parameters in the same layer.
+/// Tables which are no longer used stay in the texture until their layer is needed for new tables.
+#[derive(Debug, Default)]
+struct LutLayers {
+    /// The parameters of the tables in each layer, and whether an atmosphere uses them.
+    keys: Vec<(LutKey, bool)>,
+    /// The texels of every layer.
+    data: Vec<u8>,
+}
+
+impl LutLayers {
+    /// Marks the layers which are used by the specified parameters, and returns the keys which don't have a layer.
+    fn mark_used(&mut self, keys: &[LutKey]) -> Vec<LutKey> {
+        for (key, used) in self.keys.iter_mut() {
+            *used = keys.contains(key);
+        }
+        keys.iter().filter(|key| self.layer(key).is_none()).copied().collect()
+    }
+
+    /// Writes the tables to a new layer, or to the layer of tables which are no longer used once there are `MAX_PLANETS` layers.
+    /// Returns the layer, or `None` if every layer is in use.
+    fn insert(&mut self, lut: &AtmosphereLut) -> Option<usize> {
+        let key = lut.parameters().key();
+        let layer = match self.layer(&key) {
+            Some(layer) => layer,
+            None if self.keys.len() < MAX_PLANETS => {
+                self.keys.push((key, true));
+                self.data.resize(self.keys.len() * LAYER_BYTES, 0);
+                self.keys.len() - 1
+            }
+            None => self.keys.iter().position(|(_, used)| !used)?,
+        };
+        self.keys[layer] = (key, true);
+        lut.write_layer(&mut self.data[layer * LAYER_BYTES..(layer + 1) * LAYER_BYTES]);
+        Some(layer)
+    }
+
+    /// Gets the layer which holds the tables of the specified parameters.
+    fn layer(&self, key: &LutKey) -> Option<usize> {
+        self.keys.iter().position(|(k, _)| k == key)
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+/// The lookup tables which are used by the atmosphere pass, as a texture array.
+/// The texture has a layer for every set of `LutParameters` in the world, once its tables have been computed.
+#[derive(Debug, Default)]
+pub struct AtmosphereLuts {
+    texture: Option<Handle<Texture>>,
+    layers: HashMap<LutKey, u32>,
+}
+
+impl AtmosphereLuts {
+    /// Gets the handle of the texture, if it has been created.
+    pub fn texture(&self) -> Option<&Handle<Texture>> {
+        self.texture.as_ref()
+    }
+
+    /// Gets the layer of the texture which holds the tables of the specified atmosphere.
+    pub fn layer(&self, atmosphere: &Atmosphere) -> Option<u32> {
+        self.layers.get(&LutParameters::new(atmosphere).key()).copied()
+    }
+}
+
+/// The tables computed by a job on the thread pool, or the panic which stopped it.
+type LutResult = (LutKey, thread::Result<AtmosphereLut>);
+
+/// Computes the lookup tables of every `Atmosphere` and uploads them as a texture, whenever the atmospheres change.
+/// The tables are computed on the thread pool, and only the tables of new parameters are computed.
+/// At most `MAX_PENDING` tables are computed at once, and tables which are no longer used by the time they are done are dropped.
+#[derive(Debug)]
+pub struct AtmosphereLutSystem {
+    layers: LutLayers,
+    /// The parameters whose tables are being computed.
+    pending: Vec<LutKey>,
+    /// The parameters whose tables couldn't be computed, which aren't tried again.
+    failed: Vec<LutKey>,
+    sender: Sender<LutResult>,
+    receiver: Receiver<LutResult>,
+}
+
+impl Default for AtmosphereLutSystem {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Self { layers: LutLayers::default(), pending: Vec::new(), failed: Vec::new(), sender, receiver }
+    }
+}
+
+impl<'a> System<'a> for AtmosphereLutSystem {
+    type SystemData = (
+        ReadStorage<'a, Atmosphere>,
+        ReadExpect<'a, Loader>,
+        ReadExpect<'a, ArcThreadPool>,
+        Read<'a, AssetStorage<Texture>>,
+        Write<'a, AtmosphereLuts>,
+    );
+
+    fn run(&mut self, (atmospheres, loader, pool, texture_storage, mut luts): Self::SystemData) {
+        let mut parameters: Vec<LutParameters> = Vec::new();
+        for atmosphere in (&atmospheres).join() {
+            let p = LutParameters::new(atmosphere);
+            if parameters.len() < MAX_PLANETS && !parameters.iter().any(|q| q.key() == p.key()) {
+                parameters.push(p);
+            }
+        }
+        let keys: Vec<LutKey> = parameters.iter().map(LutParameters::key).collect();
+        // The keys which are still missing once the pending tables are done are queued again in a later frame.
+        for key in self.layers.mark_used(keys.as_slice()) {
+            if self.pending.len() == MAX_PENDING {
+                break;
+            }
+            if self.pending.contains(&key) || self.failed.contains(&key) {
+                continue;
+            }
+            self.pending.push(key);
+            let p = parameters[keys.iter().position(|k| *k == key).unwrap()];
+            let sender = self.sender.clone();
+            pool.spawn(move || {
+                // A panic would otherwise abort the thread pool, and leave the key pending forever.
+                let lut = panic::catch_unwind(AssertUnwindSafe(|| AtmosphereLut::compute(p)));
+                let _ = sender.send((key, lut));
+            });
+        }
+
+        // The atmospheres use the raymarching pass until their tables are ready.
+        let mut changed = false;
+        for (key, lut) in self.receiver.try_iter() {
+            self.pending.retain(|k| *k != key);
+            match lut {
+                // The atmosphere may have changed while its tables were computed, in which case they are stale.
+                Ok(lut) if keys.contains(&key) => changed |= self.layers.insert(&lut).is_some(),
+                Ok(_) => {}
+                Err(_) => {
+                    log::error!("Failed to compute the lookup tables of an atmosphere, which is raymarched instead.");
+                    self.failed.push(key);
+                }
+            }
+        }
+        if !changed {
+            return;
+        }
+
+        // Only the new tables are computed, but the whole texture is uploaded again.
+        let builder = TextureBuilder::new()
+            .with_kind(hal::image::Kind::D2(LUT_WIDTH as u32, LUT_LAYER_HEIGHT as u32, self.layers.len() as hal::image::Layer, 1))
+            .with_view_kind(hal::image::ViewKind::D2Array)
+            .with_data_width(LUT_WIDTH as u32)
+            .with_data_height(LUT_LAYER_HEIGHT as u32)
+            .with_sampler_info(hal::image::SamplerInfo::new(hal::image::Filter::Linear, hal::image::WrapMode::Clamp))
+            .with_raw_data(self.layers.data.clone(), hal::format::Format::Rgba16Sfloat);
+        luts.texture = Some(loader.load_from_data(TextureData(builder), (), &texture_storage));
+        luts.layers = self.layers.keys.iter().enumerate().map(|(layer, (key, _))| (*key, layer as u32)).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::renderer::palette::Srgb;
+
+    use super::*;
+    use crate::planet::EARTH_OZONE;
+
+    fn earth() -> Atmosphere {
+        Atmosphere::new(1.025, Srgb::new(1.0, 1.0, 1.0), 1.0, 1.0)
+    }
+
+    /// The transmittance from the ground to the top of the atmosphere along the horizon.
+    fn horizon_transmittance(atmosphere: &Atmosphere) -> Vector4<f32> {
+        AtmosphereLut::with_transmittance(LutParameters::new(atmosphere)).transmittance_to_top(1.0, 0.0)
+    }
+
+    #[test]
+    fn absorbing_layer_has_a_tent_profile() {
+        let parameters = LutParameters::new(&earth().with_absorption(EARTH_OZONE, 0.25, 0.15));
+        let (center, width) = (parameters.absorption_center, parameters.absorption_width);
+        assert!((center - (1.0 + 0.25 * 0.025)).abs() < 1e-6);
+        assert!((width - 0.15 * 0.025).abs() < 1e-6);
+        assert_eq!(parameters.absorption_density(center), 1.0);
+        // The density falls linearly on both sides of the center.
+        for &x in &[0.25, 0.5, 0.75] {
+            assert!((parameters.absorption_density(center + x * width) - (1.0 - x)).abs() < 1e-3);
+            assert!((parameters.absorption_density(center - x * width) - (1.0 - x)).abs() < 1e-3);
+        }
+        assert_eq!(parameters.absorption_density(center + width * 1.01), 0.0);
+        assert_eq!(parameters.absorption_density(center - width * 1.01), 0.0);
+    }
+
+    #[test]
+    fn absorption_lowers_the_transmittance_of_its_channels() {
+        let clear = horizon_transmittance(&earth());
+        let ozone = horizon_transmittance(&earth().with_absorption(EARTH_OZONE, 0.25, 0.15));
+        let ratio = ozone.component_div(&clear);
+        for c in 0..3 {
+            assert!(ratio[c] < 1.0);
+        }
+        // The ozone absorbs green the most and blue the least.
+        assert!(ratio.y < ratio.x);
+        assert!(ratio.x < ratio.z);
+        // The absorbing layer doesn't change the coverage of the atmosphere.
+        assert_eq!(ratio.w, 1.0);
+
+        // Absorption in a single channel leaves the others alone.
+        let red = horizon_transmittance(&earth().with_absorption([5.0, 0.0, 0.0], 0.25, 0.15));
+        assert!(red.x < clear.x);
+        assert_eq!((red.y, red.z, red.w), (clear.y, clear.z, clear.w));
+    }
+
+    #[test]
+    fn transmittance_falls_with_path_length() {
+        let lut = AtmosphereLut::with_transmittance(LutParameters::new(&earth()));
+        let ar = lut.parameters().atmosphere_radius;
+        for &(r, mu) in &[(1.0, 0.0), (1.005, 0.5), (1.01, -0.05), (1.02, 1.0), (1.01, -0.5)] {
+            let ground = intersects_ground(r, mu);
+            let length = if ground { distance_to_ground(r, mu) } else { lut.parameters().distance_to_top(r, mu) };
+            let mut previous = Vector4::repeat(1.0);
+            for i in 0..=16 {
+                let t = lut.transmittance(r, mu, length * i as f32 / 16.0, ground);
+                for c in 0..4 {
+                    assert!(t[c] >= 0.0 && t[c] <= 1.0);
+                    assert!(t[c] <= previous[c] + 1e-3);
+                }
+                previous = t;
+            }
+        }
+        assert!(lut.transmittance_to_top(1.0, 0.0).x < lut.transmittance_to_top(1.0, 1.0).x);
+        assert!(lut.transmittance_to_top(1.0, 1.0).x < lut.transmittance_to_top(ar, 1.0).x);
+    }
+
+    #[test]
+    fn transmittance_matches_the_ground_and_the_top_of_the_atmosphere() {
+        let lut = AtmosphereLut::with_transmittance(LutParameters::new(&earth()));
+        let p = *lut.parameters();
+        let ar = p.atmosphere_radius;
+        // Nothing is in the way at the top of the atmosphere.
+        for &mu in &[0.0, 0.5, 1.0] {
+            let t = lut.transmittance_to_top(ar, mu);
+            for c in 0..4 {
+                assert!((t[c] - 1.0).abs() < 1e-5);
+            }
+        }
+        // Straight up from the ground, the optical depth of each kind of particle is the integral of its exponential density.
+        let depth = |scale_height: f32| scale_height * (1.0 - (-(ar - 1.0) / scale_height).exp());
+        let expected = exp4(-(p.rayleigh * depth(p.rayleigh_scale_height)
+            + Vector4::repeat(p.mie * p.mie_extinction * depth(p.mie_scale_height))));
+        let t = lut.transmittance_to_top(1.0, 1.0);
+        for c in 0..4 {
+            assert!((t[c] / expected[c] - 1.0).abs() < 1e-2, "{} != {}", t[c], expected[c]);
+        }
+    }
+
+    #[test]
+    fn keys_only_change_with_the_parameters_of_the_tables() {
+        let key = |atmosphere: &Atmosphere| LutParameters::new(atmosphere).key();
+        assert_eq!(key(&earth()), key(&earth()));
+        // The density and the Mie anisotropy are applied by the shader.
+        assert_eq!(key(&earth()), key(&Atmosphere::new(1.025, Srgb::new(1.0, 1.0, 1.0), 0.5, 2.0)));
+        assert_eq!(key(&earth()), key(&earth().with_mie(21.0, 1.1, 0.5)));
+
+        assert_ne!(key(&earth()), key(&Atmosphere::new(1.05, Srgb::new(1.0, 1.0, 1.0), 1.0, 1.0)));
+        assert_ne!(key(&earth()), key(&Atmosphere::new(1.025, Srgb::new(1.0, 0.5, 1.0), 1.0, 1.0)));
+        assert_ne!(key(&earth()), key(&earth().with_mie(10.0, 1.1, -0.78)));
+        assert_ne!(key(&earth()), key(&earth().with_scale_heights(0.02, 0.004)));
+        assert_ne!(key(&earth()), key(&earth().with_absorption(EARTH_OZONE, 0.25, 0.15)));
+    }
+
+    /// A lookup table without any texels, which is enough to find its layer.
+    fn empty_lut(height: f32) -> AtmosphereLut {
+        AtmosphereLut {
+            parameters: LutParameters::new(&Atmosphere::new(height, Srgb::new(1.0, 1.0, 1.0), 1.0, 1.0)),
+            transmittance: Vec::new(),
+            rayleigh: Vec::new(),
+            mie: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn layers_are_reused() {
+        let luts: Vec<AtmosphereLut> = (0..=MAX_PLANETS).map(|i| empty_lut(1.01 + 0.01 * i as f32)).collect();
+        let keys: Vec<LutKey> = luts.iter().map(|lut| lut.parameters().key()).collect();
+        let mut layers = LutLayers::default();
+        assert_eq!(layers.mark_used(&keys[..2]), &keys[..2]);
+        assert_eq!(layers.insert(&luts[0]), Some(0));
+        assert_eq!(layers.insert(&luts[1]), Some(1));
+        assert_eq!(layers.data.len(), 2 * LAYER_BYTES);
+
+        // Cached tables aren't computed again, and keep their layer even when they aren't used.
+        assert!(layers.mark_used(&keys[..2]).is_empty());
+        assert_eq!(layers.mark_used(&keys[1..3]), &keys[2..3]);
+        assert_eq!(layers.insert(&luts[2]), Some(2));
+        assert_eq!(layers.layer(&keys[0]), Some(0));
+        assert!(layers.mark_used(&keys[..3]).is_empty());
+
+        // Once every layer is taken, new tables replace the tables which aren't used.
+        assert_eq!(layers.mark_used(&keys[..MAX_PLANETS]), &keys[3..MAX_PLANETS]);
+        for (i, lut) in luts.iter().enumerate().take(MAX_PLANETS).skip(3) {
+            assert_eq!(layers.insert(lut), Some(i));
+        }
+        assert_eq!(layers.insert(&luts[MAX_PLANETS]), None);
+        assert_eq!(layers.mark_used(&keys[1..]), &keys[MAX_PLANETS..]);
+        assert_eq!(layers.insert(&luts[MAX_PLANETS]), Some(0));
+        assert_eq!(layers.layer(&keys[0]), None);
+        assert_eq!(layers.len(), MAX_PLANETS);
+        assert_eq!(layers.data.len(), MAX_PLANETS * LAYER_BYTES);
+    }
+
+    #[test]
+    fn half_floats_round_to_the_nearest_value() {
+        assert_eq!(half_bits(0.0), 0x0000);
+        assert_eq!(half_bits(-0.0), 0x8000);
+        assert_eq!(half_bits(1.0), 0x3c00);
+        assert_eq!(half_bits(0.5), 0x3800);
+        assert_eq!(half_bits(-2.0), 0xc000);
+        assert_eq!(half_bits(65504.0), 0x7bff);
+        assert_eq!(half_bits(1e6), 0x7c00);
+        assert_eq!(half_bits(f32::INFINITY), 0x7c00);
+        assert_eq!(half_bits(f32::NAN) & 0x7c00, 0x7c00);
+        assert_ne!(half_bits(f32::NAN) & 0x03ff, 0);
+        // The halfway point between two halves rounds to the even one.
+        assert_eq!(half_bits(1.0 + 1.0 / 2048.0), 0x3c00);
+        assert_eq!(half_bits(1.0 + 3.0 / 2048.0), 0x3c02);
+        assert_eq!(half_bits(1.0 + 1.5 / 2048.0), 0x3c01);
+        // Subnormal halves.
+        assert_eq!(half_bits(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(half_bits(2.0f32.powi(-15)), 0x0200);
+        assert_eq!(half_bits(2.0f32.powi(-26)), 0x0000);
+    }
+}
@@ -1,5 +1,10 @@
 pub mod pass;
 pub mod sub;
+pub mod scattering;
+pub mod lut;
+pub mod terrain;
+pub mod terrain_map;
+pub mod preset;
 
 use amethyst::{
     assets::PrefabData,
@@ -16,6 +21,8 @@ use glsl_layout::*;
 
 use serde::{Serialize, Deserialize};
 
+use terrain::Terrain;
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PrefabData)]
 #[prefab(Component)]
 /// Describes a planet's planet (from a rendering perspective).
@@ -27,17 +34,161 @@ pub struct Atmosphere {
     pub base_planet_radius: f32,
 
     /// Hue color of the planet.
+    /// This tints the Rayleigh scattering coefficients, so that the sky scatters more of the hue's color.
+    /// White leaves the coefficients unchanged.
     #[serde(with = "amethyst::renderer::serde_shim::srgb")]
     pub hue: Srgb,
 
     /// The degree of obfuscation which is invoked by this planet.
     pub density: f32,
+
+    /// The Rayleigh scattering coefficients (red, green, blue) for a planet with a radius of 1.0.
+    /// Larger values scatter more of that color, which is why the blue coefficient of the earth's sky is the largest.
+    #[serde(default = "default_rayleigh")]
+    pub rayleigh: [f32; 3],
+
+    /// The Mie scattering coefficient for a planet with a radius of 1.0, which controls the amount of dust and haze.
+    #[serde(default = "default_mie")]
+    pub mie: f32,
+
+    /// The ratio of Mie extinction to Mie scattering, since aerosols absorb some of the light.
+    #[serde(default = "default_mie_extinction")]
+    pub mie_extinction: f32,
+
+    /// The anisotropy of Mie scattering, from -1.0 (all forward scattering) to 1.0 (all back scattering).
+    #[serde(default = "default_mie_anisotropy")]
+    pub mie_anisotropy: f32,
+
+    /// The height at which the density of Rayleigh scattering air falls by a factor of e, relative to the atmosphere's height.
+    #[serde(default = "default_rayleigh_scale_height")]
+    pub rayleigh_scale_height: f32,
+
+    /// The height at which the density of Mie scattering aerosols falls by a factor of e, relative to the atmosphere's height.
+    #[serde(default = "default_mie_scale_height")]
+    pub mie_scale_height: f32,
+
+    /// The absorption coefficients (red, green, blue) of an absorbing layer such as ozone, for a planet with a radius of 1.0.
+    /// The layer removes light without scattering it. Zero (the default) disables the layer.
+    /// Use `EARTH_OZONE` for the ozone of the earth, which deepens the blue of the sky at twilight.
+    #[serde(default)]
+    pub absorption: [f32; 3],
+
+    /// The altitude of the densest part of the absorbing layer, as a fraction of the way from the ground to the top of the atmosphere.
+    #[serde(default = "default_absorption_altitude")]
+    pub absorption_altitude: f32,
+
+    /// The distance from the densest part of the absorbing layer to where it ends, as a fraction of the thickness of the atmosphere.
+    /// The density falls linearly on both sides, so the layer has a tent shaped profile.
+    #[serde(default = "default_absorption_width")]
+    pub absorption_width: f32,
+}
+
+/// The absorption coefficients of the earth's ozone layer, for an atmosphere which is 2.5% of the planet's radius thick
+/// with the default altitude and width of the absorbing layer.
+pub const EARTH_OZONE: [f32; 3] = [2.6, 7.5, 0.34];
+
+fn default_rayleigh() -> [f32; 3] {
+    [3.8, 13.5, 33.1]
+}
+
+fn default_mie() -> f32 {
+    21.0
+}
+
+fn default_mie_extinction() -> f32 {
+    1.1
+}
+
+fn default_mie_anisotropy() -> f32 {
+    -0.78
+}
+
+fn default_rayleigh_scale_height() -> f32 {
+    0.01
+}
+
+fn default_mie_scale_height() -> f32 {
+    0.004
+}
+
+fn default_absorption_altitude() -> f32 {
+    0.25
+}
+
+fn default_absorption_width() -> f32 {
+    0.15
 }
 
 impl Atmosphere {
     /// Create a new planet component with the specified data.
+    /// The scattering coefficients are those of the earth, which can be changed with the `with_` methods.
     pub fn new(height: f32, hue: Srgb, density: f32, base_planet_radius: f32) -> Self {
-        Self { height, hue, density, base_planet_radius }
+        Self {
+            height,
+            hue,
+            density,
+            base_planet_radius,
+            rayleigh: default_rayleigh(),
+            mie: default_mie(),
+            mie_extinction: default_mie_extinction(),
+            mie_anisotropy: default_mie_anisotropy(),
+            rayleigh_scale_height: default_rayleigh_scale_height(),
+            mie_scale_height: default_mie_scale_height(),
+            absorption: [0.0; 3],
+            absorption_altitude: default_absorption_altitude(),
+            absorption_width: default_absorption_width(),
+        }
+    }
+
+    /// Gets the Rayleigh scattering coefficients after they have been tinted by the hue.
+    /// Each coefficient is multiplied by the matching channel of the hue, and then they are all rescaled so that
+    /// the total amount of scattering stays the same. This means that the hue changes the color of the sky but not its thickness.
+    pub fn tinted_rayleigh(&self) -> [f32; 3] {
+        let hue = [self.hue.red.max(0.0), self.hue.green.max(0.0), self.hue.blue.max(0.0)];
+        let tinted = [self.rayleigh[0] * hue[0], self.rayleigh[1] * hue[1], self.rayleigh[2] * hue[2]];
+        let total: f32 = tinted.iter().sum();
+        if total <= 0.0 {
+            return self.rayleigh;
+        }
+        let scale = self.rayleigh.iter().sum::<f32>() / total;
+        [tinted[0] * scale, tinted[1] * scale, tinted[2] * scale]
+    }
+
+    /// Changes the Rayleigh scattering coefficients (red, green, blue).
+    pub fn with_rayleigh(mut self, rayleigh: [f32; 3]) -> Self {
+        self.rayleigh = rayleigh;
+        self
+    }
+
+    /// Changes the Mie scattering coefficient, the ratio of extinction to scattering and the anisotropy.
+    pub fn with_mie(mut self, mie: f32, mie_extinction: f32, mie_anisotropy: f32) -> Self {
+        self.mie = mie;
+        self.mie_extinction = mie_extinction;
+        self.mie_anisotropy = mie_anisotropy;
+        self
+    }
+
+    /// Changes the Rayleigh and Mie scale heights (relative to the atmosphere's height).
+    pub fn with_scale_heights(mut self, rayleigh_scale_height: f32, mie_scale_height: f32) -> Self {
+        self.rayleigh_scale_height = rayleigh_scale_height;
+        self.mie_scale_height = mie_scale_height;
+        self
+    }
+
+    /// Adds an absorbing layer (such as ozone) with the specified absorption coefficients (red, green, blue).
+    /// The altitude of its densest part and its half width are fractions of the thickness of the atmosphere.
+    pub fn with_absorption(mut self, absorption: [f32; 3], absorption_altitude: f32, absorption_width: f32) -> Self {
+        self.absorption = absorption;
+        self.absorption_altitude = absorption_altitude;
+        self.absorption_width = absorption_width;
+        self
+    }
+
+    /// Gets the radius of the densest part of the absorbing layer, and the distance over which its density falls to zero,
+    /// for a planet with a radius of 1.0.
+    pub fn absorption_layer(&self) -> (f32, f32) {
+        let thickness = (self.height - 1.0).max(0.0);
+        (1.0 + self.absorption_altitude * thickness, (self.absorption_width * thickness).max(1e-6))
     }
 
     #[inline]
@@ -65,9 +216,10 @@ impl Component for Atmosphere {
 }
 
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, PrefabData)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
 #[prefab(Component)]
 /// Describes a planet's planet (from a rendering perspective).
+/// The planet is a smooth sphere unless it has a `Terrain` on the same entity.
 pub struct Planet {
     /// Hue color of the planet.
     pub radius: f32,
@@ -82,6 +234,25 @@ impl Planet {
         Self { radius, density }
     }
 
+    /// Gets the distance from the center of the planet to its surface in the specified direction (in the planet's local space),
+    /// where `terrain` is the `Terrain` of the planet, if it has one.
+    pub fn surface_radius(&self, terrain: Option<&Terrain>, direction: Vector3<f32>) -> f32 {
+        match terrain {
+            Some(terrain) => self.radius * (1.0 + terrain.height(direction)),
+            None => self.radius,
+        }
+    }
+
+    /// Gets the point on the surface in the specified direction from the center of the planet (in the planet's local space).
+    pub fn surface_point(&self, terrain: Option<&Terrain>, direction: Vector3<f32>) -> Vector3<f32> {
+        direction.normalize() * self.surface_radius(terrain, direction)
+    }
+
+    /// Gets the height of a point (in the planet's local space) above the surface, which is negative below the surface.
+    pub fn altitude(&self, terrain: Option<&Terrain>, point: Vector3<f32>) -> f32 {
+        point.norm() - self.surface_radius(terrain, point)
+    }
+
     #[inline]
     /// Gets the hue for this planet.
     pub fn radius(&self) -> f32 {
@@ -101,24 +272,57 @@ impl Component for Planet {
 
 pub const MAX_PLANETS: usize = 8;
 
+/// The absorbing layer of an atmosphere (such as ozone), which removes light without scattering it.
+/// This is shared by the uniforms of every shader which filters light through an atmosphere, matching `absorption.glsl`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct AbsorptionData {
+    pub coefficients: vec3,
+    /// The radius of the densest part of the absorbing layer, relative to the planet's radius.
+    pub center: float,
+    pub width: float,
+}
+
+impl AbsorptionData {
+    /// Gets the absorbing layer of an atmosphere. Without an atmosphere nothing is absorbed.
+    pub(crate) fn new(atmosphere: Option<&Atmosphere>) -> Self {
+        let (coefficients, (center, width)) = atmosphere.map_or(([0.0; 3], (1.0, 1.0)), |atmosphere| (atmosphere.absorption, atmosphere.absorption_layer()));
+        Self { coefficients: coefficients.into(), center, width }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
 #[repr(C, align(4))]
 pub(crate) struct PlanetData {
     pub center: vec3,
     pub radius: float,
-    pub hue: vec3,
     pub atmosphere_radius: float,
     pub atmosphere_density: float,
+    pub rayleigh: vec3,
+    pub mie: float,
+    pub mie_extinction: float,
+    pub mie_anisotropy: float,
+    pub rayleigh_scale_height: float,
+    pub mie_scale_height: float,
+    pub lut_layer: uint,
+    pub absorption: AbsorptionData,
 }
 
 impl PlanetData {
-    pub(crate) fn new(atmosphere: &Atmosphere, center: Vector3<f32>, radius: f32) -> Self {
+    pub(crate) fn new(atmosphere: &Atmosphere, center: Vector3<f32>, radius: f32, lut_layer: u32) -> Self {
         Self {
             center: Into::<[f32; 3]>::into(center).into(),
             radius,
-            hue: [atmosphere.hue.red, atmosphere.hue.green, atmosphere.hue.blue].into(),
             atmosphere_radius: radius * atmosphere.height(),
             atmosphere_density: atmosphere.density(),
+            rayleigh: atmosphere.tinted_rayleigh().into(),
+            mie: atmosphere.mie,
+            mie_extinction: atmosphere.mie_extinction,
+            mie_anisotropy: atmosphere.mie_anisotropy,
+            rayleigh_scale_height: atmosphere.rayleigh_scale_height,
+            mie_scale_height: atmosphere.mie_scale_height,
+            lut_layer,
+            absorption: AbsorptionData::new(Some(atmosphere)),
         }
     }
 }
@@ -139,4 +343,71 @@ impl PlanetList {
         }
         Self { planets, count: planet_data.len() as u32 }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use amethyst::core::{math::Vector4, transform::Transform};
+
+    use super::*;
+    use super::scattering::ScatteringSphere;
+
+    fn with_hue(red: f32, green: f32, blue: f32) -> Atmosphere {
+        Atmosphere::new(1.025, Srgb::new(red, green, blue), 1.0, 1.0)
+    }
+
+    /// The transmittance along a ray which grazes the top of the ground.
+    fn grazing_transmittance(atmosphere: &Atmosphere) -> Vector3<f32> {
+        let mut transform = Transform::default();
+        transform.copy_local_to_global();
+        ScatteringSphere::new(atmosphere, &transform).transmittance(Vector3::new(-5.0, 1.005, 0.0), Vector3::new(1.0, 0.0, 0.0), f32::INFINITY)
+    }
+
+    /// The light which is scattered towards the ground from the zenith, when the sun is 60 degrees from the zenith.
+    fn sky_radiance(atmosphere: &Atmosphere) -> Vector4<f32> {
+        let mu_s = 0.5;
+        lut::AtmosphereLut::single_scattering(lut::LutParameters::new(atmosphere), 1.0, 1.0, mu_s, mu_s, atmosphere.mie_anisotropy)
+    }
+
+    #[test]
+    fn white_hue_leaves_the_coefficients_unchanged() {
+        let atmosphere = with_hue(1.0, 1.0, 1.0);
+        let tinted = atmosphere.tinted_rayleigh();
+        for (tinted, rayleigh) in tinted.iter().zip(atmosphere.rayleigh.iter()) {
+            assert!((tinted - rayleigh).abs() < 1e-4);
+        }
+        // Scaling the hue doesn't change its color.
+        assert_eq!(with_hue(0.5, 0.5, 0.5).tinted_rayleigh(), tinted);
+    }
+
+    #[test]
+    fn hue_keeps_the_total_scattering() {
+        let atmosphere = with_hue(1.0, 0.4, 0.2);
+        let total: f32 = atmosphere.tinted_rayleigh().iter().sum();
+        assert!((total - atmosphere.rayleigh.iter().sum::<f32>()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn hue_changes_the_color_of_the_sky() {
+        let red = with_hue(1.0, 0.5, 0.5);
+        let blue = with_hue(0.5, 0.5, 1.0);
+        let (red_rayleigh, blue_rayleigh) = (red.tinted_rayleigh(), blue.tinted_rayleigh());
+        assert!(red_rayleigh[0] > blue_rayleigh[0] && red_rayleigh[2] < blue_rayleigh[2]);
+
+        // The reddish atmosphere scatters more red light out of the ray, and so lets less of it through.
+        let (red_transmittance, blue_transmittance) = (grazing_transmittance(&red), grazing_transmittance(&blue));
+        assert!(red_transmittance.x < blue_transmittance.x);
+        assert!(red_transmittance.z > blue_transmittance.z);
+
+        // The sky which the lookup tables are built from is redder, since the reddish atmosphere scatters more red light towards the ground.
+        let (red_lut, blue_lut) = (lut::LutParameters::new(&red), lut::LutParameters::new(&blue));
+        assert_ne!(red_lut.key(), blue_lut.key());
+        let (red_sky, blue_sky) = (sky_radiance(&red), sky_radiance(&blue));
+        assert!(red_sky.x / red_sky.z > blue_sky.x / blue_sky.z);
+    }
+
+    #[test]
+    fn black_hue_falls_back_to_the_coefficients() {
+        let atmosphere = with_hue(0.0, 0.0, 0.0);
+        assert_eq!(atmosphere.tinted_rayleigh(), atmosphere.rayleigh);
+    }
+}
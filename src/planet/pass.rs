@@ -19,13 +19,14 @@ use amethyst::{
             mesh::{AsVertex, Position},
             shader::{Shader, SpirvShader},
         },
-        submodules::{FlatEnvironmentSub},
+        submodules::{FlatEnvironmentSub, TextureId, TextureSub},
         types::Backend, util,
     },
 };
 
 use crate::{
-    planet::sub::*,
+    planet::{lut::{AtmosphereLuts, AtmosphereLutSystem}, sub::*},
+    rings::sub::RingSub,
     star::sub::*,
 };
 
@@ -59,17 +60,32 @@ lazy_static::lazy_static! {
         ShaderStageFlags::FRAGMENT,
         "main",
     ).unwrap();
+
+    static ref LUT_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/atmosphere_lut.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
 }
 
 /// Draw triangles.
 #[derive(Clone, Debug, Default, PartialEq)]
-pub struct DrawAtmosphereDesc;
+pub struct DrawAtmosphereDesc {
+    /// Whether the precomputed lookup tables are used when they are available.
+    precomputed: bool,
+}
 
 impl DrawAtmosphereDesc {
     /// Create instance of `DrawAtmosphereDesc` render group
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Uses the precomputed lookup tables (from the `AtmosphereLuts` resource) instead of raymarching each pixel.
+    pub fn with_precomputed(mut self, precomputed: bool) -> Self {
+        self.precomputed = precomputed;
+        self
+    }
 }
 
 impl<B: Backend> RenderGroupDesc<B, World> for DrawAtmosphereDesc {
@@ -88,6 +104,10 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawAtmosphereDesc {
         let env = FlatEnvironmentSub::new(factory)?;
         let planets = PlanetSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
         let stars = StarSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        // The rings cast shadows on the atmospheres and the ground.
+        let rings = RingSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        // Planets and moons cast shadows on the atmospheres (including their own) and on the ground of other planets.
+        let occluders = OccluderSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
          // We need to generate the sphere mesh for the planet.
         let vertex = StaticVertexBuffer::new();
         let (pipeline, pipeline_layout) = build_custom_pipeline(
@@ -95,17 +115,37 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawAtmosphereDesc {
             subpass,
             framebuffer_width,
             framebuffer_height,
-            vec![env.raw_layout(), planets.raw_layout(), stars.raw_layout()],
+            vec![env.raw_layout(), planets.raw_layout(), stars.raw_layout(), rings.raw_layout(), occluders.raw_layout()],
+            None,
+            &FRAGMENT,
+        )?;
+
+        // This pipeline reads the scattering from the lookup tables, which is much cheaper than raymarching.
+        let lut = TextureSub::new(factory)?;
+        let (lut_pipeline, lut_pipeline_layout) = build_custom_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            vec![env.raw_layout(), planets.raw_layout(), stars.raw_layout(), lut.raw_layout(), rings.raw_layout(), occluders.raw_layout()],
             None,
+            &LUT_FRAGMENT,
         )?;
 
         Ok(Box::new(DrawAtmosphere::<B> {
             pipeline,
             pipeline_layout,
+            lut_pipeline,
+            lut_pipeline_layout,
             env,
             vertex,
             planets,
             stars,
+            rings,
+            occluders,
+            lut,
+            lut_id: None,
+            precomputed: self.precomputed,
         }))
     }
 }
@@ -119,6 +159,14 @@ pub struct DrawAtmosphere<B: Backend> {
     vertex: StaticVertexBuffer<B, Position>,
     planets: PlanetSub<B>,
     stars: StarSub<B>,
+    rings: RingSub<B>,
+    occluders: OccluderSub<B>,
+    lut_pipeline: B::GraphicsPipeline,
+    lut_pipeline_layout: B::PipelineLayout,
+    lut: TextureSub<B>,
+    /// The lookup table texture, if it is loaded and every planet has its tables in it.
+    lut_id: Option<TextureId>,
+    precomputed: bool,
 }
 
 impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
@@ -134,6 +182,23 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
         self.env.process(factory, index, world);
         self.planets.process(factory, index, world);
         self.stars.process(factory, index, world);
+        self.rings.process(factory, index, world);
+        self.occluders.process(factory, index, world);
+
+        // Fall back to raymarching until the lookup tables of every planet are ready.
+        self.lut_id = None;
+        if self.precomputed {
+            if let Some(luts) = world.try_fetch::<AtmosphereLuts>() {
+                if let Some(texture) = luts.texture() {
+                    if let Some((lut_id, _)) = self.lut.insert(factory, world, texture, hal::image::Layout::ShaderReadOnlyOptimal) {
+                        if self.lut.loaded(lut_id) && self.planets.has_luts() {
+                            self.lut_id = Some(lut_id);
+                        }
+                    }
+                }
+            }
+            self.lut.maintain(factory, world);
+        }
 
         self.vertex.prepare(
             factory,
@@ -153,11 +218,27 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
         _subpass: hal::pass::Subpass<'_, B>,
         _world: &World,
     ) {
-        if !self.planets.is_empty() {
+        if self.planets.is_empty() {
+            return;
+        }
+        if let Some(lut_id) = self.lut_id {
+            encoder.bind_graphics_pipeline(&self.lut_pipeline);
+            self.env.bind(index, &self.lut_pipeline_layout, 0, &mut encoder);
+            self.planets.bind(index, &self.lut_pipeline_layout, 1, &mut encoder);
+            self.stars.bind(index, &self.lut_pipeline_layout, 2, &mut encoder);
+            self.lut.bind(&self.lut_pipeline_layout, 3, lut_id, &mut encoder);
+            self.rings.bind(index, &self.lut_pipeline_layout, 4, &mut encoder);
+            self.occluders.bind(index, &self.lut_pipeline_layout, 5, &mut encoder);
+            unsafe {
+                self.vertex.draw(&mut encoder, 0..1, index);
+            }
+        } else {
             encoder.bind_graphics_pipeline(&self.pipeline);
             self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
             self.planets.bind(index, &self.pipeline_layout, 1, &mut encoder);
             self.stars.bind(index, &self.pipeline_layout, 2, &mut encoder);
+            self.rings.bind(index, &self.pipeline_layout, 3, &mut encoder);
+            self.occluders.bind(index, &self.pipeline_layout, 4, &mut encoder);
             unsafe {
                 self.vertex.draw(&mut encoder, 0..1, index);
             }
@@ -170,6 +251,10 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
             factory
                 .device()
                 .destroy_pipeline_layout(self.pipeline_layout);
+            factory.device().destroy_graphics_pipeline(self.lut_pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.lut_pipeline_layout);
         }
     }
 }
@@ -181,6 +266,7 @@ fn build_custom_pipeline<B: Backend>(
     framebuffer_height: u32,
     layouts: Vec<&B::DescriptorSetLayout>,
     push_constant: Option<(hal::pso::ShaderStageFlags, Range<u32>)>,
+    fragment: &SpirvShader,
 ) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
     let pipeline_layout = unsafe {
         factory
@@ -189,7 +275,7 @@ fn build_custom_pipeline<B: Backend>(
     }?;
     // Load the shaders
     let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { FRAGMENT.module(factory).unwrap() };
+    let shader_fragment = unsafe { fragment.module(factory).unwrap() };
 
     // Build the pipeline
     let pipes = PipelinesBuilder::new()
@@ -233,20 +319,49 @@ fn build_custom_pipeline<B: Backend>(
 }
 
 /// A [RenderPlugin] for our custom plugin
-#[derive(Debug, Default)]
-pub struct AtmosphereRender;
+#[derive(Debug)]
+pub struct AtmosphereRender {
+    precomputed: bool,
+}
+
+impl Default for AtmosphereRender {
+    fn default() -> Self {
+        Self { precomputed: true }
+    }
+}
+
+impl AtmosphereRender {
+    /// Creates the atmosphere plugin, which uses precomputed lookup tables for the scattering.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Chooses between the precomputed lookup tables and raymarching every pixel.
+    /// The lookup tables include multiple scattering, which brightens the sky and its twilight.
+    /// Raymarching only includes single scattering, but it doesn't need to recompute the tables when an atmosphere changes.
+    pub fn with_precomputed(mut self, precomputed: bool) -> Self {
+        self.precomputed = precomputed;
+        self
+    }
+}
 
 impl<B: Backend> RenderPlugin<B> for AtmosphereRender {
     fn on_build<'a, 'b>(
         &mut self,
         world: &mut World,
-        _builder: &mut DispatcherBuilder<'a, 'b>,
+        builder: &mut DispatcherBuilder<'a, 'b>,
     ) -> Result<(), Error> {
         // Add the required components to the world ECS
         // We need to move the object out of the option to obtain it validly.
         world.register::<crate::Planet>();
         world.register::<crate::Atmosphere>();
+        world.register::<crate::Rings>();
         world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        if self.precomputed {
+            world.insert(AtmosphereLuts::default());
+            builder.add(AtmosphereLutSystem::default(), "atmosphere_lut_system", &[]);
+        }
         Ok(())
     }
 
@@ -256,9 +371,10 @@ impl<B: Backend> RenderPlugin<B> for AtmosphereRender {
         _factory: &mut Factory<B>,
         _world: &World,
     ) -> Result<(), Error> {
-        plan.extend_target(Target::Main, |ctx| {
+        let precomputed = self.precomputed;
+        plan.extend_target(Target::Main, move |ctx| {
             // Add our Description
-            ctx.add(RenderOrder::LinearPostEffects, DrawAtmosphereDesc::new().builder())?;
+            ctx.add(RenderOrder::LinearPostEffects, DrawAtmosphereDesc::new().with_precomputed(precomputed).builder())?;
             Ok(())
         });
         Ok(())
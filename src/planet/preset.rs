@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+
+use amethyst::{
+    assets::{Asset, AssetStorage, Handle, Processor},
+    core::bundle::SystemBundle,
+    ecs::prelude::*,
+    error::Error,
+    prelude::WorldExt,
+    renderer::palette::Srgb,
+};
+
+use serde::{Serialize, Deserialize};
+
+use super::{Atmosphere, Planet};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// The physical description of a planet's atmosphere, which is converted into an `Atmosphere` for a planet of any size.
+/// Every distance is in kilometres and every coefficient is per kilometre, at the base of the atmosphere,
+/// so values can be taken straight from the literature.
+///
+/// Presets can be loaded from RON files (see `assets/atmospheres`) as an Amethyst asset with the `RonFormat`.
+/// Put the `Handle<AtmospherePreset>` on a planet entity and the `AtmospherePresetBundle` gives it the matching `Atmosphere`.
+pub struct AtmospherePreset {
+    /// The radius of the planet (the level of 1 bar for the gas giants).
+    pub planet_radius: f32,
+
+    /// The distance from the base of the atmosphere to its top.
+    pub thickness: f32,
+
+    /// The Rayleigh scattering coefficients (red, green, blue) at the base of the atmosphere.
+    pub rayleigh: [f32; 3],
+
+    /// The height at which the density of the Rayleigh scattering gas falls by a factor of e.
+    pub rayleigh_scale_height: f32,
+
+    /// The Mie scattering coefficient of the dust, haze or cloud droplets at the base of the atmosphere.
+    pub mie: f32,
+
+    /// The ratio of Mie extinction to Mie scattering.
+    pub mie_extinction: f32,
+
+    /// The anisotropy of Mie scattering, from -1.0 (all forward scattering) to 1.0 (all back scattering).
+    pub mie_anisotropy: f32,
+
+    /// The height at which the density of the Mie scattering particles falls by a factor of e.
+    pub mie_scale_height: f32,
+
+    /// The absorption coefficients (red, green, blue) of the densest part of the absorbing layer.
+    #[serde(default)]
+    pub absorption: [f32; 3],
+
+    /// The altitude of the densest part of the absorbing layer.
+    #[serde(default)]
+    pub absorption_altitude: f32,
+
+    /// The distance from the densest part of the absorbing layer to where it ends.
+    /// Zero (the default) spreads the layer over the whole thickness of the atmosphere.
+    #[serde(default)]
+    pub absorption_width: f32,
+
+    /// The hue of the atmosphere, which tints the Rayleigh scattering. The presets are white, since their color comes from the coefficients.
+    #[serde(with = "amethyst::renderer::serde_shim::srgb", default = "default_hue")]
+    pub hue: Srgb,
+
+    /// The brightness of the scattered light.
+    #[serde(default = "default_density")]
+    pub density: f32,
+}
+
+fn default_hue() -> Srgb {
+    Srgb::new(1.0, 1.0, 1.0)
+}
+
+fn default_density() -> f32 {
+    1.0
+}
+
+impl AtmospherePreset {
+    /// The earth, with the nitrogen and oxygen, aerosols and ozone of the reference model of Bruneton (2017).
+    pub fn earth() -> Self {
+        Self {
+            planet_radius: 6360.0,
+            thickness: 60.0,
+            rayleigh: [5.802e-3, 13.558e-3, 33.1e-3],
+            rayleigh_scale_height: 8.0,
+            mie: 3.996e-3,
+            mie_extinction: 1.11,
+            mie_anisotropy: -0.8,
+            mie_scale_height: 1.2,
+            absorption: [0.65e-3, 1.881e-3, 0.085e-3],
+            absorption_altitude: 25.0,
+            absorption_width: 15.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Mars, with a thin carbon dioxide atmosphere (6.1 mbar at 210 K) and well mixed dust with an optical depth of 0.5.
+    /// The dust absorbs blue light, which turns the sky the color of butterscotch.
+    pub fn mars() -> Self {
+        Self {
+            planet_radius: 3389.5,
+            thickness: 100.0,
+            // Carbon dioxide scatters about 2.5 times as much as air per molecule, at 0.8% of the earth's surface density.
+            rayleigh: [0.120e-3, 0.280e-3, 0.683e-3],
+            rayleigh_scale_height: 11.1,
+            mie: 45.0e-3,
+            mie_extinction: 1.0,
+            mie_anisotropy: -0.65,
+            mie_scale_height: 11.1,
+            // The single scattering albedo of the dust falls from 0.97 in red light to 0.65 in blue light.
+            absorption: [1.36e-3, 6.82e-3, 15.9e-3],
+            absorption_altitude: 0.0,
+            absorption_width: 22.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Venus, with a carbon dioxide atmosphere of 92 bar at 737 K under sulfuric acid clouds with an optical depth of 30.
+    /// An unknown absorber near the cloud tops takes out the blue light, which makes the planet pale yellow.
+    pub fn venus() -> Self {
+        Self {
+            planet_radius: 6051.8,
+            thickness: 150.0,
+            rayleigh: [0.515, 1.203, 2.936],
+            rayleigh_scale_height: 15.9,
+            mie: 1.9,
+            mie_extinction: 1.0,
+            mie_anisotropy: -0.7,
+            mie_scale_height: 15.9,
+            absorption: [2.0e-3, 10.0e-3, 50.0e-3],
+            absorption_altitude: 65.0,
+            absorption_width: 10.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Titan, with a nitrogen atmosphere of 1.47 bar at 94 K under an orange haze of organic particles reaching 300 km.
+    pub fn titan() -> Self {
+        Self {
+            planet_radius: 2574.7,
+            thickness: 600.0,
+            // Nitrogen scatters about as much as air per molecule, at 4.45 times the earth's surface density.
+            rayleigh: [25.8e-3, 60.3e-3, 147.3e-3],
+            rayleigh_scale_height: 20.0,
+            mie: 46.0e-3,
+            mie_extinction: 1.0,
+            mie_anisotropy: -0.65,
+            mie_scale_height: 65.0,
+            // The haze absorbs strongly in blue light, with optical depths of 0.3, 1.0 and 2.5.
+            absorption: [2.0e-3, 6.67e-3, 16.7e-3],
+            absorption_altitude: 150.0,
+            absorption_width: 150.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Jupiter above the 1 bar level, with hydrogen and helium at 165 K and a thin haze tinted by brown chromophores.
+    pub fn jupiter() -> Self {
+        Self {
+            planet_radius: 71492.0,
+            thickness: 300.0,
+            // Hydrogen scatters about a fifth as much as air per molecule.
+            rayleigh: [1.72e-3, 4.0e-3, 9.81e-3],
+            rayleigh_scale_height: 27.0,
+            mie: 18.5e-3,
+            mie_extinction: 1.1,
+            mie_anisotropy: -0.7,
+            mie_scale_height: 27.0,
+            absorption: [0.67e-3, 2.67e-3, 6.67e-3],
+            absorption_altitude: 0.0,
+            absorption_width: 60.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Saturn above the 1 bar level, with hydrogen and helium at 134 K and a thick yellow haze.
+    pub fn saturn() -> Self {
+        Self {
+            planet_radius: 60268.0,
+            thickness: 600.0,
+            rayleigh: [2.36e-3, 5.5e-3, 13.5e-3],
+            rayleigh_scale_height: 59.5,
+            mie: 25.0e-3,
+            mie_extinction: 1.1,
+            mie_anisotropy: -0.7,
+            mie_scale_height: 60.0,
+            absorption: [0.5e-3, 1.67e-3, 4.17e-3],
+            absorption_altitude: 0.0,
+            absorption_width: 120.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Uranus above the 1 bar level, with hydrogen, helium and 2.3% methane at 76 K.
+    /// The methane absorbs red light, which leaves the planet cyan.
+    pub fn uranus() -> Self {
+        Self {
+            planet_radius: 25559.0,
+            thickness: 300.0,
+            rayleigh: [3.6e-3, 8.4e-3, 20.6e-3],
+            rayleigh_scale_height: 27.7,
+            mie: 7.2e-3,
+            mie_extinction: 1.0,
+            mie_anisotropy: -0.6,
+            mie_scale_height: 27.7,
+            absorption: [48.2e-3, 7.23e-3, 1.2e-3],
+            absorption_altitude: 0.0,
+            absorption_width: 83.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Neptune above the 1 bar level, with hydrogen, helium and 1.5% methane at 72 K.
+    /// The methane absorbs red light, and the thinner haze leaves the planet a deeper blue than Uranus.
+    pub fn neptune() -> Self {
+        Self {
+            planet_radius: 24764.0,
+            thickness: 250.0,
+            rayleigh: [3.65e-3, 8.5e-3, 20.8e-3],
+            rayleigh_scale_height: 20.0,
+            mie: 10.0e-3,
+            mie_extinction: 1.0,
+            mie_anisotropy: -0.6,
+            mie_scale_height: 20.0,
+            absorption: [83.3e-3, 11.7e-3, 1.67e-3],
+            absorption_altitude: 0.0,
+            absorption_width: 60.0,
+            hue: default_hue(),
+            density: default_density(),
+        }
+    }
+
+    /// Converts the preset into an `Atmosphere` for a planet with the specified radius in world units.
+    /// The coefficients are rescaled to the planet's radius and the heights to the thickness of the atmosphere,
+    /// so the optical depths (and so the colors) are the same as those of the real planet.
+    pub fn atmosphere(&self, base_planet_radius: f32) -> Atmosphere {
+        let radius = self.planet_radius.max(1e-3);
+        let thickness = self.thickness.max(1e-3);
+        let height = 1.0 + thickness / radius;
+        // The renderer multiplies the scale heights by 85% of the atmosphere's radius.
+        let rf = height * 0.85;
+        let scale = |coefficients: [f32; 3]| [coefficients[0] * radius, coefficients[1] * radius, coefficients[2] * radius];
+        let mut atmosphere = Atmosphere::new(height, self.hue, self.density, base_planet_radius)
+            .with_rayleigh(scale(self.rayleigh))
+            .with_mie(self.mie * radius, self.mie_extinction, self.mie_anisotropy)
+            .with_scale_heights(self.rayleigh_scale_height / radius / rf, self.mie_scale_height / radius / rf);
+        if self.absorption.iter().any(|&absorption| absorption > 0.0) {
+            let width = if self.absorption_width > 0.0 { self.absorption_width } else { thickness };
+            atmosphere = atmosphere.with_absorption(scale(self.absorption), self.absorption_altitude / thickness, width / thickness);
+        }
+        atmosphere
+    }
+}
+
+impl Asset for AtmospherePreset {
+    const NAME: &'static str = "space_render::AtmospherePreset";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+impl Atmosphere {
+    /// The atmosphere of the earth, for a planet with a radius of 1.0 (see `AtmospherePreset::earth`).
+    pub fn earth() -> Self {
+        AtmospherePreset::earth().atmosphere(1.0)
+    }
+
+    /// The atmosphere of Mars, for a planet with a radius of 1.0 (see `AtmospherePreset::mars`).
+    pub fn mars() -> Self {
+        AtmospherePreset::mars().atmosphere(1.0)
+    }
+
+    /// The atmosphere of Venus, for a planet with a radius of 1.0 (see `AtmospherePreset::venus`).
+    pub fn venus() -> Self {
+        AtmospherePreset::venus().atmosphere(1.0)
+    }
+
+    /// The atmosphere of Titan, for a moon with a radius of 1.0 (see `AtmospherePreset::titan`).
+    pub fn titan() -> Self {
+        AtmospherePreset::titan().atmosphere(1.0)
+    }
+
+    /// The atmosphere of Jupiter, for a planet with a radius of 1.0 (see `AtmospherePreset::jupiter`).
+    pub fn jupiter() -> Self {
+        AtmospherePreset::jupiter().atmosphere(1.0)
+    }
+
+    /// The atmosphere of Saturn, for a planet with a radius of 1.0 (see `AtmospherePreset::saturn`).
+    pub fn saturn() -> Self {
+        AtmospherePreset::saturn().atmosphere(1.0)
+    }
+
+    /// The atmosphere of Uranus, for a planet with a radius of 1.0 (see `AtmospherePreset::uranus`).
+    pub fn uranus() -> Self {
+        AtmospherePreset::uranus().atmosphere(1.0)
+    }
+
+    /// The atmosphere of Neptune, for a planet with a radius of 1.0 (see `AtmospherePreset::neptune`).
+    pub fn neptune() -> Self {
+        AtmospherePreset::neptune().atmosphere(1.0)
+    }
+}
+
+/// Gives every entity with a loaded `Handle<AtmospherePreset>` the matching `Atmosphere`, sized to its `Planet`.
+/// The atmosphere is only replaced when the preset (or the planet's radius) changes, so it can be tweaked after it has been applied.
+#[derive(Debug, Default)]
+pub struct AtmospherePresetSystem {
+    /// The preset and radius which each atmosphere was made from.
+    applied: HashMap<Entity, (AtmospherePreset, f32)>,
+}
+
+impl<'a> System<'a> for AtmospherePresetSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Handle<AtmospherePreset>>,
+        ReadStorage<'a, Planet>,
+        WriteStorage<'a, Atmosphere>,
+        Read<'a, AssetStorage<AtmospherePreset>>,
+    );
+
+    fn run(&mut self, (entities, handles, planets, mut atmospheres, presets): Self::SystemData) {
+        self.applied.retain(|entity, _| entities.is_alive(*entity) && handles.contains(*entity));
+
+        for (entity, handle, planet) in (&entities, &handles, planets.maybe()).join() {
+            let preset = match presets.get(handle) {
+                Some(preset) => *preset,
+                None => continue,
+            };
+            // The planet decides the size of the atmosphere, falling back to the size of the atmosphere it already has.
+            let radius = match (planet, atmospheres.get(entity)) {
+                (Some(planet), _) => planet.radius,
+                (None, Some(atmosphere)) => atmosphere.base_planet_radius,
+                (None, None) => 1.0,
+            };
+            if self.applied.get(&entity) == Some(&(preset, radius)) {
+                continue;
+            }
+            atmospheres.insert(entity, preset.atmosphere(radius)).expect("Failed to add atmosphere from preset!");
+            self.applied.insert(entity, (preset, radius));
+        }
+    }
+}
+
+/// Adds the processor which loads `AtmospherePreset` assets and the `AtmospherePresetSystem` to the dispatcher.
+#[derive(Debug, Default)]
+pub struct AtmospherePresetBundle;
+
+impl AtmospherePresetBundle {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for AtmospherePresetBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        world.register::<Atmosphere>();
+        world.register::<Handle<AtmospherePreset>>();
+        builder.add(Processor::<AtmospherePreset>::new(), "atmosphere_preset_processor", &[]);
+        builder.add(AtmospherePresetSystem::default(), "atmosphere_preset_system", &["atmosphere_preset_processor"]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::assets::{Format, RonFormat};
+
+    use super::*;
+
+    fn parse(ron: &str) -> AtmospherePreset {
+        RonFormat.import_simple(ron.as_bytes().to_vec()).expect("Failed to parse atmosphere preset!")
+    }
+
+    #[test]
+    fn files_match_the_presets() {
+        let presets = [
+            ("earth", AtmospherePreset::earth()),
+            ("mars", AtmospherePreset::mars()),
+            ("venus", AtmospherePreset::venus()),
+            ("titan", AtmospherePreset::titan()),
+            ("jupiter", AtmospherePreset::jupiter()),
+            ("saturn", AtmospherePreset::saturn()),
+            ("uranus", AtmospherePreset::uranus()),
+            ("neptune", AtmospherePreset::neptune()),
+        ];
+        let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/atmospheres");
+        assert_eq!(std::fs::read_dir(&directory).expect("Failed to read presets!").count(), presets.len());
+        for (name, preset) in presets.iter() {
+            let ron = std::fs::read_to_string(directory.join(format!("{}.ron", name))).expect("Failed to read preset!");
+            assert_eq!(parse(&ron), *preset, "{}.ron", name);
+        }
+    }
+
+    #[test]
+    fn absorption_without_a_width_fills_the_atmosphere() {
+        let preset = parse("(
+            planet_radius: 1000.0,
+            thickness: 50.0,
+            rayleigh: (0.001, 0.002, 0.004),
+            rayleigh_scale_height: 10.0,
+            mie: 0.001,
+            mie_extinction: 1.1,
+            mie_anisotropy: -0.7,
+            mie_scale_height: 5.0,
+            absorption: (0.001, 0.0, 0.0),
+        )");
+        let atmosphere = preset.atmosphere(1.0);
+        assert_eq!((atmosphere.absorption_altitude, atmosphere.absorption_width), (0.0, 1.0));
+        let (center, width) = atmosphere.absorption_layer();
+        assert_eq!(center, 1.0);
+        assert!((width - 0.05).abs() < 1e-6);
+
+        // Without absorption there is no layer at all.
+        let clear = AtmospherePreset { absorption: [0.0; 3], ..preset }.atmosphere(1.0);
+        assert_eq!(clear.absorption, [0.0; 3]);
+    }
+}
@@ -0,0 +1,195 @@
+use amethyst::{
+    core::{
+        math::Vector3,
+        transform::Transform,
+    },
+    ecs::prelude::*,
+};
+
+use super::Atmosphere;
+use crate::renderutils::uniform_scale;
+
+/// The number of samples used to integrate the optical depth along a ray.
+const NUM_SAMPLES: usize = 16;
+
+/// A planet's atmosphere in world space, which attenuates light using a CPU version of the scattering model in `scattering.glsl`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ScatteringSphere {
+    /// The center of the planet.
+    pub center: Vector3<f32>,
+
+    /// The radius of the planet's surface.
+    pub radius: f32,
+
+    /// The radius of the top of the atmosphere.
+    pub atmosphere_radius: f32,
+
+    /// The Rayleigh scattering coefficients for a planet with a radius of 1.0, after they have been tinted by the hue.
+    pub rayleigh: [f32; 3],
+
+    /// The Mie scattering coefficient for a planet with a radius of 1.0.
+    pub mie: f32,
+
+    /// The ratio of Mie extinction to Mie scattering.
+    pub mie_extinction: f32,
+
+    /// The Rayleigh scale height relative to the atmosphere's height.
+    pub rayleigh_scale_height: f32,
+
+    /// The Mie scale height relative to the atmosphere's height.
+    pub mie_scale_height: f32,
+
+    /// The absorption coefficients of the absorbing layer for a planet with a radius of 1.0.
+    pub absorption: [f32; 3],
+
+    /// The radius of the densest part of the absorbing layer, for a planet with a radius of 1.0.
+    pub absorption_center: f32,
+
+    /// The distance over which the density of the absorbing layer falls to zero, for a planet with a radius of 1.0.
+    pub absorption_width: f32,
+}
+
+impl ScatteringSphere {
+    /// Creates the scattering sphere of an `Atmosphere` with the specified transform.
+    pub fn new(atmosphere: &Atmosphere, transform: &Transform) -> Self {
+        let matrix = transform.global_matrix();
+        let radius = atmosphere.base_planet_radius * uniform_scale(matrix);
+        let (absorption_center, absorption_width) = atmosphere.absorption_layer();
+        Self {
+            center: matrix.column(3).xyz(),
+            radius,
+            atmosphere_radius: radius * atmosphere.height(),
+            rayleigh: atmosphere.tinted_rayleigh(),
+            mie: atmosphere.mie,
+            mie_extinction: atmosphere.mie_extinction,
+            rayleigh_scale_height: atmosphere.rayleigh_scale_height,
+            mie_scale_height: atmosphere.mie_scale_height,
+            absorption: atmosphere.absorption,
+            absorption_center,
+            absorption_width,
+        }
+    }
+
+    /// Collects the scattering spheres of every `Atmosphere` in the world.
+    pub fn gather(world: &World) -> Vec<Self> {
+        (&world.read_storage::<Atmosphere>(), &world.read_storage::<Transform>())
+            .join()
+            .map(|(atmosphere, transform)| Self::new(atmosphere, transform))
+            .collect()
+    }
+
+    /// Calculates the fraction of light (red, green, blue) which passes through the atmosphere
+    /// along the ray from `origin` in the specified (normalized) direction, up to the distance `max_t`.
+    /// A ray which hits the planet's surface before `max_t` is fully blocked.
+    pub fn transmittance(&self, origin: Vector3<f32>, direction: Vector3<f32>, max_t: f32) -> Vector3<f32> {
+        // Everything is scaled down to a planet radius of 1.0, like in the shader.
+        let scale = 1.0 / self.radius;
+        let ar = self.atmosphere_radius * scale;
+        let o = (origin - self.center) * scale;
+        let max_t = max_t * scale;
+
+        let (near, far) = match ray_vs_sphere(o, direction, ar) {
+            Some((near, far)) if far > 0.0 && near < max_t => (near.max(0.0), far.min(max_t)),
+            _ => return Vector3::new(1.0, 1.0, 1.0),
+        };
+        if let Some((ground, _)) = ray_vs_sphere(o, direction, 1.0) {
+            if ground > 0.0 && ground < max_t {
+                return Vector3::zeros();
+            }
+        }
+
+        let (ph_ray, ph_mie) = self.scale_heights(ar);
+        let step = (far - near) / NUM_SAMPLES as f32;
+        let mut n_ray = 0.0;
+        let mut n_mie = 0.0;
+        let mut n_abs = 0.0;
+        for i in 0..NUM_SAMPLES {
+            let p = o + direction * (near + step * (i as f32 + 0.5));
+            n_ray += density(p, ph_ray);
+            n_mie += density(p, ph_mie);
+            n_abs += absorption_density(p, self.absorption_center, self.absorption_width);
+        }
+        n_ray *= step;
+        n_mie *= step;
+        n_abs *= step;
+
+        let mie = n_mie * self.mie * self.mie_extinction;
+        Vector3::new(
+            (-(n_ray * self.rayleigh[0] + mie + n_abs * self.absorption[0])).exp(),
+            (-(n_ray * self.rayleigh[1] + mie + n_abs * self.absorption[1])).exp(),
+            (-(n_ray * self.rayleigh[2] + mie + n_abs * self.absorption[2])).exp(),
+        )
+    }
+
+    /// Gets the Rayleigh and Mie scale heights for the relative atmosphere radius, in the same way as the shader.
+    fn scale_heights(&self, ar: f32) -> (f32, f32) {
+        let rf = ar * 0.85;
+        (self.rayleigh_scale_height * rf, self.mie_scale_height * rf)
+    }
+}
+
+/// Calculates the transmittance of the light travelling from a star to the observer, through every atmosphere between them.
+pub fn star_transmittance(observer: Vector3<f32>, star_center: Vector3<f32>, spheres: &[ScatteringSphere]) -> Vector3<f32> {
+    let offset = star_center - observer;
+    let distance = offset.norm();
+    if distance <= 0.0 {
+        return Vector3::new(1.0, 1.0, 1.0);
+    }
+    let direction = offset / distance;
+    spheres
+        .iter()
+        .fold(Vector3::new(1.0, 1.0, 1.0), |total, sphere| total.component_mul(&sphere.transmittance(observer, direction, distance)))
+}
+
+fn density(p: Vector3<f32>, ph: f32) -> f32 {
+    (-(p.norm() - 1.0).max(0.0) / ph).exp()
+}
+
+/// The density of the absorbing layer, which peaks at the `center` radius and falls to zero `width` above and below it.
+fn absorption_density(p: Vector3<f32>, center: f32, width: f32) -> f32 {
+    (1.0 - (p.norm() - center).abs() / width).max(0.0)
+}
+
+/// Gets the distances along the ray to where it enters and leaves the sphere, if it hits the sphere at all.
+fn ray_vs_sphere(p: Vector3<f32>, dir: Vector3<f32>, r: f32) -> Option<(f32, f32)> {
+    let b = p.dot(&dir);
+    let c = p.dot(&p) - r * r;
+    let d = b * b - c;
+    if d < 0.0 {
+        return None;
+    }
+    let d = d.sqrt();
+    Some((-b - d, -b + d))
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::renderer::palette::Srgb;
+
+    use super::*;
+
+    fn sphere_at(x: f32, y: f32) -> ScatteringSphere {
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(x, y, 0.0);
+        transform.copy_local_to_global();
+        ScatteringSphere::new(&Atmosphere::new(1.1, Srgb::new(1.0, 1.0, 1.0), 1.0, 1.0), &transform)
+    }
+
+    #[test]
+    fn planets_beyond_the_star_dont_dim_it() {
+        let star = Vector3::new(10.0, 0.0, 0.0);
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        // A planet behind the star on the same line of sight doesn't block it.
+        assert_eq!(star_transmittance(Vector3::zeros(), star, &[sphere_at(20.0, 0.0)]), white);
+
+        // The line of sight only passes through the atmosphere of this planet, which reddens the light unless the planet is beyond the star.
+        let grazing = sphere_at(12.0, 1.05);
+        let through = grazing.transmittance(Vector3::zeros(), Vector3::x(), f32::INFINITY);
+        assert!(through.z < through.x && through.x < 1.0);
+        assert_eq!(star_transmittance(Vector3::zeros(), star, &[grazing]), white);
+        assert_eq!(star_transmittance(Vector3::zeros(), star * 2.0, &[grazing]), through);
+
+        // A planet in front of the star still blocks it.
+        assert_eq!(star_transmittance(Vector3::zeros(), star, &[sphere_at(5.0, 0.0)]), Vector3::zeros());
+    }
+}
@@ -19,34 +19,54 @@ use amethyst::{
 };
 
 use super::*;
+use crate::renderutils::uniform_scale;
+
+/// The index of an entity which has no `Atmosphere`, which is past the end of every list of planets.
+pub(crate) const NO_PLANET: u32 = u32::MAX;
+
+/// Finds the index of the atmosphere of `entity` in the list of planets written by `PlanetSub`, so that other uniforms can refer to it.
+/// This is `NO_PLANET` if the entity doesn't have an `Atmosphere`.
+pub(crate) fn planet_index(world: &World, entity: Entity) -> u32 {
+    (&world.entities(), &world.read_storage::<Atmosphere>(), &world.read_storage::<Transform>())
+        .join()
+        .position(|(planet, _, _)| planet == entity)
+        .map_or(NO_PLANET, |index| index as u32)
+}
 
 #[derive(Debug)]
 pub(crate) struct PlanetSub<B: Backend> {
     uniform: DynamicUniform<B, PlanetList>,
     data: PlanetList,
+    /// Whether the lookup tables of every planet have a layer in the `AtmosphereLuts` texture.
+    has_luts: bool,
 }
 
 impl<B: Backend> PlanetSub<B> {
     pub fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
         let uniform = DynamicUniform::new(factory, flags)?;
-        Ok(Self { uniform, data: PlanetList::default() })
+        Ok(Self { uniform, data: PlanetList::default(), has_luts: false })
     }
 
     pub fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
         let mut planet_list: Vec<PlanetData> = Vec::new();
+        let luts = world.try_fetch::<lut::AtmosphereLuts>();
+        let mut has_luts = luts.is_some();
         for (atmosphere, transform) in (&world.read_storage::<Atmosphere>(), &world.read_storage::<Transform>()).join() {
             let matrix: Matrix4<f32> = *transform.global_matrix();
             let translation: Vector4<f32> = matrix.column(3).into();
-            let uniform_scale: f32 = matrix.row(0)[0].abs();
+            let scale = uniform_scale(&matrix);
            // if matrix.column(0)[0].abs() == matrix.column(1)[1].abs() && matrix.column(1)[1].abs() == matrix.column(2)[2].abs() {
                 // The scale is uniform - this is good.
 
-            planet_list.push(PlanetData::new(&atmosphere,translation.xyz(), atmosphere.base_planet_radius * uniform_scale));
+            let lut_layer = luts.as_ref().and_then(|luts| luts.layer(atmosphere));
+            has_luts &= lut_layer.is_some();
+            planet_list.push(PlanetData::new(atmosphere, translation.xyz(), atmosphere.base_planet_radius * scale, lut_layer.unwrap_or(0)));
            // } else {
                 // The scale is non uniform, which means that we cannot extract a radius for the planet.
                //panic!("Non uniform scale provided for planet! We need a uniform scale (x, y, z components of scale are the same) to determine the radius of the planet, as it is spherical.");
             //}
         }
+        self.has_luts = has_luts;
         self.data = PlanetList::new(planet_list.as_slice());
         self.uniform.write(factory, index, self.data.std140());
     }
@@ -60,6 +80,11 @@ impl<B: Backend> PlanetSub<B> {
         self.uniform.bind(index,  pipeline_layout, binding_id, encoder);
     }
 
+    /// Whether every planet has a layer in the `AtmosphereLuts` texture, so the precomputed tables can be used.
+    pub fn has_luts(&self) -> bool {
+        self.has_luts
+    }
+
     pub fn is_empty(&self) -> bool {
         self.data.count == 0
     }
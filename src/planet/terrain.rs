@@ -0,0 +1,452 @@
+use amethyst::{
+    assets::PrefabData,
+    derive::PrefabData,
+    ecs::prelude::*,
+    error::Error,
+    core::math::Vector3,
+    renderer::palette::Srgb,
+};
+
+use serde::{Serialize, Deserialize};
+
+use crate::renderutils::unit_hash;
+
+/// The width (in degrees) over which neighbouring latitude bands of biomes are blended.
+const LATITUDE_BLEND: f32 = 5.0;
+
+/// How far (in degrees) the edges of the latitude bands are moved by noise, so that they aren't perfect circles.
+const LATITUDE_WOBBLE: f32 = 6.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// A layer of fractal noise, which is made up of several octaves of gradient noise.
+pub struct NoiseLayer {
+    /// The frequency of the first octave, on a planet with a radius of 1.0.
+    pub frequency: f32,
+
+    /// The largest height of the layer, relative to the planet's radius.
+    pub amplitude: f32,
+
+    /// The number of octaves.
+    pub octaves: u32,
+
+    /// The factor which the frequency is multiplied by for each octave.
+    pub lacunarity: f32,
+
+    /// The factor which the amplitude is multiplied by for each octave.
+    pub gain: f32,
+}
+
+impl NoiseLayer {
+    /// Create a new noise layer with the usual lacunarity (2.0) and gain (0.5).
+    pub fn new(frequency: f32, amplitude: f32, octaves: u32) -> Self {
+        Self { frequency, amplitude, octaves, lacunarity: 2.0, gain: 0.5 }
+    }
+
+    /// Sums the octaves of noise, which gives a value from about -1.0 to 1.0.
+    pub fn fractal(&self, p: Vector3<f32>, seed: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        for octave in 0..self.octaves {
+            sum += gradient_noise(p * frequency, seed.wrapping_add(octave)) * amplitude;
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        if total > 0.0 { sum / total } else { 0.0 }
+    }
+
+    /// Sums octaves of ridged noise, which has sharp crests like mountain ranges, giving a value from 0.0 to 1.0.
+    /// Each octave is weighted by the one before it, so that the detail collects along the ridges.
+    pub fn ridged(&self, p: Vector3<f32>, seed: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut weight = 1.0;
+        for octave in 0..self.octaves {
+            let ridge = 1.0 - gradient_noise(p * frequency, seed.wrapping_add(octave)).abs();
+            let ridge = ridge * ridge * weight;
+            weight = ridge.clamp(0.0, 1.0);
+            sum += ridge * amplitude;
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        if total > 0.0 { sum / total } else { 0.0 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// Impact craters, which are scattered randomly over the surface.
+pub struct Craters {
+    /// The number of cells (where each cell may hold a crater) across a planet with a radius of 1.0.
+    pub frequency: f32,
+
+    /// The fraction of cells which hold a crater.
+    pub coverage: f32,
+
+    /// The largest radius of a crater, relative to the size of a cell (up to 0.5).
+    pub radius: f32,
+
+    /// The depth of the largest crater, relative to the planet's radius.
+    pub depth: f32,
+
+    /// The height of the rim around a crater, relative to its depth.
+    pub rim: f32,
+}
+
+impl Craters {
+    /// Create a new layer of craters.
+    pub fn new(frequency: f32, coverage: f32, depth: f32) -> Self {
+        Self { frequency, coverage, radius: 0.4, depth, rim: 0.3 }
+    }
+
+    /// Gets the change in height caused by the craters at the point on a planet with a radius of 1.0.
+    pub fn height(&self, p: Vector3<f32>, seed: u32) -> f32 {
+        let p = p * self.frequency;
+        let cell = p.map(f32::floor);
+        let mut height = 0.0;
+        // A crater can reach into the neighbouring cells, so every cell around the point is checked.
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let (x, y, z) = (cell.x as i32 + dx, cell.y as i32 + dy, cell.z as i32 + dz);
+                    if lattice_hash(x, y, z, seed, 0) > self.coverage {
+                        continue;
+                    }
+                    let center = Vector3::new(
+                        x as f32 + lattice_hash(x, y, z, seed, 1),
+                        y as f32 + lattice_hash(x, y, z, seed, 2),
+                        z as f32 + lattice_hash(x, y, z, seed, 3),
+                    );
+                    // Smaller craters are more common than large ones.
+                    let size = lattice_hash(x, y, z, seed, 4);
+                    let radius = self.radius * (0.25 + 0.75 * size * size);
+                    let d = (p - center).norm() / radius;
+                    let depth = self.depth * radius / self.radius;
+                    height += crater_profile(d, self.rim) * depth;
+                }
+            }
+        }
+        height
+    }
+}
+
+/// The shape of a crater, where `d` is the distance from its center relative to its radius.
+/// This is a bowl inside the crater, with a raised rim which falls away outside it.
+fn crater_profile(d: f32, rim: f32) -> f32 {
+    if d < 1.0 {
+        let bowl = d * d - 1.0;
+        bowl + rim * smoothstep(0.7, 1.0, d)
+    } else {
+        let falloff = 1.0 - smoothstep(1.0, 1.6, d);
+        rim * falloff * falloff
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// A rule which colors the terrain between two altitudes and two latitudes.
+pub struct Biome {
+    /// The color of the ground in the biome.
+    #[serde(with = "amethyst::renderer::serde_shim::srgb")]
+    pub color: Srgb,
+
+    /// The lowest and highest altitude of the biome, relative to the sea level (as a fraction of the planet's radius).
+    pub altitude: (f32, f32),
+
+    /// The lowest and highest latitude of the biome in degrees, where 0.0 is the equator and 90.0 is either pole.
+    pub latitude: (f32, f32),
+}
+
+impl Biome {
+    /// Create a new biome which covers the specified range of altitudes at every latitude.
+    pub fn new(color: Srgb, min_altitude: f32, max_altitude: f32) -> Self {
+        Self { color, altitude: (min_altitude, max_altitude), latitude: (0.0, 90.0) }
+    }
+
+    /// Limits the biome to the specified range of latitudes (in degrees).
+    pub fn with_latitude(mut self, min_latitude: f32, max_latitude: f32) -> Self {
+        self.latitude = (min_latitude, max_latitude);
+        self
+    }
+
+    /// Gets how strongly this biome applies, from 0.0 to 1.0.
+    fn weight(&self, altitude: f32, latitude: f32, altitude_blend: f32) -> f32 {
+        band(altitude, self.altitude, altitude_blend) * band(latitude, self.latitude, LATITUDE_BLEND)
+    }
+}
+
+/// The height and color of the terrain at a point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TerrainSample {
+    /// The height of the ground above the planet's radius, relative to the planet's radius.
+    pub height: f32,
+
+    /// The color of the ground.
+    pub albedo: Srgb,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// Describes the shape and coloring of the surface of the `Planet` on the same entity.
+/// Every function is deterministic for the seed, so the terrain can be queried on the CPU (e.g. for collision).
+pub struct Terrain {
+    /// The seed of every noise layer.
+    pub seed: u32,
+
+    /// The broad shape of the continents and ocean basins.
+    pub continents: NoiseLayer,
+
+    /// Ridged mountain ranges, which only rise above the sea.
+    pub mountains: NoiseLayer,
+
+    /// Impact craters, which are mostly seen on planets without an atmosphere.
+    #[serde(default)]
+    pub craters: Option<Craters>,
+
+    /// The height of the sea, relative to the planet's radius.
+    pub sea_level: f32,
+
+    /// The rules which color the terrain. Where several biomes overlap, their colors are blended.
+    pub biomes: Vec<Biome>,
+
+    /// The range of altitudes over which neighbouring biomes are blended, relative to the planet's radius.
+    pub biome_blend: f32,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            continents: NoiseLayer::new(1.5, 0.01, 6),
+            mountains: NoiseLayer::new(3.0, 0.01, 6),
+            craters: None,
+            sea_level: 0.0,
+            biomes: default_biomes(),
+            biome_blend: 0.0005,
+        }
+    }
+}
+
+/// The biomes of an earth-like planet.
+pub fn default_biomes() -> Vec<Biome> {
+    vec![
+        Biome::new(Srgb::new(0.05, 0.12, 0.3), -1.0, -0.002),
+        Biome::new(Srgb::new(0.1, 0.3, 0.45), -0.002, 0.0),
+        Biome::new(Srgb::new(0.76, 0.7, 0.5), 0.0, 0.0004).with_latitude(0.0, 60.0),
+        Biome::new(Srgb::new(0.8, 0.68, 0.42), 0.0004, 0.004).with_latitude(0.0, 25.0),
+        Biome::new(Srgb::new(0.2, 0.42, 0.14), 0.0004, 0.004).with_latitude(25.0, 60.0),
+        Biome::new(Srgb::new(0.35, 0.38, 0.28), 0.0, 0.004).with_latitude(60.0, 72.0),
+        Biome::new(Srgb::new(0.42, 0.38, 0.34), 0.004, 0.009).with_latitude(0.0, 72.0),
+        Biome::new(Srgb::new(0.95, 0.95, 0.97), 0.009, 1.0),
+        Biome::new(Srgb::new(0.95, 0.95, 0.97), -1.0, 1.0).with_latitude(72.0, 90.0),
+    ]
+}
+
+impl Component for Terrain {
+    // The storage is flagged, so that the systems which build from the terrain only rebuild when it changes.
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+/// Registers a reader of the changes to every `Terrain`, for a system which builds something from the terrain.
+pub(crate) fn register_terrain_reader(world: &mut World) -> ReaderId<ComponentEvent> {
+    WriteStorage::<Terrain>::fetch(world).register_reader()
+}
+
+/// Gets the entities whose `Terrain` has been inserted, modified or removed since the reader was last read.
+pub(crate) fn changed_terrain(terrains: &ReadStorage<'_, Terrain>, reader: Option<&mut ReaderId<ComponentEvent>>) -> BitSet {
+    let mut changed = BitSet::new();
+    if let Some(reader) = reader {
+        for event in terrains.channel().read(reader) {
+            match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) | ComponentEvent::Removed(id) => {
+                    changed.add(*id);
+                }
+            }
+        }
+    }
+    changed
+}
+
+impl Terrain {
+    /// Create a new terrain with the specified seed, and the layers and biomes of an earth-like planet.
+    pub fn new(seed: u32) -> Self {
+        Self { seed, ..Default::default() }
+    }
+
+    /// Changes the continent and mountain layers.
+    pub fn with_layers(mut self, continents: NoiseLayer, mountains: NoiseLayer) -> Self {
+        self.continents = continents;
+        self.mountains = mountains;
+        self
+    }
+
+    /// Adds craters to the terrain.
+    pub fn with_craters(mut self, craters: Craters) -> Self {
+        self.craters = Some(craters);
+        self
+    }
+
+    /// Changes the height of the sea.
+    pub fn with_sea_level(mut self, sea_level: f32) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Changes the rules which color the terrain.
+    pub fn with_biomes(mut self, biomes: Vec<Biome>) -> Self {
+        self.biomes = biomes;
+        self
+    }
+
+    /// Gets the height of the terrain (relative to the planet's radius) in the specified direction from the center of the planet.
+    pub fn height(&self, direction: Vector3<f32>) -> f32 {
+        let p = direction.normalize();
+        let continents = self.continents.fractal(p, self.seed) * self.continents.amplitude;
+        // The mountains fade in as the land rises above the sea.
+        let land = smoothstep(self.sea_level, self.sea_level + self.continents.amplitude * 0.25, continents);
+        let mountains = self.mountains.ridged(p, self.seed.wrapping_add(1000)) * self.mountains.amplitude * land;
+        let craters = self.craters.map_or(0.0, |craters| craters.height(p, self.seed.wrapping_add(2000)));
+        continents + mountains + craters
+    }
+
+    /// Gets the color of the terrain at the specified height (from `height`) in the specified direction.
+    pub fn albedo(&self, direction: Vector3<f32>, height: f32) -> Srgb {
+        let p = direction.normalize();
+        let wobble = gradient_noise(p * 4.0, self.seed.wrapping_add(3000)) * LATITUDE_WOBBLE;
+        let latitude = p.y.abs().min(1.0).asin().to_degrees() + wobble;
+        let altitude = height - self.sea_level;
+
+        let mut color = [0.0; 3];
+        let mut total = 0.0;
+        for biome in self.biomes.iter() {
+            let weight = biome.weight(altitude, latitude, self.biome_blend);
+            color[0] += biome.color.red * weight;
+            color[1] += biome.color.green * weight;
+            color[2] += biome.color.blue * weight;
+            total += weight;
+        }
+        if total <= 0.0 {
+            return Srgb::new(0.5, 0.5, 0.5);
+        }
+        Srgb::new(color[0] / total, color[1] / total, color[2] / total)
+    }
+
+    /// Gets the height and color of the terrain in the specified direction.
+    pub fn sample(&self, direction: Vector3<f32>) -> TerrainSample {
+        let height = self.height(direction);
+        TerrainSample { height, albedo: self.albedo(direction, height) }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Gets how much `x` is within the range, with soft edges of the specified width.
+fn band(x: f32, range: (f32, f32), blend: f32) -> f32 {
+    let blend = blend.max(1e-6);
+    smoothstep(range.0 - blend, range.0 + blend, x) * (1.0 - smoothstep(range.1 - blend, range.1 + blend, x))
+}
+
+/// Hashes a lattice point, the seed and the channel into a value from 0.0 to 1.0.
+fn lattice_hash(x: i32, y: i32, z: i32, seed: u32, channel: u32) -> f32 {
+    let key = (x as u32 as u64).wrapping_mul(0x8DA6_B343)
+        ^ (y as u32 as u64).wrapping_mul(0xD816_3841)
+        ^ (z as u32 as u64).wrapping_mul(0xCB1A_B31F);
+    unit_hash(key, seed, channel)
+}
+
+/// Gets the gradient of a lattice point, which is one of the 12 directions to the edges of a cube.
+fn lattice_gradient(x: i32, y: i32, z: i32, seed: u32) -> Vector3<f32> {
+    const GRADIENTS: [[f32; 3]; 12] = [
+        [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+        [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+        [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+    ];
+    let index = ((lattice_hash(x, y, z, seed, 0x6772) * 12.0) as usize).min(11);
+    Vector3::from(GRADIENTS[index])
+}
+
+/// Three dimensional gradient (Perlin) noise, which gives a value from about -1.0 to 1.0.
+pub fn gradient_noise(p: Vector3<f32>, seed: u32) -> f32 {
+    let cell = p.map(f32::floor);
+    let f = p - cell;
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    // The quintic fade curve of improved Perlin noise.
+    let fade = f.map(|t| t * t * t * (t * (t * 6.0 - 15.0) + 10.0));
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let offset = f - Vector3::new(dx as f32, dy as f32, dz as f32);
+        lattice_gradient(x + dx, y + dy, z + dz, seed).dot(&offset)
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x);
+    lerp(lerp(x00, x10, fade.y), lerp(x01, x11, fade.y), fade.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planet::Planet;
+
+    fn directions() -> Vec<Vector3<f32>> {
+        (0..64)
+            .map(|i| {
+                let t = i as f32 * 0.37;
+                Vector3::new(t.sin(), (t * 1.7).cos(), (t * 0.3).sin() - 0.5).normalize()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn height_is_deterministic_for_the_seed() {
+        let terrain = Terrain::new(7).with_craters(Craters::new(8.0, 0.5, 0.002));
+        let same = Terrain::new(7).with_craters(Craters::new(8.0, 0.5, 0.002));
+        let other = Terrain::new(8).with_craters(Craters::new(8.0, 0.5, 0.002));
+        let heights: Vec<f32> = directions().into_iter().map(|d| terrain.height(d)).collect();
+        assert_eq!(heights, directions().into_iter().map(|d| same.height(d)).collect::<Vec<f32>>());
+        assert_ne!(heights, directions().into_iter().map(|d| other.height(d)).collect::<Vec<f32>>());
+        // The terrain isn't flat.
+        assert!(heights.iter().any(|h| (h - heights[0]).abs() > 1e-4));
+    }
+
+    #[test]
+    fn surface_points_have_no_altitude() {
+        let planet = Planet::new(3.0, 1.0);
+        let terrain = Terrain::new(3).with_craters(Craters::new(8.0, 0.5, 0.002));
+        for d in directions() {
+            for terrain in &[None, Some(&terrain)] {
+                let point = planet.surface_point(*terrain, d * 2.0);
+                assert!(planet.altitude(*terrain, point).abs() < 1e-4);
+                assert!((planet.altitude(*terrain, point * 1.1) - point.norm() * 0.1).abs() < 1e-4);
+            }
+        }
+        assert_eq!(planet.surface_radius(None, directions()[0]), 3.0);
+    }
+
+    #[test]
+    fn only_changed_terrain_is_reported() {
+        let mut world = World::new();
+        world.register::<Terrain>();
+        let mut reader = register_terrain_reader(&mut world);
+        let a = world.create_entity().with(Terrain::new(1)).build();
+        let b = world.create_entity().with(Terrain::new(2)).build();
+        let changed = changed_terrain(&world.read_storage(), Some(&mut reader));
+        assert!(changed.contains(a.id()) && changed.contains(b.id()));
+        assert!((&changed_terrain(&world.read_storage(), Some(&mut reader))).join().next().is_none());
+
+        world.write_storage::<Terrain>().get_mut(b).unwrap().sea_level = 0.001;
+        let changed = changed_terrain(&world.read_storage(), Some(&mut reader));
+        assert!(!changed.contains(a.id()) && changed.contains(b.id()));
+
+        world.write_storage::<Terrain>().remove(a);
+        let changed = changed_terrain(&world.read_storage(), Some(&mut reader));
+        assert!(changed.contains(a.id()) && !changed.contains(b.id()));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    marker::PhantomData,
+};
+
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader},
+    core::math::Vector3,
+    ecs::prelude::*,
+    renderer::{
+        rendy::{
+            hal,
+            texture::{TextureBuilder, pixel::Rgba8Unorm},
+        },
+        types::TextureData,
+        Texture,
+    },
+};
+
+use super::terrain::{changed_terrain, register_terrain_reader, Terrain};
+
+/// Gets the direction from the center of a planet through a point of an equirectangular map, where `u` and `v` are from 0.0 to 1.0.
+/// The longitude runs around the planet's Y axis and `v` runs from the north (+Y) pole to the south pole.
+pub fn equirectangular_direction(u: f32, v: f32) -> Vector3<f32> {
+    let theta = v * PI;
+    let phi = (u - 0.5) * 2.0 * PI;
+    Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+/// Generates a grey equirectangular map which is twice as wide as it is high, with `resolution` texels from pole to pole.
+/// `value` maps a direction from the center of the planet to the value of the map in that direction, from 0.0 to 1.0.
+pub fn equirectangular_map<F>(resolution: u32, value: F) -> TextureData
+    where
+        F: Fn(Vector3<f32>) -> f32,
+{
+    let height = resolution.max(1);
+    let width = height * 2;
+    let mut texels = Vec::with_capacity((width * height) as usize);
+    for j in 0..height {
+        for i in 0..width {
+            let direction = equirectangular_direction((i as f32 + 0.5) / width as f32, (j as f32 + 0.5) / height as f32);
+            let byte = (value(direction).clamp(0.0, 1.0) * 255.0).round() as u8;
+            texels.push(Rgba8Unorm { repr: [byte, byte, byte, 255] });
+        }
+    }
+    let builder = TextureBuilder::new()
+        .with_kind(hal::image::Kind::D2(width, height, 1, 1))
+        .with_view_kind(hal::image::ViewKind::D2)
+        .with_data_width(width)
+        .with_data_height(height)
+        .with_sampler_info(hal::image::SamplerInfo::new(hal::image::Filter::Linear, hal::image::WrapMode::Tile))
+        .with_data(texels);
+    TextureData(builder)
+}
+
+/// A component which needs a map generated from the `Terrain` of its planet, such as the depth of an ocean.
+pub trait TerrainMap: Component + Send + Sync {
+    /// The settings which the map is generated with. The map is generated again whenever they change.
+    type Settings: std::fmt::Debug + PartialEq + Send + Sync + 'static;
+
+    /// Gets the settings of the map, or `None` if this component doesn't need a map.
+    fn map_settings(&self) -> Option<Self::Settings>;
+
+    /// Generates the map of the terrain.
+    fn build_map(terrain: &Terrain, settings: &Self::Settings) -> TextureData;
+}
+
+/// The maps of every `C` on a planet with terrain, by the planet entity.
+#[derive(Debug)]
+pub struct TerrainMaps<C> {
+    maps: HashMap<Entity, Handle<Texture>>,
+    marker: PhantomData<C>,
+}
+
+impl<C> Default for TerrainMaps<C> {
+    fn default() -> Self {
+        Self { maps: HashMap::new(), marker: PhantomData }
+    }
+}
+
+impl<C> TerrainMaps<C> {
+    /// Gets the map of the specified planet entity, if it has been generated.
+    pub fn get(&self, entity: Entity) -> Option<&Handle<Texture>> {
+        self.maps.get(&entity)
+    }
+}
+
+/// Generates the map of every `C` with a `Terrain`, whenever the terrain or the settings of the map change.
+#[derive(Debug)]
+pub struct TerrainMapSystem<C: TerrainMap> {
+    /// The settings which each map was generated with.
+    built: HashMap<Entity, C::Settings>,
+    terrain_reader: Option<ReaderId<ComponentEvent>>,
+}
+
+impl<C: TerrainMap> Default for TerrainMapSystem<C> {
+    fn default() -> Self {
+        Self { built: HashMap::new(), terrain_reader: None }
+    }
+}
+
+impl<'a, C: TerrainMap> System<'a> for TerrainMapSystem<C> {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Terrain>,
+        ReadStorage<'a, C>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Texture>>,
+        Write<'a, TerrainMaps<C>>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.terrain_reader = Some(register_terrain_reader(world));
+    }
+
+    fn run(&mut self, (entities, terrains, components, loader, texture_storage, mut maps): Self::SystemData) {
+        // Forget the maps of components which no longer exist or no longer need one, and of terrain which has changed.
+        let changed = changed_terrain(&terrains, self.terrain_reader.as_mut());
+        let built = &mut self.built;
+        built.retain(|entity, _| !changed.contains(entity.id()));
+        maps.maps.retain(|entity, _| {
+            let keep = entities.is_alive(*entity)
+                && components.get(*entity).and_then(C::map_settings).is_some()
+                && terrains.contains(*entity);
+            if !keep {
+                built.remove(entity);
+            }
+            keep
+        });
+
+        for (entity, terrain, component) in (&entities, &terrains, &components).join() {
+            let settings = match component.map_settings() {
+                Some(settings) => settings,
+                None => continue,
+            };
+            if self.built.get(&entity) == Some(&settings) {
+                continue;
+            }
+            let map = C::build_map(terrain, &settings);
+            maps.maps.insert(entity, loader.load_from_data(map, (), &texture_storage));
+            self.built.insert(entity, settings);
+        }
+    }
+}
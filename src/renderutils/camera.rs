@@ -1,7 +1,16 @@
+use std::ops::Deref;
+
 use amethyst::{
-    ecs::prelude::*,
+    core::{
+        math::Vector3,
+        transform::Transform,
+    },
+    ecs::{
+        prelude::*,
+        storage::MaskedStorage,
+    },
     renderer::{
-        camera::{Camera, Projection},
+        camera::{ActiveCamera, Camera, Projection},
         submodules::gather::CameraGatherer,
     }
 };
@@ -23,4 +32,32 @@ pub fn set_camera_far(world: &World, far: f32) {
                 ))
             }
         }
- }
\ No newline at end of file
+ }
+
+/// Gets the world space position of the active camera, if there is one.
+pub fn camera_position(world: &World) -> Option<Vector3<f32>> {
+    let camera_entity = CameraGatherer::gather_camera_entity(world)?;
+    let transforms = world.read_storage::<Transform>();
+    let transform = transforms.get(camera_entity)?;
+    Some(transform.global_matrix().column(3).xyz())
+}
+
+
+/// Gets the world space position of the active camera from within a system.
+/// This falls back to the first camera if there is no active camera, like the `CameraGatherer`.
+pub fn find_camera_position<D>(
+    entities: &Entities<'_>,
+    active_camera: &ActiveCamera,
+    cameras: &ReadStorage<'_, Camera>,
+    transforms: &Storage<'_, Transform, D>,
+) -> Option<Vector3<f32>>
+    where
+        D: Deref<Target = MaskedStorage<Transform>>,
+{
+    let camera_entity = active_camera
+        .entity
+        .or_else(|| (entities, cameras).join().map(|(entity, _)| entity).next())?;
+    transforms
+        .get(camera_entity)
+        .map(|transform| transform.global_matrix().column(3).xyz())
+}
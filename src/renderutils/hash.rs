@@ -0,0 +1,9 @@
+/// Hashes a key along with a seed and a channel into a value from 0.0 to 1.0, so that each channel gives an independent value.
+pub fn unit_hash(key: u64, seed: u32, channel: u32) -> f32 {
+    let mut h = key ^ (u64::from(seed) << 32) ^ u64::from(channel).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    // The finalizer of splitmix64.
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^= h >> 31;
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
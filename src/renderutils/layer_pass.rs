@@ -0,0 +1,312 @@
+use std::borrow::Cow;
+
+use amethyst::{
+    assets::Handle,
+    core::ecs::World,
+    renderer::{
+        pipeline::{PipelineDescBuilder, PipelinesBuilder},
+        rendy::{
+            command::{QueueId, RenderPassEncoder},
+            factory::Factory,
+            graph::render::{PrepareResult, RenderGroup},
+            hal::{self, device::Device, pso, pso::ShaderStageFlags},
+            mesh::{AsVertex, Position},
+            shader::{Shader, SpirvShader},
+        },
+        submodules::{FlatEnvironmentSub, TextureId, TextureSub},
+        types::Backend,
+        Texture,
+    },
+};
+
+use super::StaticVertexBuffer;
+use crate::{planet::sub::PlanetSub, star::sub::StarSub};
+
+const STATIC_DEPTH: f32 = 0.0;
+
+const STATIC_VERTEX_DATA: [Position; 4] = [
+    Position([-1.0, -1.0, STATIC_DEPTH]),
+    Position([-1.0, 1.0, STATIC_DEPTH]),
+    Position([1.0, 1.0, STATIC_DEPTH]),
+    Position([1.0, -1.0, STATIC_DEPTH]),
+];
+
+const STATIC_INSTANCE_DATA: [u32; 6] = [0, 1, 2, 0, 3, 2];
+
+/// The id of the boolean specialization constant which tells a layer shader whether it reads the texture at set 4.
+const TEXTURED_CONSTANT_ID: u32 = 0;
+
+lazy_static::lazy_static! {
+    static ref VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/atmosphere.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+}
+
+/// The uniforms of a kind of layer around planets (such as clouds or rings), where each layer may have a texture.
+pub(crate) trait LayerSub<B: Backend>: std::fmt::Debug + Send + Sync + Sized {
+    fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error>;
+
+    fn process(&mut self, factory: &Factory<B>, index: usize, world: &World);
+
+    /// Returns the raw `DescriptorSetLayout` of the uniforms.
+    fn raw_layout(&self) -> &B::DescriptorSetLayout;
+
+    fn bind(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>);
+
+    /// Gets the texture of each layer, in the order of the list.
+    fn textures(&self) -> &[Option<Handle<Texture>>];
+
+    fn is_empty(&self) -> bool;
+}
+
+/// How a kind of layer is drawn by a `DrawLayers` render group.
+/// The fragment shader is drawn over the whole screen once for each layer, with the index of the layer pushed before each draw.
+/// The planets are bound at set 3, so that a layer can refer to the atmosphere of its planet by its index.
+/// It declares `layout(constant_id = 0) const bool TEXTURED`, which is specialized to true for the layers which have a texture.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LayerPass {
+    pub fragment: &'static SpirvShader,
+    pub depth_test: pso::DepthTest,
+    pub blend: pso::BlendState,
+    /// A word which is pushed after the index of each layer.
+    pub push: Option<u32>,
+}
+
+impl LayerPass {
+    /// The size of the push constants, which hold the index of the layer and the extra word.
+    fn push_constant_size(&self) -> u32 {
+        if self.push.is_some() { 8 } else { 4 }
+    }
+
+    /// Builds the render group which draws the layers of `S`.
+    pub fn build<B: Backend, S: LayerSub<B> + 'static>(
+        self,
+        factory: &mut Factory<B>,
+        subpass: hal::pass::Subpass<'_, B>,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        let env = FlatEnvironmentSub::new(factory)?;
+        let layers = S::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        let stars = StarSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        let planets = PlanetSub::new(factory, pso::ShaderStageFlags::FRAGMENT)?;
+        let tex = TextureSub::new(factory)?;
+        let vertex = StaticVertexBuffer::new();
+        let (pipeline, pipeline_layout) = build_layer_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            vec![env.raw_layout(), layers.raw_layout(), stars.raw_layout(), planets.raw_layout()],
+            &self,
+            false,
+        )?;
+
+        // Layers with a texture use the same shader, specialized to read the texture.
+        let (texture_pipeline, texture_pipeline_layout) = build_layer_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            vec![env.raw_layout(), layers.raw_layout(), stars.raw_layout(), planets.raw_layout(), tex.raw_layout()],
+            &self,
+            true,
+        )?;
+
+        Ok(Box::new(DrawLayers::<B, S> {
+            pipeline,
+            pipeline_layout,
+            texture_pipeline,
+            texture_pipeline_layout,
+            env,
+            vertex,
+            layers,
+            stars,
+            planets,
+            tex,
+            textures: Vec::new(),
+            push: self.push,
+        }))
+    }
+}
+
+/// A render group which draws every layer of `S`, reading their textures once they are loaded.
+#[derive(Debug)]
+pub(crate) struct DrawLayers<B: Backend, S> {
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    texture_pipeline: B::GraphicsPipeline,
+    texture_pipeline_layout: B::PipelineLayout,
+    env: FlatEnvironmentSub<B>,
+    vertex: StaticVertexBuffer<B, Position>,
+    layers: S,
+    stars: StarSub<B>,
+    planets: PlanetSub<B>,
+    tex: TextureSub<B>,
+    /// The texture of each layer, or `None` if the layer is procedural (or its texture hasn't loaded).
+    textures: Vec<Option<TextureId>>,
+    push: Option<u32>,
+}
+
+impl<B: Backend, S: LayerSub<B>> RenderGroup<B, World> for DrawLayers<B, S> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) -> PrepareResult {
+
+        self.env.process(factory, index, world);
+        self.layers.process(factory, index, world);
+        self.stars.process(factory, index, world);
+        self.planets.process(factory, index, world);
+
+        // Layers are drawn without their texture until it is loaded.
+        let tex = &mut self.tex;
+        self.textures = self.layers
+            .textures()
+            .iter()
+            .map(|texture| {
+                texture
+                    .as_ref()
+                    .and_then(|texture| tex.insert(factory, world, texture, hal::image::Layout::ShaderReadOnlyOptimal))
+                    .map(|(tex_id, _)| tex_id)
+            })
+            .collect();
+        self.tex.maintain(factory, world);
+
+        self.vertex.prepare(
+            factory,
+            queue,
+            &STATIC_VERTEX_DATA,
+            Some(&STATIC_INSTANCE_DATA),
+            index
+        ).expect("Failed to prepare static vertex buffer!");
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _world: &World,
+    ) {
+        if self.layers.is_empty() {
+            return;
+        }
+        for (i, tex_id) in self.textures.iter().enumerate() {
+            let layout = match tex_id {
+                Some(tex_id) if self.tex.loaded(*tex_id) => {
+                    encoder.bind_graphics_pipeline(&self.texture_pipeline);
+                    self.tex.bind(&self.texture_pipeline_layout, 4, *tex_id, &mut encoder);
+                    &self.texture_pipeline_layout
+                }
+                _ => {
+                    encoder.bind_graphics_pipeline(&self.pipeline);
+                    &self.pipeline_layout
+                }
+            };
+            self.env.bind(index, layout, 0, &mut encoder);
+            self.layers.bind(index, layout, 1, &mut encoder);
+            self.stars.bind(index, layout, 2, &mut encoder);
+            self.planets.bind(index, layout, 3, &mut encoder);
+            unsafe {
+                match self.push {
+                    Some(word) => encoder.push_constants(layout, pso::ShaderStageFlags::FRAGMENT, 0, &[i as u32, word]),
+                    None => encoder.push_constants(layout, pso::ShaderStageFlags::FRAGMENT, 0, &[i as u32]),
+                }
+                self.vertex.draw(&mut encoder, 0..1, index);
+            }
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+            factory.device().destroy_graphics_pipeline(self.texture_pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.texture_pipeline_layout);
+        }
+    }
+}
+
+fn build_layer_pipeline<B: Backend>(
+    factory: &Factory<B>,
+    subpass: hal::pass::Subpass<'_, B>,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    layouts: Vec<&B::DescriptorSetLayout>,
+    pass: &LayerPass,
+    textured: bool,
+) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+    let pipeline_layout = unsafe {
+        factory
+            .device()
+            .create_pipeline_layout(layouts, Some((pso::ShaderStageFlags::FRAGMENT, 0..pass.push_constant_size())))
+    }?;
+    // Load the shaders
+    let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
+    let shader_fragment = unsafe { pass.fragment.module(factory).unwrap() };
+
+    let shaders = pso::GraphicsShaderSet {
+        vertex: pso::EntryPoint {
+            entry: "main",
+            module: &shader_vertex,
+            specialization: pso::Specialization::default(),
+        },
+        fragment: Some(pso::EntryPoint {
+            entry: "main",
+            module: &shader_fragment,
+            // Booleans are specialized as 32 bit values.
+            specialization: pso::Specialization {
+                constants: Cow::Owned(vec![pso::SpecializationConstant { id: TEXTURED_CONSTANT_ID, range: 0..4 }]),
+                data: Cow::Owned((textured as u32).to_ne_bytes().to_vec()),
+            },
+        }),
+        hull: None,
+        domain: None,
+        geometry: None,
+    };
+
+    // Build the pipeline
+    let pipes = PipelinesBuilder::new()
+        .with_pipeline(
+            PipelineDescBuilder::new()
+                .with_vertex_desc(&[(Position::vertex(), pso::VertexInputRate::Vertex)])
+                .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleList))
+                .with_shaders(shaders)
+                .with_layout(&pipeline_layout)
+                .with_subpass(subpass)
+                .with_framebuffer_size(framebuffer_width, framebuffer_height)
+                .with_depth_test(pass.depth_test)
+                .with_blend_targets(vec![pso::ColorBlendDesc { blend: Some(pass.blend), mask: pso::ColorMask::ALL}]),
+        )
+        .build(factory, None);
+
+    // Destoy the shaders once loaded
+    unsafe {
+        factory.destroy_shader_module(shader_vertex);
+        factory.destroy_shader_module(shader_fragment);
+    }
+
+    // Handle the Errors
+    match pipes {
+        Err(e) => {
+            unsafe {
+                factory.device().destroy_pipeline_layout(pipeline_layout);
+            }
+            Err(e)
+        }
+        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+    }
+}
@@ -2,8 +2,14 @@ pub mod shader_buffer;
 pub mod static_buffer;
 
 pub mod camera;
+pub mod hash;
+pub(crate) mod layer_pass;
+pub mod scale;
 
 pub use shader_buffer::*;
 pub use static_buffer::*;
 
-pub use camera::*;
\ No newline at end of file
+pub use camera::*;
+pub use hash::*;
+pub(crate) use layer_pass::*;
+pub use scale::*;
\ No newline at end of file
@@ -0,0 +1,7 @@
+use amethyst::core::math::Matrix4;
+
+/// Gets the uniform scale of a transform matrix, which is the length of its first column.
+/// Unlike the first element of the diagonal, this doesn't shrink when the transform is rotated.
+pub fn uniform_scale(matrix: &Matrix4<f32>) -> f32 {
+    matrix.column(0).xyz().norm()
+}
@@ -0,0 +1,168 @@
+pub mod pass;
+pub(crate) mod sub;
+
+use amethyst::{
+    assets::{Handle, PrefabData},
+    derive::PrefabData,
+    ecs::prelude::*,
+    error::Error,
+    renderer::{palette::Srgb, Texture},
+    core::math::Vector3,
+};
+
+use glsl_layout::*;
+
+use serde::{Serialize, Deserialize};
+
+use crate::planet::MAX_PLANETS;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// A system of rings around a planet, which lies in the planet's equatorial (XZ) plane.
+/// The rings are lit by every `Star`, are shadowed by the planet, and cast shadows on the planet and its `Atmosphere`.
+pub struct Rings {
+    /// The radius of the inner edge of the rings, relative to the planet's radius.
+    pub inner_radius: f32,
+
+    /// The radius of the outer edge of the rings, relative to the planet's radius.
+    pub outer_radius: f32,
+
+    /// The fraction of the light which is blocked by the densest part of the rings, when seen face on (0.0 to 1.0).
+    pub opacity: f32,
+
+    /// The number of procedural bands between the inner and outer edges. There are no bands if this is zero.
+    pub bands: u32,
+
+    /// The difference in density between the bands, from 0.0 (uniform) to 1.0 (with gaps between the bands).
+    pub band_contrast: f32,
+
+    /// The seed of the procedural bands.
+    pub seed: u32,
+
+    /// The color of the ring particles.
+    #[serde(with = "amethyst::renderer::serde_shim::srgb")]
+    pub color: Srgb,
+
+    /// The angle (in radians) by which the rings are tilted about the planet's X axis, away from its equator.
+    pub tilt: f32,
+
+    /// A radial profile which replaces the procedural bands, where the left edge of the texture is the inner edge of the rings.
+    /// The color of each texel tints the rings and its alpha scales the density.
+    /// The shadows of textured rings use a uniform density, since the texture isn't available to the atmosphere.
+    #[serde(skip)]
+    pub texture: Option<Handle<Texture>>,
+}
+
+impl Default for Rings {
+    fn default() -> Self {
+        Self {
+            inner_radius: 1.3,
+            outer_radius: 2.3,
+            opacity: 0.8,
+            bands: 24,
+            band_contrast: 0.6,
+            seed: 0,
+            color: Srgb::new(0.85, 0.78, 0.65),
+            tilt: 0.0,
+            texture: None,
+        }
+    }
+}
+
+impl Rings {
+    /// Create new rings between the specified radii (relative to the planet's radius).
+    pub fn new(inner_radius: f32, outer_radius: f32) -> Self {
+        assert!(inner_radius < outer_radius, "The inner radius of the rings must be smaller than the outer radius!");
+        Self { inner_radius, outer_radius, ..Default::default() }
+    }
+
+    /// Changes the opacity and color of the rings.
+    pub fn with_opacity(mut self, opacity: f32, color: Srgb) -> Self {
+        self.opacity = opacity;
+        self.color = color;
+        self
+    }
+
+    /// Changes the number, contrast and seed of the procedural bands.
+    pub fn with_bands(mut self, bands: u32, band_contrast: f32, seed: u32) -> Self {
+        self.bands = bands;
+        self.band_contrast = band_contrast;
+        self.seed = seed;
+        self
+    }
+
+    /// Tilts the rings about the planet's X axis.
+    pub fn with_tilt(mut self, tilt: f32) -> Self {
+        self.tilt = tilt;
+        self
+    }
+
+    /// Uses a texture for the radial profile instead of the procedural bands.
+    pub fn with_texture(mut self, texture: Handle<Texture>) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    /// Gets the normal of the plane of the rings, in the planet's local space.
+    pub fn normal(&self) -> Vector3<f32> {
+        Vector3::new(0.0, self.tilt.cos(), self.tilt.sin())
+    }
+}
+
+impl Component for Rings {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub const MAX_RINGS: usize = MAX_PLANETS;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct RingData {
+    pub center: vec3,
+    pub radius: float,
+    /// The normal of the plane of the rings in world space.
+    pub normal: vec3,
+    pub inner_radius: float,
+    pub outer_radius: float,
+    pub opacity: float,
+    /// Zero for textured rings, which have a uniform density in the shadows.
+    pub bands: float,
+    pub band_contrast: float,
+    pub color: vec3,
+    pub seed: float,
+}
+
+impl RingData {
+    pub(crate) fn new(rings: &Rings, center: Vector3<f32>, normal: Vector3<f32>, radius: f32) -> Self {
+        Self {
+            center: Into::<[f32; 3]>::into(center).into(),
+            radius,
+            normal: Into::<[f32; 3]>::into(normal).into(),
+            inner_radius: rings.inner_radius,
+            outer_radius: rings.outer_radius,
+            opacity: rings.opacity.clamp(0.0, 1.0),
+            bands: if rings.texture.is_some() { 0.0 } else { rings.bands as f32 },
+            band_contrast: rings.band_contrast,
+            color: [rings.color.red, rings.color.green, rings.color.blue].into(),
+            seed: (rings.seed % 1024) as f32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct RingList {
+    pub(crate) count: uint,
+    rings: [RingData; MAX_RINGS],
+}
+
+impl RingList {
+    pub(crate) fn new(ring_data: &[RingData]) -> Self {
+        assert!(ring_data.len() <= MAX_RINGS);
+        let mut rings: [RingData; MAX_RINGS] = Default::default();
+        for (i, data) in ring_data.iter().enumerate() {
+            rings[i] = *data;
+        }
+        Self { rings, count: ring_data.len() as u32 }
+    }
+}
@@ -0,0 +1,128 @@
+use amethyst::{
+    core::ecs::{
+        DispatcherBuilder, World,
+    },
+    error::Error,
+    renderer::{
+        bundle::{RenderOrder, RenderPlan, RenderPlugin, Target},
+        rendy::{
+            command::QueueId,
+            factory::Factory,
+            graph::{
+                GraphContext,
+                NodeBuffer, NodeImage, render::{RenderGroup, RenderGroupDesc},
+            },
+            hal::{self, pso, pso::ShaderStageFlags},
+            shader::SpirvShader,
+        },
+        types::Backend,
+    },
+};
+
+use super::sub::RingSub;
+
+use crate::renderutils::LayerPass;
+
+use amethyst::prelude::WorldExt;
+
+lazy_static::lazy_static! {
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/rings.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawRingsDesc {
+    /// Whether this group draws the half of the rings in front of the planet, rather than the half behind it.
+    front: bool,
+}
+
+impl DrawRingsDesc {
+    /// Create instance of `DrawRingsDesc` render group, which draws the half of the rings behind each planet.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Draws the half of the rings in front of each planet instead.
+    pub fn with_front(mut self, front: bool) -> Self {
+        self.front = front;
+        self
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawRingsDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        // The half behind the planet is hidden by nearer geometry, but it doesn't hide the atmosphere which is drawn over it.
+        // The half in front is drawn over the atmosphere, whose depth is in front of the planet, so it can't be depth tested.
+        // Instead the shader hides the rings behind their planet.
+        let depth_test = if self.front {
+            pso::DepthTest { fun: pso::Comparison::Always, write: false }
+        } else {
+            pso::DepthTest { fun: pso::Comparison::Less, write: false }
+        };
+
+        // Rings with a profile texture read it instead of generating the bands.
+        // The half which is drawn is pushed after the index of each ring system.
+        LayerPass {
+            fragment: &FRAGMENT,
+            depth_test,
+            blend: pso::BlendState::ALPHA,
+            push: Some(self.front as u32),
+        }.build::<B, RingSub<B>>(factory, subpass, framebuffer_width, framebuffer_height)
+    }
+}
+
+/// A [RenderPlugin] which draws every `Rings` component.
+/// The half of the rings behind each planet is drawn before the atmospheres, and the half in front of it afterwards.
+/// This plugin must be added after the `AtmosphereRender` plugin, so that the front half is drawn over the atmosphere.
+#[derive(Debug, Default)]
+pub struct RingRender;
+
+impl RingRender {
+    /// Creates the ring plugin.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for RingRender {
+    fn on_build<'a, 'b>(
+        &mut self,
+        world: &mut World,
+        _builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        world.register::<super::Rings>();
+        world.register::<crate::Planet>();
+        world.register::<crate::Atmosphere>();
+        world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _world: &World,
+    ) -> Result<(), Error> {
+        plan.extend_target(Target::Main, |ctx| {
+            ctx.add(RenderOrder::AfterTransparent, DrawRingsDesc::new().builder())?;
+            ctx.add(RenderOrder::LinearPostEffects, DrawRingsDesc::new().with_front(true).builder())?;
+            Ok(())
+        });
+        Ok(())
+    }
+}
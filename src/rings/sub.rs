@@ -0,0 +1,91 @@
+use amethyst::{
+    assets::Handle,
+    core::{
+        transform::Transform,
+        math::{
+            Matrix4,
+            Vector4,
+            U3,
+        }
+    },
+    renderer::{
+        submodules::DynamicUniform,
+        rendy::{
+            command::RenderPassEncoder,
+            factory::Factory,
+            hal,
+        },
+        types::Backend,
+        Texture,
+    },
+    ecs::prelude::*,
+};
+
+use super::*;
+use crate::{
+    planet::{Atmosphere, Planet},
+    renderutils::{camera_position, uniform_scale, LayerSub},
+};
+
+#[derive(Debug)]
+pub(crate) struct RingSub<B: Backend> {
+    uniform: DynamicUniform<B, RingList>,
+    data: RingList,
+    /// The profile texture of each ring system, in the same order as the list.
+    textures: Vec<Option<Handle<Texture>>>,
+}
+
+impl<B: Backend> LayerSub<B> for RingSub<B> {
+    fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
+        let uniform = DynamicUniform::new(factory, flags)?;
+        Ok(Self { uniform, data: RingList::default(), textures: Vec::new() })
+    }
+
+    fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
+        let atmospheres = world.read_storage::<Atmosphere>();
+        let planets = world.read_storage::<Planet>();
+        let camera = camera_position(world);
+        let mut systems: Vec<(f32, RingData, Option<Handle<Texture>>)> = Vec::new();
+        for (rings, atmosphere, planet, transform) in (&world.read_storage::<Rings>(), atmospheres.maybe(), planets.maybe(), &world.read_storage::<Transform>()).join() {
+            let matrix: Matrix4<f32> = *transform.global_matrix();
+            let translation: Vector4<f32> = matrix.column(3).into();
+            let scale = uniform_scale(&matrix);
+            // The rings use the same radius as the atmosphere, so that their shadows line up with it.
+            let radius = match (atmosphere, planet) {
+                (Some(atmosphere), _) => atmosphere.base_planet_radius * scale,
+                (None, Some(planet)) => planet.radius * scale,
+                (None, None) => continue,
+            };
+            if systems.len() == MAX_RINGS {
+                break;
+            }
+            let normal = (matrix.fixed_slice::<U3, U3>(0, 0) * rings.normal()).normalize();
+            let distance = camera.map_or(0.0, |camera| (camera - translation.xyz()).norm());
+            systems.push((distance, RingData::new(rings, translation.xyz(), normal, radius), rings.texture.clone()));
+        }
+        // The ring systems are drawn from back to front, so that nearer rings are blended over the ones behind them.
+        systems.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let ring_list: Vec<RingData> = systems.iter().map(|(_, data, _)| *data).collect();
+        self.textures = systems.into_iter().map(|(_, _, texture)| texture).collect();
+        self.data = RingList::new(ring_list.as_slice());
+        self.uniform.write(factory, index, self.data.std140());
+    }
+
+    /// Returns the raw `DescriptorSetLayout` for this environment
+    fn raw_layout(&self) -> &B::DescriptorSetLayout {
+        self.uniform.raw_layout()
+    }
+
+    fn bind(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        self.uniform.bind(index,  pipeline_layout, binding_id, encoder);
+    }
+
+    /// Gets the profile texture of each ring system, in the order of the list.
+    fn textures(&self) -> &[Option<Handle<Texture>>] {
+        self.textures.as_slice()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.count == 0
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use amethyst::{
+    core::{
+        math::Vector3,
+        transform::Transform,
+    },
+    ecs::prelude::*,
+    renderer::camera::{ActiveCamera, Camera},
+    shrev::EventChannel,
+};
+
+use super::Star;
+use crate::{
+    planet::{Atmosphere, Planet},
+    renderutils::{find_camera_position, uniform_scale},
+};
+
+/// The visible fraction below which a star is considered to be eclipsed.
+pub const ECLIPSE_THRESHOLD: f32 = 0.999;
+
+/// A sphere which can block the light of a star, made up of a solid body and an optional atmosphere.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Occluder {
+    /// The center of the sphere in world space.
+    pub center: Vector3<f32>,
+
+    /// The radius of the solid body, which blocks all light.
+    pub radius: f32,
+
+    /// The outer radius of the atmosphere, which only blocks part of the light.
+    pub atmosphere_radius: f32,
+
+    /// The fraction of light which is blocked by the atmosphere.
+    pub atmosphere_opacity: f32,
+}
+
+impl Occluder {
+    /// Creates an occluder with no atmosphere.
+    pub fn solid(center: Vector3<f32>, radius: f32) -> Self {
+        Self { center, radius, atmosphere_radius: radius, atmosphere_opacity: 0.0 }
+    }
+
+    /// Creates the occluder of an entity with a `Planet` and/or an `Atmosphere`.
+    /// The solid body is the planet if there is one, and otherwise the ground of the atmosphere.
+    pub fn from_body(planet: Option<&Planet>, atmosphere: Option<&Atmosphere>, transform: &Transform) -> Option<Self> {
+        let matrix = transform.global_matrix();
+        let scale = uniform_scale(matrix);
+        let center = matrix.column(3).xyz();
+        match (planet, atmosphere) {
+            (Some(planet), Some(atmosphere)) => Some(Occluder {
+                center,
+                radius: planet.radius * scale,
+                atmosphere_radius: atmosphere.base_planet_radius * scale * atmosphere.height,
+                atmosphere_opacity: 1.0 - (-atmosphere.density).exp(),
+            }),
+            (None, Some(atmosphere)) => Some(Occluder {
+                center,
+                radius: atmosphere.base_planet_radius * scale,
+                atmosphere_radius: atmosphere.base_planet_radius * scale * atmosphere.height,
+                atmosphere_opacity: 1.0 - (-atmosphere.density).exp(),
+            }),
+            (Some(planet), None) => Some(Occluder::solid(center, planet.radius * scale)),
+            (None, None) => None,
+        }
+    }
+
+    /// Finds the occluder of every entity with a `Planet` and/or an `Atmosphere`.
+    pub fn gather(world: &World) -> Vec<Self> {
+        (world.read_storage::<Planet>().maybe(), world.read_storage::<Atmosphere>().maybe(), &world.read_storage::<Transform>())
+            .join()
+            .filter_map(|(planet, atmosphere, transform)| Self::from_body(planet, atmosphere, transform))
+            .collect()
+    }
+
+    /// Calculates the fraction of the disc of a star which is blocked by this occluder, as seen from the observer.
+    pub fn occluded_fraction(&self, observer: Vector3<f32>, star_center: Vector3<f32>, star_radius: f32) -> f32 {
+        let to_star = star_center - observer;
+        let star_distance = to_star.norm();
+        let to_occluder = self.center - observer;
+        let occluder_distance = to_occluder.norm();
+        if star_distance <= star_radius {
+            // The observer is inside the star.
+            return 0.0;
+        }
+        if occluder_distance <= self.radius {
+            // The observer is inside the solid body.
+            return 1.0;
+        }
+        if occluder_distance >= star_distance {
+            // The occluder is behind the star.
+            return 0.0;
+        }
+
+        let star_angle = (star_radius / star_distance).asin();
+        let separation = (to_star.dot(&to_occluder) / (star_distance * occluder_distance)).clamp(-1.0, 1.0).acos();
+        let solid = disc_overlap(star_angle, (self.radius / occluder_distance).asin(), separation);
+        // The observer sees the atmosphere from the inside if it is within it, so it is not treated as a disc.
+        let atmosphere = if occluder_distance > self.atmosphere_radius {
+            disc_overlap(star_angle, (self.atmosphere_radius / occluder_distance).asin(), separation).max(solid)
+        } else {
+            solid
+        };
+        let star_area = std::f32::consts::PI * star_angle * star_angle;
+        ((solid + (atmosphere - solid) * self.atmosphere_opacity) / star_area).min(1.0)
+    }
+}
+
+/// Calculates the area of the overlap between two discs with the specified radii, whose centers are `distance` apart.
+pub fn disc_overlap(r1: f32, r2: f32, distance: f32) -> f32 {
+    if distance >= r1 + r2 {
+        0.0
+    } else if distance <= (r1 - r2).abs() {
+        let r = r1.min(r2);
+        std::f32::consts::PI * r * r
+    } else {
+        let d2 = distance * distance;
+        let a1 = ((d2 + r1 * r1 - r2 * r2) / (2.0 * distance * r1)).clamp(-1.0, 1.0).acos();
+        let a2 = ((d2 + r2 * r2 - r1 * r1) / (2.0 * distance * r2)).clamp(-1.0, 1.0).acos();
+        let kite = ((-distance + r1 + r2) * (distance + r1 - r2) * (distance - r1 + r2) * (distance + r1 + r2)).max(0.0).sqrt();
+        r1 * r1 * a1 + r2 * r2 * a2 - 0.5 * kite
+    }
+}
+
+/// Calculates the fraction of the disc of a star which is visible from the observer.
+/// Each occluder is assumed to block an independent part of the star.
+pub fn visible_fraction(observer: Vector3<f32>, star_center: Vector3<f32>, star_radius: f32, occluders: &[Occluder]) -> f32 {
+    occluders
+        .iter()
+        .map(|occluder| 1.0 - occluder.occluded_fraction(observer, star_center, star_radius))
+        .product()
+}
+
+/// An event which is sent when a planet starts or stops blocking the camera's view of a star.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EclipseEvent {
+    /// The star with the specified entity has started to be eclipsed.
+    Began(Entity),
+    /// The star with the specified entity is no longer eclipsed.
+    Ended(Entity),
+}
+
+/// The fraction of each `Star` which is visible from the camera, taking into account `Planet` and `Atmosphere` entities in front of it.
+/// Unlike `StarVisibility` this is calculated on the CPU, so it is available in the same frame and does not depend on the renderer.
+#[derive(Debug, Clone, Default)]
+pub struct StarOcclusion {
+    visible: HashMap<Entity, f32>,
+    occluders: Vec<Occluder>,
+}
+
+impl StarOcclusion {
+    /// Gets the visible fraction (0.0 to 1.0) of the specified star entity.
+    /// Stars which haven't been processed yet are assumed to be fully visible.
+    pub fn visible_fraction(&self, star: Entity) -> f32 {
+        self.visible.get(&star).copied().unwrap_or(1.0)
+    }
+
+    /// Iterates over every star and its visible fraction.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, f32)> + '_ {
+        self.visible.iter().map(|(entity, visible)| (*entity, *visible))
+    }
+
+    /// Gets the occluders which were found in the last frame.
+    /// These can be used to calculate the visibility of a star from somewhere other than the camera (e.g. a solar panel).
+    pub fn occluders(&self) -> &[Occluder] {
+        self.occluders.as_slice()
+    }
+
+    /// Calculates the fraction of a star which is visible from the specified point, using the occluders found in the last frame.
+    pub fn visible_fraction_from(&self, observer: Vector3<f32>, star_center: Vector3<f32>, star_radius: f32) -> f32 {
+        visible_fraction(observer, star_center, star_radius, self.occluders.as_slice())
+    }
+}
+
+/// Calculates how much of each star is hidden behind planets, which is stored in the `StarOcclusion` resource.
+#[derive(Debug, Default)]
+pub struct StarOcclusionSystem;
+
+impl<'a> System<'a> for StarOcclusionSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Star>,
+        ReadStorage<'a, Planet>,
+        ReadStorage<'a, Atmosphere>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Camera>,
+        Read<'a, ActiveCamera>,
+        Write<'a, StarOcclusion>,
+        Write<'a, EventChannel<EclipseEvent>>,
+    );
+
+    fn run(&mut self, (entities, stars, planets, atmospheres, transforms, cameras, active_camera, mut occlusion, mut events): Self::SystemData) {
+        let occluders: Vec<Occluder> = (planets.maybe(), atmospheres.maybe(), &transforms)
+            .join()
+            .filter_map(|(planet, atmosphere, transform)| Occluder::from_body(planet, atmosphere, transform))
+            .collect();
+
+        let camera_position = find_camera_position(&entities, &active_camera, &cameras, &transforms);
+        let mut visible: HashMap<Entity, f32> = HashMap::new();
+        for (entity, _, transform) in (&entities, &stars, &transforms).join() {
+            let matrix = transform.global_matrix();
+            let fraction = match camera_position {
+                Some(camera_position) => visible_fraction(camera_position, matrix.column(3).xyz(), uniform_scale(matrix), occluders.as_slice()),
+                None => 1.0,
+            };
+
+            let was_eclipsed = occlusion.visible_fraction(entity) < ECLIPSE_THRESHOLD;
+            let is_eclipsed = fraction < ECLIPSE_THRESHOLD;
+            if is_eclipsed && !was_eclipsed {
+                events.single_write(EclipseEvent::Began(entity));
+            } else if was_eclipsed && !is_eclipsed {
+                events.single_write(EclipseEvent::Ended(entity));
+            }
+            visible.insert(entity, fraction);
+        }
+
+        occlusion.visible = visible;
+        occlusion.occluders = occluders;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use amethyst::core::math::{UnitQuaternion, Vector3};
+
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn disjoint_discs_do_not_overlap() {
+        assert_eq!(disc_overlap(1.0, 2.0, 3.0), 0.0);
+        assert_eq!(disc_overlap(1.0, 2.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn contained_disc_overlaps_with_its_whole_area() {
+        assert!(close(disc_overlap(1.0, 3.0, 0.0), PI));
+        assert!(close(disc_overlap(3.0, 1.0, 1.5), PI));
+        assert!(close(disc_overlap(2.0, 2.0, 0.0), 4.0 * PI));
+    }
+
+    #[test]
+    fn partial_overlap_matches_the_lens_area() {
+        // Two unit discs whose centers are one radius apart.
+        let lens = 2.0 * PI / 3.0 - 3.0f32.sqrt() / 2.0;
+        assert!(close(disc_overlap(1.0, 1.0, 1.0), lens));
+        assert!(close(disc_overlap(1.0, 2.0, 2.2), disc_overlap(2.0, 1.0, 2.2)));
+        // The overlap shrinks continuously as the discs separate.
+        let mut previous = disc_overlap(1.0, 2.0, 1.0);
+        assert!(close(previous, PI));
+        for i in 1..=20 {
+            let overlap = disc_overlap(1.0, 2.0, 1.0 + 2.0 * i as f32 / 20.0);
+            assert!(overlap <= previous + 1e-5);
+            previous = overlap;
+        }
+        assert!(close(previous, 0.0));
+    }
+
+    #[test]
+    fn occluder_between_the_observer_and_the_star() {
+        let star = Vector3::new(100.0, 0.0, 0.0);
+        let observer = Vector3::zeros();
+        // A body much larger (in angle) than the star blocks all of it, and one off to the side blocks none of it.
+        assert!(close(Occluder::solid(Vector3::new(50.0, 0.0, 0.0), 5.0).occluded_fraction(observer, star, 1.0), 1.0));
+        assert_eq!(Occluder::solid(Vector3::new(50.0, 20.0, 0.0), 5.0).occluded_fraction(observer, star, 1.0), 0.0);
+        // A body with the same angular size as the star, half way across it.
+        let partial = Occluder::solid(Vector3::new(50.0, 0.5, 0.0), 0.5).occluded_fraction(observer, star, 1.0);
+        assert!(partial > 0.3 && partial < 0.5);
+    }
+
+    #[test]
+    fn occluder_behind_the_star_or_around_the_observer() {
+        let star = Vector3::new(100.0, 0.0, 0.0);
+        assert_eq!(Occluder::solid(Vector3::new(200.0, 0.0, 0.0), 50.0).occluded_fraction(Vector3::zeros(), star, 1.0), 0.0);
+        assert_eq!(Occluder::solid(Vector3::zeros(), 2.0).occluded_fraction(Vector3::new(1.0, 0.0, 0.0), star, 1.0), 1.0);
+        assert_eq!(visible_fraction(Vector3::zeros(), star, 1.0, &[]), 1.0);
+    }
+
+    #[test]
+    fn atmosphere_blocks_part_of_the_light() {
+        let star = Vector3::new(100.0, 0.0, 0.0);
+        let occluder = Occluder { center: Vector3::new(50.0, 0.0, 0.0), radius: 0.1, atmosphere_radius: 5.0, atmosphere_opacity: 0.25 };
+        let fraction = occluder.occluded_fraction(Vector3::zeros(), star, 1.0);
+        assert!(fraction > 0.25 && fraction < 0.3);
+    }
+
+    #[test]
+    fn rotation_does_not_shrink_the_occluder() {
+        let mut transform = Transform::default();
+        transform.set_scale(Vector3::new(3.0, 3.0, 3.0));
+        transform.set_rotation(UnitQuaternion::from_euler_angles(0.3, 1.2, -0.7));
+        transform.copy_local_to_global();
+        let occluder = Occluder::from_body(Some(&Planet::new(2.0, 1.0)), None, &transform).unwrap();
+        assert!(close(occluder.radius, 6.0));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use amethyst::{
+    assets::PrefabData,
+    derive::PrefabData,
+    core::{
+        math::Vector3,
+        timing::Time,
+        transform::{Parent, Transform},
+    },
+    ecs::prelude::*,
+    error::Error,
+    renderer::{
+        camera::{ActiveCamera, Camera},
+        light::{DirectionalLight, Light, PointLight},
+    },
+};
+
+use serde::{Serialize, Deserialize};
+
+use super::{Star, StarPhotometry, variability::Variability};
+use crate::renderutils::{find_camera_position, uniform_scale};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// The type of `Light` which is used to represent the light emitted by a star.
+pub enum StarLightKind {
+    /// A point light at the center of the star.
+    /// This is correct for scenes which span a large part of the star system.
+    Point,
+    /// A directional light from the star towards the camera.
+    /// This is useful when the scene around the camera is small compared to its distance from the star.
+    Directional,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// Controls the `Light` which is created for a `Star`.
+/// Stars without this component are lit using the default settings.
+pub struct StarLight {
+    /// Whether the star should illuminate the scene at all.
+    pub enabled: bool,
+
+    /// The type of light used to represent the star.
+    pub kind: StarLightKind,
+
+    /// The illuminance of the sun at a distance of 1 AU.
+    /// The illuminance of other stars is scaled by their luminosity and distance.
+    pub intensity: f32,
+}
+
+impl StarLight {
+    /// Create a new star light component with the specified data.
+    pub fn new(kind: StarLightKind, intensity: f32) -> Self {
+        Self { enabled: true, kind, intensity }
+    }
+
+    /// Creates a star light component which stops the star from illuminating the scene.
+    pub fn disabled() -> Self {
+        Self { enabled: false, ..Default::default() }
+    }
+
+    /// Calculates the intensity of the light at a unit distance (in world units) from a star with the specified luminosity.
+    pub fn luminous_intensity(&self, photometry: &StarPhotometry, luminosity: f32) -> f32 {
+        self.intensity * luminosity * photometry.astronomical_unit * photometry.astronomical_unit
+    }
+
+    /// Calculates the intensity of the light from a star with the specified luminosity, at the specified distance (in world units).
+    /// This follows the inverse square law.
+    pub fn illuminance(&self, photometry: &StarPhotometry, luminosity: f32, distance: f32) -> f32 {
+        self.intensity * photometry.flux(luminosity, distance)
+    }
+}
+
+impl Default for StarLight {
+    fn default() -> Self {
+        Self::new(StarLightKind::Point, 5.0)
+    }
+}
+
+impl Component for StarLight {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Creates a child `Light` entity for every `Star`, and keeps its color and intensity in sync with the star.
+#[derive(Debug, Default)]
+pub struct StarLightSystem {
+    /// The light entity which was created for each star entity.
+    lights: HashMap<Entity, Entity>,
+}
+
+impl<'a> System<'a> for StarLightSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Star>,
+        ReadStorage<'a, StarLight>,
+        ReadStorage<'a, Variability>,
+        ReadStorage<'a, Camera>,
+        Read<'a, ActiveCamera>,
+        Read<'a, StarPhotometry>,
+        Read<'a, Time>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Parent>,
+        WriteStorage<'a, Light>,
+    );
+
+    fn run(&mut self, (entities, stars, star_lights, variability, cameras, active_camera, photometry, time, mut transforms, mut parents, mut lights): Self::SystemData) {
+        // Find the camera position, which is needed to orient directional lights.
+        let camera_position = find_camera_position(&entities, &active_camera, &cameras, &transforms);
+
+        // Remove the lights of stars which no longer exist or have opted out.
+        let stale: Vec<Entity> = self.lights
+            .keys()
+            .filter(|star_entity| {
+                !entities.is_alive(**star_entity)
+                    || !stars.contains(**star_entity)
+                    || !star_lights.get(**star_entity).map(|star_light| star_light.enabled).unwrap_or(true)
+            })
+            .copied()
+            .collect();
+        for star_entity in stale {
+            // The light is a child of its star, so the transform hierarchy may have deleted it already.
+            if let Some(light_entity) = self.lights.remove(&star_entity) {
+                if entities.is_alive(light_entity) {
+                    let _ = entities.delete(light_entity);
+                }
+            }
+        }
+
+        let default_star_light = StarLight::default();
+        for (star_entity, star) in (&entities, &stars).join() {
+            let star_light = star_lights.get(star_entity).unwrap_or(&default_star_light);
+            if !star_light.enabled {
+                continue;
+            }
+            let (center, radius) = match transforms.get(star_entity) {
+                Some(transform) => {
+                    let matrix = transform.global_matrix();
+                    (matrix.column(3).xyz(), uniform_scale(matrix))
+                }
+                None => continue,
+            };
+
+            // Variable stars change the brightness and color of their light over time.
+            let sample = variability.get(star_entity).map(|variability| variability.sample(time.absolute_time_seconds())).unwrap_or_default();
+            let color = sample.color(star.color);
+            let luminosity = photometry.luminosity(star, radius) * sample.intensity;
+            let light = match star_light.kind {
+                StarLightKind::Point => Light::Point(PointLight {
+                    color,
+                    // The light falls off with the inverse square of the distance, so we give the intensity at a unit distance.
+                    intensity: star_light.luminous_intensity(&photometry, luminosity),
+                    ..Default::default()
+                }),
+                StarLightKind::Directional => {
+                    let camera_position = camera_position.unwrap_or_else(Vector3::zeros);
+                    let offset = camera_position - center;
+                    let distance = offset.norm();
+                    let direction = if distance > 0.0 { offset / distance } else { -Vector3::y() };
+                    Light::Directional(DirectionalLight {
+                        color,
+                        intensity: star_light.illuminance(&photometry, luminosity, distance.max(radius)),
+                        direction,
+                    })
+                }
+            };
+
+            // Recreate the light if its entity has been deleted from under us.
+            let light_entity = *self.lights
+                .entry(star_entity)
+                .and_modify(|light_entity| if !entities.is_alive(*light_entity) { *light_entity = entities.create() })
+                .or_insert_with(|| entities.create());
+            if !transforms.contains(light_entity) {
+                transforms.insert(light_entity, Transform::default()).expect("Failed to add transform to star light!");
+            }
+            if !parents.contains(light_entity) {
+                parents.insert(light_entity, Parent::new(star_entity)).expect("Failed to add parent to star light!");
+            }
+            lights.insert(light_entity, light).expect("Failed to add light to star light!");
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod sub;
 pub mod pass;
+pub mod light;
+pub mod eclipse;
+pub mod variability;
 use amethyst::{
     assets::{
         PrefabData,
@@ -22,7 +25,11 @@ use serde::{Serialize, Deserialize};
 
 use glsl_layout::*;
 
+use variability::VariabilitySample;
+
 #[derive(Debug, Clone, PartialEq)]
+/// The glow texture of a star.
+/// The resource is used by every star, unless the star entity has its own `StarTexture` component.
 pub struct StarTexture {
     texture: Handle<Texture>,
     tex_id: Option<TextureId>,
@@ -35,6 +42,25 @@ impl StarTexture {
             tex_id: None,
         }
     }
+
+    /// Gets the handle of the glow texture.
+    pub fn texture(&self) -> &Handle<Texture> {
+        &self.texture
+    }
+}
+
+impl Component for StarTexture {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The effective surface temperature of the sun in kelvin.
+pub const SOLAR_TEMPERATURE: f32 = 5778.0;
+
+/// Scales the perceived brightness of a star so that the sun at 1 AU appears close to full brightness.
+const BRIGHTNESS_SCALE: f32 = 2.0;
+
+fn default_temperature() -> f32 {
+    SOLAR_TEMPERATURE
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PrefabData)]
@@ -42,11 +68,22 @@ impl StarTexture {
 pub struct Star {
     #[serde(with = "amethyst::renderer::serde_shim::srgb")]
     pub color: Srgb,
+
+    /// The effective surface temperature of the star in kelvin.
+    /// Together with the radius (the scale of the star's transform), this determines the luminosity of the star.
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
 }
 
 impl Star {
     pub fn new(color: Srgb) -> Self {
-        Self { color }
+        Self { color, temperature: SOLAR_TEMPERATURE }
+    }
+
+    /// Changes the effective surface temperature of the star (in kelvin).
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
     }
 }
 
@@ -60,6 +97,124 @@ impl Component for Star {
     type Storage = DenseVecStorage<Self>;
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// The settings of the glow which is drawn for stars that have no texture.
+/// The glow is made up of a bright core, a soft radial falloff and diffraction spikes.
+pub struct ProceduralGlow {
+    /// The number of diffraction spikes around the star.
+    pub spikes: u32,
+
+    /// The rotation of the spikes in radians.
+    pub rotation: f32,
+
+    /// The width of each spike relative to the size of the glow.
+    pub width: f32,
+
+    /// The brightness of the spikes relative to the core.
+    pub spike_intensity: f32,
+}
+
+impl ProceduralGlow {
+    /// Create a new procedural glow with the specified spikes.
+    pub fn new(spikes: u32, rotation: f32, width: f32) -> Self {
+        Self { spikes, rotation, width, spike_intensity: 0.6 }
+    }
+
+    /// Creates a procedural glow with no diffraction spikes.
+    pub fn without_spikes() -> Self {
+        Self::new(0, 0.0, 0.0)
+    }
+}
+
+impl Default for ProceduralGlow {
+    fn default() -> Self {
+        Self::new(4, std::f32::consts::FRAC_PI_4, 0.015)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct GlowData {
+    spikes: uint,
+    rotation: float,
+    width: float,
+    spike_intensity: float,
+}
+
+impl From<ProceduralGlow> for GlowData {
+    fn from(glow: ProceduralGlow) -> Self {
+        Self {
+            spikes: glow.spikes,
+            rotation: glow.rotation,
+            width: glow.width,
+            spike_intensity: glow.spike_intensity,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// Describes the scale of the world and the camera's exposure, which determine how bright each star appears.
+pub struct StarPhotometry {
+    /// The radius of the sun in world units.
+    pub solar_radius: f32,
+
+    /// The distance from the earth to the sun in world units.
+    pub astronomical_unit: f32,
+
+    /// The exposure of the camera. Higher values make every star appear brighter and larger.
+    pub exposure: f32,
+}
+
+impl StarPhotometry {
+    /// Create a new photometry resource with the specified scale and exposure.
+    pub fn new(solar_radius: f32, astronomical_unit: f32, exposure: f32) -> Self {
+        Self { solar_radius, astronomical_unit, exposure }
+    }
+
+    /// Calculates the luminosity of a star with the specified radius (in world units) relative to the sun.
+    /// This follows the Stefan-Boltzmann law, so it is proportional to the square of the radius and the fourth power of the temperature.
+    pub fn luminosity(&self, star: &Star, radius: f32) -> f32 {
+        let r = radius / self.solar_radius;
+        r * r * (star.temperature / SOLAR_TEMPERATURE).powi(4)
+    }
+
+    /// Calculates the flux received from a star with the specified luminosity at the specified distance (in world units).
+    /// This is relative to the flux of the sun at 1 AU, and follows the inverse square law.
+    pub fn flux(&self, luminosity: f32, distance: f32) -> f32 {
+        let d = distance / self.astronomical_unit;
+        luminosity / (d * d).max(f32::EPSILON)
+    }
+
+    /// Calculates the perceived brightness (from 0.0 to 1.0) of a star with the specified flux.
+    /// The glow size in `star.vert.glsl` follows the same fourth root of the exposed flux.
+    pub fn brightness(&self, flux: f32) -> f32 {
+        1.0 - (-(flux * self.exposure).powf(0.25) * BRIGHTNESS_SCALE).exp()
+    }
+}
+
+impl Default for StarPhotometry {
+    /// A sun with a radius of 1.0 world units, so 1 AU is 215 world units.
+    fn default() -> Self {
+        Self::new(1.0, 215.0, 1.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct PhotometryData {
+    astronomical_unit: float,
+    exposure: float,
+}
+
+impl From<StarPhotometry> for PhotometryData {
+    fn from(photometry: StarPhotometry) -> Self {
+        Self {
+            astronomical_unit: photometry.astronomical_unit,
+            exposure: photometry.exposure,
+        }
+    }
+}
+
 pub const MAX_STARS: usize = 4;
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
@@ -68,14 +223,31 @@ pub(crate) struct StarData {
     pub center: vec3,
     pub radius: float,
     pub color: vec3,
+    pub luminosity: float,
+    pub visibility: float,
+    pub intensity: float,
+    pub transmittance: vec3,
 }
 
 impl StarData {
-    pub(crate) fn new(star: &Star, center: Vector3<f32>, radius: f32) -> Self {
+    pub(crate) fn new(
+        star: &Star,
+        center: Vector3<f32>,
+        radius: f32,
+        luminosity: f32,
+        visibility: f32,
+        sample: VariabilitySample,
+        transmittance: Vector3<f32>,
+    ) -> Self {
+        let color = sample.color(star.color);
         Self {
             center: Into::<[f32; 3]>::into(center).into(),
             radius,
-            color: [star.color.red, star.color.green, star.color.blue].into(),
+            color: [color.red, color.green, color.blue].into(),
+            luminosity,
+            visibility,
+            intensity: sample.intensity,
+            transmittance: Into::<[f32; 3]>::into(transmittance).into(),
         }
     }
 }
@@ -96,4 +268,98 @@ impl StarList {
         }
         Self { stars, count: star_data.len() as u32 }
     }
-}
\ No newline at end of file
+}
+
+/// The maximum number of bodies which shadow the atmospheres, which must match `MAX_OCCLUDERS` in the atmosphere shaders.
+pub(crate) const MAX_OCCLUDERS: usize = crate::planet::MAX_PLANETS;
+
+/// A solid body which casts a shadow, in the shaders which shadow the atmospheres.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct OccluderData {
+    pub center: vec3,
+    pub radius: float,
+}
+
+impl OccluderData {
+    pub(crate) fn new(occluder: &eclipse::Occluder) -> Self {
+        Self {
+            center: Into::<[f32; 3]>::into(occluder.center).into(),
+            radius: occluder.radius,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub(crate) struct OccluderList {
+    count: uint,
+    occluders: [OccluderData; MAX_OCCLUDERS],
+}
+
+impl OccluderList {
+    pub(crate) fn new(occluder_data: &[OccluderData]) -> Self {
+        assert!(occluder_data.len() <= MAX_OCCLUDERS);
+        let mut occluders: [OccluderData; MAX_OCCLUDERS] = Default::default();
+        for (i, data) in occluder_data.iter().enumerate() {
+            occluders[i] = *data;
+        }
+        Self { occluders, count: occluder_data.len() as u32 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-4 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn sun_has_unit_luminosity() {
+        let photometry = StarPhotometry::default();
+        assert!(close(photometry.luminosity(&Star::default(), photometry.solar_radius), 1.0));
+    }
+
+    #[test]
+    fn luminosity_follows_radius_squared_and_temperature_to_the_fourth() {
+        let photometry = StarPhotometry::new(2.0, 430.0, 1.0);
+        let sun = Star::default();
+        let hot = Star::default().with_temperature(SOLAR_TEMPERATURE * 2.0);
+        assert!(close(photometry.luminosity(&sun, 6.0), 9.0));
+        assert!(close(photometry.luminosity(&hot, 2.0), 16.0));
+        assert!(close(photometry.luminosity(&hot, 6.0), 144.0));
+    }
+
+    #[test]
+    fn flux_follows_the_inverse_square_law() {
+        let photometry = StarPhotometry::default();
+        let au = photometry.astronomical_unit;
+        assert!(close(photometry.flux(1.0, au), 1.0));
+        assert!(close(photometry.flux(1.0, au * 2.0), 0.25));
+        assert!(close(photometry.flux(3.0, au * 0.5), 12.0));
+        assert!(photometry.flux(1.0, 0.0).is_finite());
+    }
+
+    #[test]
+    fn brightness_rises_from_zero_towards_one() {
+        let photometry = StarPhotometry::default();
+        assert_eq!(photometry.brightness(0.0), 0.0);
+        let mut previous = 0.0;
+        for &flux in &[1e-6, 1e-3, 1.0, 1e3, 1e6] {
+            let brightness = photometry.brightness(flux);
+            assert!(brightness > previous && brightness < 1.0 + 1e-6);
+            previous = brightness;
+        }
+        // The sun at 1 AU with the default exposure.
+        assert!(close(photometry.brightness(1.0), 1.0 - (-BRIGHTNESS_SCALE).exp()));
+    }
+
+    #[test]
+    fn exposure_scales_the_flux() {
+        let dim = StarPhotometry::new(1.0, 215.0, 1.0);
+        let bright = StarPhotometry::new(1.0, 215.0, 4.0);
+        assert!(close(bright.brightness(0.25), dim.brightness(1.0)));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::ops::Range;
 
 use amethyst::{
@@ -6,7 +7,7 @@ use amethyst::{
         AssetStorage,
     },
     core::ecs::{
-        DispatcherBuilder, World,
+        DispatcherBuilder, World, Entity, Join,
     },
     error::Error,
     renderer::{
@@ -24,17 +25,21 @@ use amethyst::{
             shader::{Shader, SpirvShader},
         },
         submodules::{
+            DynamicUniform,
             FlatEnvironmentSub,
             TextureSub,
         },
         Texture,
         types::Backend, util,
     },
+    shrev::EventChannel,
 };
 
 use super::*;
 use crate::{
     star::sub::*,
+    star::light::*,
+    star::eclipse::*,
 };
 
 use crate::renderutils::*;
@@ -66,6 +71,12 @@ lazy_static::lazy_static! {
         ShaderStageFlags::FRAGMENT,
         "main",
     ).unwrap();
+
+    static ref GLOW_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../shaders/spirv/star_glow.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -97,6 +108,8 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawStarDesc {
             hal::pso::ShaderStageFlags::VERTEX | hal::pso::ShaderStageFlags::FRAGMENT
         )?;
         let tex = TextureSub::new(factory)?;
+        let glow = DynamicUniform::new(factory, hal::pso::ShaderStageFlags::FRAGMENT)?;
+        let photometry = DynamicUniform::new(factory, hal::pso::ShaderStageFlags::VERTEX)?;
         
         // Load billboard mesh.
         let vertex = StaticVertexBuffer::new();
@@ -105,23 +118,35 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawStarDesc {
             subpass,
             framebuffer_width,
             framebuffer_height,
-            vec![env.raw_layout(), stars.raw_layout(), tex.raw_layout()],
+            vec![env.raw_layout(), stars.raw_layout(), tex.raw_layout(), photometry.raw_layout()],
             None,
+            &FRAGMENT,
         )?;
 
-        // let query_pool = factory.device().create_query_pool(
-        //     query::Type::Occlusion,
-        //     1,
-        // );
+        // Stars without a texture use a glow which is generated in the fragment shader.
+        let (glow_pipeline, glow_pipeline_layout) = build_custom_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            vec![env.raw_layout(), stars.raw_layout(), glow.raw_layout(), photometry.raw_layout()],
+            None,
+            &GLOW_FRAGMENT,
+        )?;
 
         Ok(Box::new(DrawStar::<B> {
             pipeline,
             pipeline_layout,
+            glow_pipeline,
+            glow_pipeline_layout,
             env,
             vertex,
             stars,
             tex,
-            // query_pool,
+            glow,
+            photometry,
+            batches: Vec::new(),
+            procedural: 0..0,
         }))
     }
 }
@@ -131,11 +156,18 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawStarDesc {
 pub struct DrawStar<B: Backend> {
     pipeline: B::GraphicsPipeline,
     pipeline_layout: B::PipelineLayout,
+    glow_pipeline: B::GraphicsPipeline,
+    glow_pipeline_layout: B::PipelineLayout,
     env: FlatEnvironmentSub<B>,
     vertex: StaticVertexBuffer<B, PosTex>,
     stars: StarSub<B>,
     tex: TextureSub<B>,
-    // query_pool: B::QueryPool,
+    glow: DynamicUniform<B, GlowData>,
+    photometry: DynamicUniform<B, PhotometryData>,
+    /// The range of star instances which use each texture.
+    batches: Vec<(TextureId, Range<u32>)>,
+    /// The range of star instances which have no texture, and so use the procedural glow.
+    procedural: Range<u32>,
 }
 
 impl<B: Backend> RenderGroup<B, World> for DrawStar<B> {
@@ -149,17 +181,60 @@ impl<B: Backend> RenderGroup<B, World> for DrawStar<B> {
     ) -> PrepareResult {
 
         self.env.process(factory, index, world);
-        self.stars.process(factory, index, world);
 
         // Load any unloaded textures.
         // TODO: make more efficient!
-        if let Some(mut star_texture) = world.try_fetch_mut::<StarTexture>() {
+        let default_tex_id = if let Some(mut star_texture) = world.try_fetch_mut::<StarTexture>() {
             if let Some((texture, _)) = self.tex.insert(factory, world, &star_texture.texture, hal::image::Layout::ShaderReadOnlyOptimal) {
                 star_texture.tex_id = Some(texture);
             }
+            star_texture.tex_id
+        } else {
+            None
+        };
+
+        // Find the texture of each star, falling back to the global texture when the star has no texture of its own (or it hasn't loaded yet).
+        let mut textures: Vec<TextureId> = Vec::new();
+        let mut texture_slots: HashMap<Entity, Option<usize>> = HashMap::new();
+        for (entity, _, star_texture) in (&world.entities(), &world.read_storage::<Star>(), (&world.read_storage::<StarTexture>()).maybe()).join() {
+            let tex_id = star_texture
+                .and_then(|star_texture| self.tex.insert(factory, world, &star_texture.texture, hal::image::Layout::ShaderReadOnlyOptimal))
+                .map(|(texture, _)| texture)
+                .or(default_tex_id);
+            let slot = tex_id.map(|tex_id| {
+                textures.iter().position(|texture| *texture == tex_id).unwrap_or_else(|| {
+                    textures.push(tex_id);
+                    textures.len() - 1
+                })
+            });
+            texture_slots.insert(entity, slot);
         }
         self.tex.maintain(factory, world);
 
+        // Order the stars by texture so that each texture only needs to be bound once.
+        self.stars.process_by(factory, index, world, |entity| texture_slots.get(&entity).copied().flatten());
+        self.batches.clear();
+        self.procedural = 0..0;
+        for (i, entity) in self.stars.entities().iter().enumerate() {
+            let instance = i as u32;
+            if let Some(Some(slot)) = texture_slots.get(entity) {
+                let tex_id = textures[*slot];
+                match self.batches.last_mut() {
+                    Some((batch_tex_id, instances)) if *batch_tex_id == tex_id => instances.end = instance + 1,
+                    _ => self.batches.push((tex_id, instance..instance + 1)),
+                }
+            } else {
+                // Stars without a texture are sorted first, so they are always contiguous.
+                self.procedural.end = instance + 1;
+            }
+        }
+
+        let glow = world.try_fetch::<ProceduralGlow>().map(|glow| *glow).unwrap_or_default();
+        self.glow.write(factory, index, GlowData::from(glow).std140());
+
+        let photometry = world.try_fetch::<StarPhotometry>().map(|photometry| *photometry).unwrap_or_default();
+        self.photometry.write(factory, index, PhotometryData::from(photometry).std140());
+
         self.vertex.prepare(
             factory,
             queue,
@@ -176,22 +251,32 @@ impl<B: Backend> RenderGroup<B, World> for DrawStar<B> {
         mut encoder: RenderPassEncoder<'_, B>,
         index: usize,
         _subpass: hal::pass::Subpass<'_, B>,
-        world: &World,
+        _world: &World,
     ) {
         if !self.stars.is_empty() {
             encoder.bind_graphics_pipeline(&self.pipeline);
             self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
             self.stars.bind(index, &self.pipeline_layout, 1, &mut encoder);
-            if let Some(star_texture) = world.try_fetch::<StarTexture>() {
-                if let Some(texture_id) = star_texture.tex_id {
-                    if self.tex.loaded(texture_id) {
-                        self.tex.bind(&self.pipeline_layout, 2, texture_id, &mut encoder);
-                        unsafe {
-                            self.vertex.draw(&mut encoder, 0..self.stars.count() as u32, index);
-                        }
+            self.photometry.bind(index, &self.pipeline_layout, 3, &mut encoder);
+            for (texture_id, instances) in self.batches.iter() {
+                if self.tex.loaded(*texture_id) {
+                    self.tex.bind(&self.pipeline_layout, 2, *texture_id, &mut encoder);
+                    unsafe {
+                        self.vertex.draw(&mut encoder, instances.clone(), index);
                     }
                 }
             }
+
+            if self.procedural.start != self.procedural.end {
+                encoder.bind_graphics_pipeline(&self.glow_pipeline);
+                self.env.bind(index, &self.glow_pipeline_layout, 0, &mut encoder);
+                self.stars.bind(index, &self.glow_pipeline_layout, 1, &mut encoder);
+                self.glow.bind(index, &self.glow_pipeline_layout, 2, &mut encoder);
+                self.photometry.bind(index, &self.glow_pipeline_layout, 3, &mut encoder);
+                unsafe {
+                    self.vertex.draw(&mut encoder, self.procedural.clone(), index);
+                }
+            }
         }
     }
 
@@ -201,6 +286,10 @@ impl<B: Backend> RenderGroup<B, World> for DrawStar<B> {
             factory
                 .device()
                 .destroy_pipeline_layout(self.pipeline_layout);
+            factory.device().destroy_graphics_pipeline(self.glow_pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.glow_pipeline_layout);
         }
     }
 }
@@ -212,6 +301,7 @@ fn build_custom_pipeline<B: Backend>(
     framebuffer_height: u32,
     layouts: Vec<&B::DescriptorSetLayout>,
     push_constant: Option<(hal::pso::ShaderStageFlags, Range<u32>)>,
+    fragment: &SpirvShader,
 ) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
     let pipeline_layout = unsafe {
         factory
@@ -220,7 +310,7 @@ fn build_custom_pipeline<B: Backend>(
     }?;
     // Load the shaders
     let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { FRAGMENT.module(factory).unwrap() };
+    let shader_fragment = unsafe { fragment.module(factory).unwrap() };
 
     // Build the pipeline
     let pipes = PipelinesBuilder::new()
@@ -256,17 +346,41 @@ fn build_custom_pipeline<B: Backend>(
         Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
     }
 }
+/// A [RenderPlugin] which draws the glow of every `Star`.
+/// If no texture is provided (or it fails to load), the glow is generated procedurally.
 #[derive(Debug, Default)]
 pub struct StarRender {
-    flash_path: String,
+    flash_path: Option<String>,
+    glow: ProceduralGlow,
+    photometry: StarPhotometry,
 }
 
 impl StarRender {
+    /// Creates a star renderer which uses the texture at the specified asset path for the glow of every star.
     pub fn new(flash_path: impl Into<String>) -> Self {
         Self {
-            flash_path: flash_path.into(),
+            flash_path: Some(flash_path.into()),
+            glow: ProceduralGlow::default(),
+            photometry: StarPhotometry::default(),
         }
     }
+
+    /// Creates a star renderer which generates the glow of every star, so no texture asset is required.
+    pub fn procedural() -> Self {
+        Self::default()
+    }
+
+    /// Changes the settings of the procedural glow.
+    pub fn with_glow(mut self, glow: ProceduralGlow) -> Self {
+        self.glow = glow;
+        self
+    }
+
+    /// Changes the scale of the world and the exposure used to determine the brightness of each star.
+    pub fn with_photometry(mut self, photometry: StarPhotometry) -> Self {
+        self.photometry = photometry;
+        self
+    }
 }
 
 
@@ -274,23 +388,37 @@ impl<B: Backend> RenderPlugin<B> for StarRender {
     fn on_build<'a, 'b>(
         &mut self,
         world: &mut World,
-        _builder: &mut DispatcherBuilder<'a, 'b>,
+        builder: &mut DispatcherBuilder<'a, 'b>,
     ) -> Result<(), Error> {
-        let tex = {
-            if !world.has_value::<AssetStorage::<Texture>>() {
-                world.insert(AssetStorage::<Texture>::new());
-            }
-            let loader = world.read_resource::<Loader>();
-            loader.load(
-                &self.flash_path,
-                amethyst::renderer::formats::texture::ImageFormat::default(),
-                (),
-                &world.read_resource::<AssetStorage<Texture>>(),
-            )
-        };
+        // Stars illuminate the scene through regular lights unless they opt out with a `StarLight` component.
+        builder.add(StarLightSystem::default(), "star_light_system", &[]);
+        // The fraction of each star hidden behind planets is calculated on the CPU so that it is available to gameplay.
+        builder.add(StarOcclusionSystem, "star_occlusion_system", &[]);
+
+        if let Some(flash_path) = self.flash_path.as_ref() {
+            let tex = {
+                if !world.has_value::<AssetStorage::<Texture>>() {
+                    world.insert(AssetStorage::<Texture>::new());
+                }
+                let loader = world.read_resource::<Loader>();
+                loader.load(
+                    flash_path,
+                    amethyst::renderer::formats::texture::ImageFormat::default(),
+                    (),
+                    &world.read_resource::<AssetStorage<Texture>>(),
+                )
+            };
+            world.insert(StarTexture::new(tex));
+        }
 
-        world.insert(StarTexture::new(tex));
+        world.insert(self.glow);
+        world.insert(self.photometry);
         world.register::<crate::Star>();
+        world.register::<crate::star::variability::Variability>();
+        world.register::<StarTexture>();
+        world.register::<StarLight>();
+        world.insert(StarOcclusion::default());
+        world.insert(EventChannel::<EclipseEvent>::new());
         Ok(())
     }
 
@@ -1,8 +1,10 @@
 use amethyst::{
     core::{
+        timing::Time,
         transform::Transform,
         math::{
             Matrix4,
+            Vector3,
             Vector4,
         }
     },
@@ -19,38 +21,52 @@ use amethyst::{
 };
 
 use super::*;
+use super::eclipse::{Occluder, StarOcclusion};
+use super::variability::Variability;
+use crate::{
+    planet::scattering::{ScatteringSphere, star_transmittance},
+    renderutils::{camera_position, uniform_scale},
+};
 
 #[derive(Debug)]
 pub(crate) struct StarSub<B: Backend> {
     uniform: DynamicUniform<B, StarList>,
     data: StarList,
+    entities: Vec<Entity>,
 }
 
 impl<B: Backend> StarSub<B> {
     pub fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
         let uniform = DynamicUniform::new(factory, flags)?;
-        Ok(Self { uniform, data: StarList::default() })
+        Ok(Self { uniform, data: StarList::default(), entities: Vec::new() })
     }
 
     pub fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
-        let mut star_list: Vec<StarData> = Vec::new();
-        for (star, transform) in (&world.read_storage::<Star>(), &world.read_storage::<Transform>()).join() {
-            let matrix: Matrix4<f32> = *transform.global_matrix();
-            let translation: Vector4<f32> = matrix.column(3).into();
-            if matrix.column(0)[0].abs() == matrix.column(1)[1].abs() && matrix.column(1)[1].abs() == matrix.column(2)[2].abs() {
-                // The scale is uniform - this is good.
-                star_list.push(StarData::new(&star, translation.xyz(), matrix.column(0)[0].abs()));
-            } else {
-                // The scale is non uniform, which means that we cannot extract a radius for the star.
-                panic!("Non uniform scale provided for star! We need a uniform scale (x, y, z components of scale are the same) to determine the radius of the star, as it assumed to be spherical.");
-            }
-        }
-        self.data = StarList::new(star_list.as_slice());
+        let stars = gather_stars(world);
+        self.write(factory, index, stars);
+    }
+
+    /// Processes the stars, ordering them in the list by the specified key.
+    /// This allows stars which share resources to be drawn in a single batch.
+    pub fn process_by<K: Ord>(&mut self, factory: &Factory<B>, index: usize, world: &World, mut key: impl FnMut(Entity) -> K) {
+        let mut stars: Vec<(K, Entity, StarData)> = gather_stars(world)
+            .into_iter()
+            .map(|(entity, data)| (key(entity), entity, data))
+            .collect();
+        // The sort is stable so stars with the same key keep their order.
+        stars.sort_by(|a, b| a.0.cmp(&b.0));
+        self.write(factory, index, stars.into_iter().map(|(_, entity, data)| (entity, data)).collect());
+    }
+
+    fn write(&mut self, factory: &Factory<B>, index: usize, stars: Vec<(Entity, StarData)>) {
+        self.entities = stars.iter().map(|(entity, _)| *entity).collect();
+        let star_data: Vec<StarData> = stars.into_iter().map(|(_, data)| data).collect();
+        self.data = StarList::new(star_data.as_slice());
         self.uniform.write(factory, index, self.data.std140());
     }
 
     pub fn raw_layout(&self) -> &B::DescriptorSetLayout {
-        &self.uniform.raw_layout()
+        self.uniform.raw_layout()
     }
 
     pub fn bind(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
@@ -60,9 +76,71 @@ impl<B: Backend> StarSub<B> {
     pub fn is_empty(&self) -> bool {
         self.data.count == 0
     }
-    
-    pub fn count(&self) -> usize {
-        self.data.count as usize
+
+    /// Gets the star entities in the order that they were written to the list.
+    pub fn entities(&self) -> &[Entity] {
+        self.entities.as_slice()
     }
 }
 
+
+/// Gets the data of every star in the world, in the order of the join.
+fn gather_stars(world: &World) -> Vec<(Entity, StarData)> {
+    let photometry = world.try_fetch::<StarPhotometry>().map(|photometry| *photometry).unwrap_or_default();
+    let occlusion = world.try_fetch::<StarOcclusion>();
+    let time = world.try_fetch::<Time>().map(|time| time.absolute_time_seconds()).unwrap_or(0.0);
+    let variability = world.read_storage::<Variability>();
+    // Stars seen through an atmosphere are dimmed and reddened by it.
+    let camera = camera_position(world);
+    let spheres = ScatteringSphere::gather(world);
+    let mut stars = Vec::new();
+    for (entity, star, transform) in (&world.entities(), &world.read_storage::<Star>(), &world.read_storage::<Transform>()).join() {
+        let matrix: Matrix4<f32> = *transform.global_matrix();
+        let translation: Vector4<f32> = matrix.column(3).into();
+        // Rotated stars keep their size, and the glow matches the radius used by `StarOcclusion`.
+        let radius = uniform_scale(&matrix);
+        let luminosity = photometry.luminosity(star, radius);
+        let visibility = occlusion.as_ref().map(|occlusion| occlusion.visible_fraction(entity)).unwrap_or(1.0);
+        let sample = variability.get(entity).map(|variability| variability.sample(time)).unwrap_or_default();
+        let transmittance = match camera {
+            Some(camera) => star_transmittance(camera, translation.xyz(), spheres.as_slice()),
+            None => Vector3::new(1.0, 1.0, 1.0),
+        };
+        stars.push((entity, StarData::new(star, translation.xyz(), radius, luminosity, visibility, sample, transmittance)));
+    }
+    stars
+}
+
+/// The planets and moons which cast shadows on the atmospheres, as a uniform list of spheres.
+#[derive(Debug)]
+pub(crate) struct OccluderSub<B: Backend> {
+    uniform: DynamicUniform<B, OccluderList>,
+    data: OccluderList,
+}
+
+impl<B: Backend> OccluderSub<B> {
+    pub fn new(factory: &Factory<B>, flags: hal::pso::ShaderStageFlags) -> Result<Self, failure::Error> {
+        let uniform = DynamicUniform::new(factory, flags)?;
+        Ok(Self { uniform, data: OccluderList::default() })
+    }
+
+    pub fn process(&mut self, factory: &Factory<B>, index: usize, world: &World) {
+        let mut occluders = Occluder::gather(world);
+        // If there are too many bodies, the ones which look largest from the camera are kept.
+        if let Some(camera) = camera_position(world) {
+            let size = |occluder: &Occluder| occluder.radius / (occluder.center - camera).norm().max(1e-6);
+            occluders.sort_by(|a, b| size(b).partial_cmp(&size(a)).unwrap_or(std::cmp::Ordering::Equal));
+        }
+        let occluder_data: Vec<OccluderData> = occluders.iter().take(MAX_OCCLUDERS).map(OccluderData::new).collect();
+        self.data = OccluderList::new(occluder_data.as_slice());
+        self.uniform.write(factory, index, self.data.std140());
+    }
+
+    pub fn raw_layout(&self) -> &B::DescriptorSetLayout {
+        self.uniform.raw_layout()
+    }
+
+    pub fn bind(&mut self, index: usize, pipeline_layout: &B::PipelineLayout, binding_id: u32, encoder: &mut RenderPassEncoder<B>) {
+        self.uniform.bind(index, pipeline_layout, binding_id, encoder);
+    }
+}
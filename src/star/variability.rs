@@ -0,0 +1,301 @@
+use std::f32::consts::PI;
+
+use amethyst::{
+    assets::PrefabData,
+    derive::PrefabData,
+    ecs::prelude::*,
+    error::Error,
+    renderer::palette::Srgb,
+};
+
+use serde::{Serialize, Deserialize};
+
+use crate::renderutils::unit_hash;
+
+/// The color which a star shifts towards as it heats up during a flare or pulsation.
+const HOT_TINT: [f32; 3] = [0.75, 0.85, 1.0];
+
+/// The number of previous flare intervals which are checked for flares that are still decaying.
+const FLARE_HISTORY: i64 = 8;
+
+/// The fraction of the decay time which a flare takes to reach its peak.
+const FLARE_RISE: f32 = 0.1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// The way in which the brightness of a variable star changes over time.
+pub enum VariabilityMode {
+    /// A smooth sinusoidal pulsation.
+    Sinusoidal {
+        /// The time (in seconds) of one pulsation.
+        period: f32,
+        /// The fraction by which the brightness rises and falls.
+        amplitude: f32,
+    },
+    /// A Cepheid-like pulsation, which brightens quickly and fades slowly.
+    Cepheid {
+        /// The time (in seconds) of one pulsation.
+        period: f32,
+        /// The fraction by which the brightness rises and falls.
+        amplitude: f32,
+        /// The fraction of the period spent brightening.
+        rise: f32,
+    },
+    /// A pair of stars which eclipse each other, causing two dips in brightness each orbit.
+    EclipsingBinary {
+        /// The time (in seconds) of one orbit.
+        period: f32,
+        /// The fraction of the light blocked during the primary eclipse.
+        primary_depth: f32,
+        /// The fraction of the light blocked during the secondary eclipse, half an orbit later.
+        secondary_depth: f32,
+        /// The fraction of the orbit taken by each eclipse.
+        duration: f32,
+    },
+    /// Random flares which brighten the star suddenly before fading away, like a red dwarf.
+    /// The flares are generated from a seed so that every system sees the same flares at the same time.
+    Flares {
+        /// The average number of flares per second.
+        rate: f32,
+        /// The extra brightness at the peak of a flare, relative to the star's normal brightness.
+        peak: f32,
+        /// The time (in seconds) taken for a flare to fade by a factor of e.
+        decay: f32,
+        /// The seed used to place the flares.
+        seed: u32,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The state of a variable star at a single point in time.
+pub struct VariabilitySample {
+    /// The brightness of the star relative to its normal brightness.
+    pub intensity: f32,
+
+    /// How far the star's color has shifted towards a hotter (bluer) color, from 0.0 to 1.0.
+    pub heat: f32,
+}
+
+impl VariabilitySample {
+    /// Applies the color shift of this sample to the color of a star.
+    pub fn color(&self, color: Srgb) -> Srgb {
+        let heat = self.heat.clamp(0.0, 1.0);
+        Srgb::new(
+            color.red + (HOT_TINT[0] - color.red) * heat,
+            color.green + (HOT_TINT[1] - color.green) * heat,
+            color.blue + (HOT_TINT[2] - color.blue) * heat,
+        )
+    }
+}
+
+impl Default for VariabilitySample {
+    fn default() -> Self {
+        Self { intensity: 1.0, heat: 0.0 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// Makes the brightness and color of a `Star` change over time.
+pub struct Variability {
+    /// The light curve of the star.
+    pub mode: VariabilityMode,
+
+    /// The fraction of a period by which the light curve is shifted, so that stars with the same mode don't change in sync.
+    #[serde(default)]
+    pub phase: f32,
+}
+
+impl Variability {
+    /// Create a new variability component with the specified light curve.
+    pub fn new(mode: VariabilityMode) -> Self {
+        Self { mode, phase: 0.0 }
+    }
+
+    /// Creates a star with a smooth sinusoidal pulsation.
+    pub fn sinusoidal(period: f32, amplitude: f32) -> Self {
+        Self::new(VariabilityMode::Sinusoidal { period, amplitude })
+    }
+
+    /// Creates a Cepheid-like star which brightens over 30% of each period.
+    pub fn cepheid(period: f32, amplitude: f32) -> Self {
+        Self::new(VariabilityMode::Cepheid { period, amplitude, rise: 0.3 })
+    }
+
+    /// Creates an eclipsing binary star.
+    pub fn eclipsing_binary(period: f32, primary_depth: f32, secondary_depth: f32, duration: f32) -> Self {
+        Self::new(VariabilityMode::EclipsingBinary { period, primary_depth, secondary_depth, duration })
+    }
+
+    /// Creates a flare star.
+    pub fn flares(rate: f32, peak: f32, decay: f32, seed: u32) -> Self {
+        Self::new(VariabilityMode::Flares { rate, peak, decay, seed })
+    }
+
+    /// Shifts the light curve by a fraction of a period.
+    pub fn with_phase(mut self, phase: f32) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    /// Calculates the brightness and color shift of the star at the specified time (in seconds).
+    pub fn sample(&self, time: f64) -> VariabilitySample {
+        match self.mode {
+            VariabilityMode::Sinusoidal { period, amplitude } => {
+                let wave = (2.0 * PI * self.cycle(time, period)).sin();
+                // An amplitude above 1.0 would otherwise make the star darker than black.
+                VariabilitySample { intensity: (1.0 + amplitude * wave).max(0.0), heat: amplitude * wave.max(0.0) }
+            }
+            VariabilityMode::Cepheid { period, amplitude, rise } => {
+                let phase = self.cycle(time, period);
+                let rise = rise.clamp(f32::EPSILON, 1.0 - f32::EPSILON);
+                let level = if phase < rise { phase / rise } else { 1.0 - (phase - rise) / (1.0 - rise) };
+                let wave = level * 2.0 - 1.0;
+                VariabilitySample { intensity: (1.0 + amplitude * wave).max(0.0), heat: amplitude * wave.max(0.0) }
+            }
+            VariabilityMode::EclipsingBinary { period, primary_depth, secondary_depth, duration } => {
+                let phase = self.cycle(time, period);
+                let half_width = (duration * 0.5).max(f32::EPSILON);
+                // The primary eclipse is at the start of the orbit, and the secondary is half an orbit later.
+                let dip = |center: f32| {
+                    let offset = (phase - center).abs().min(1.0 - (phase - center).abs());
+                    let x = offset / half_width;
+                    if x < 1.0 { 1.0 - x * x } else { 0.0 }
+                };
+                let blocked = primary_depth * dip(0.0) + secondary_depth * dip(0.5);
+                VariabilitySample { intensity: (1.0 - blocked).max(0.0), heat: 0.0 }
+            }
+            VariabilityMode::Flares { rate, peak, decay, seed } => {
+                let flare = flare_level(time + f64::from(self.phase * decay), rate, decay, seed);
+                VariabilitySample { intensity: 1.0 + peak * flare, heat: flare }
+            }
+        }
+    }
+
+    /// Calculates the fraction (from 0.0 to 1.0) through the current period.
+    fn cycle(&self, time: f64, period: f32) -> f32 {
+        if period <= 0.0 {
+            return 0.0;
+        }
+        (time / f64::from(period) + f64::from(self.phase)).rem_euclid(1.0) as f32
+    }
+}
+
+impl Component for Variability {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Calculates the brightness of the flares (where 1.0 is the peak of a single flare) at the specified time.
+/// Time is split into intervals as long as the decay, and each interval holds at most one flare.
+fn flare_level(time: f64, rate: f32, decay: f32, seed: u32) -> f32 {
+    if rate <= 0.0 || decay <= 0.0 {
+        return 0.0;
+    }
+    let interval = f64::from(decay);
+    let chance = (rate * decay).min(1.0);
+    let current = (time / interval).floor() as i64;
+    let mut level = 0.0;
+    for index in (current - FLARE_HISTORY)..=current {
+        if hash(seed, index, 0) >= chance {
+            continue;
+        }
+        let start = (index as f64 + f64::from(hash(seed, index, 1))) * interval;
+        let age = (time - start) as f32;
+        if age < 0.0 {
+            continue;
+        }
+        let rise = FLARE_RISE * decay;
+        level += if age < rise { age / rise } else { (-(age - rise) / decay).exp() };
+    }
+    level
+}
+
+/// Hashes the seed, flare interval and channel into a value from 0.0 to 1.0.
+fn hash(seed: u32, index: i64, channel: u32) -> f32 {
+    unit_hash(index as u64, seed, channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intensities(variability: &Variability, start: f64, end: f64) -> Vec<f32> {
+        let steps = 1000;
+        (0..=steps).map(|i| variability.sample(start + (end - start) * i as f64 / steps as f64).intensity).collect()
+    }
+
+    fn range(samples: &[f32]) -> (f32, f32) {
+        samples.iter().fold((f32::MAX, f32::MIN), |(min, max), &x| (min.min(x), max.max(x)))
+    }
+
+    #[test]
+    fn sinusoidal_repeats_every_period_within_its_amplitude() {
+        let variability = Variability::sinusoidal(4.0, 0.2).with_phase(0.3);
+        for &time in &[0.0, 1.3, 2.7] {
+            let a = variability.sample(time).intensity;
+            let b = variability.sample(time + 4.0).intensity;
+            assert!((a - b).abs() < 1e-4);
+        }
+        let (min, max) = range(&intensities(&variability, 0.0, 4.0));
+        assert!((min - 0.8).abs() < 1e-3 && (max - 1.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cepheid_rises_quickly_and_fades_slowly() {
+        let variability = Variability::cepheid(10.0, 0.5);
+        assert!((variability.sample(0.0).intensity - 0.5).abs() < 1e-4);
+        assert!((variability.sample(3.0).intensity - 1.5).abs() < 1e-4);
+        assert!((variability.sample(10.0).intensity - variability.sample(0.0).intensity).abs() < 1e-4);
+        // Brightening takes 30% of the period, so the star is still fading halfway through.
+        assert!(variability.sample(5.0).intensity < variability.sample(4.0).intensity);
+        let (min, max) = range(&intensities(&variability, 0.0, 10.0));
+        assert!(min >= 0.5 - 1e-4 && max <= 1.5 + 1e-4);
+    }
+
+    #[test]
+    fn pulsations_never_go_below_black() {
+        for variability in &[Variability::sinusoidal(4.0, 1.5), Variability::cepheid(10.0, 1.5)] {
+            let (min, max) = range(&intensities(variability, 0.0, 10.0));
+            assert!(min == 0.0 && max > 2.0);
+        }
+    }
+
+    #[test]
+    fn eclipsing_binary_dips_twice_per_orbit() {
+        let variability = Variability::eclipsing_binary(8.0, 0.6, 0.2, 0.1);
+        assert!((variability.sample(0.0).intensity - 0.4).abs() < 1e-4);
+        assert!((variability.sample(4.0).intensity - 0.8).abs() < 1e-4);
+        assert_eq!(variability.sample(2.0).intensity, 1.0);
+        assert!((variability.sample(8.0).intensity - 0.4).abs() < 1e-4);
+        let (min, max) = range(&intensities(&variability, 0.0, 8.0));
+        assert!(min >= 0.4 - 1e-4 && max <= 1.0);
+    }
+
+    #[test]
+    fn flares_are_deterministic_per_seed() {
+        let a = Variability::flares(0.5, 3.0, 2.0, 7);
+        let b = Variability::flares(0.5, 3.0, 2.0, 7);
+        let c = Variability::flares(0.5, 3.0, 2.0, 8);
+        let samples_a = intensities(&a, 0.0, 200.0);
+        assert_eq!(samples_a, intensities(&b, 0.0, 200.0));
+        assert_ne!(samples_a, intensities(&c, 0.0, 200.0));
+        // The star never dims, and flares do happen.
+        assert!(samples_a.iter().all(|&intensity| intensity >= 1.0));
+        assert!(samples_a.iter().any(|&intensity| intensity > 1.5));
+    }
+
+    #[test]
+    fn flare_hash_is_uniform_and_stable() {
+        let values: Vec<f32> = (0..10_000).map(|index| hash(42, index, 0)).collect();
+        assert!(values.iter().all(|x| (0.0..1.0).contains(x)));
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        assert!((mean - 0.5).abs() < 0.02);
+        assert_eq!(hash(42, 17, 1), hash(42, 17, 1));
+        assert_ne!(hash(42, 17, 0), hash(42, 17, 1));
+    }
+
+    #[test]
+    fn no_flares_without_a_rate() {
+        let variability = Variability::flares(0.0, 3.0, 2.0, 1);
+        assert!(intensities(&variability, 0.0, 50.0).iter().all(|&intensity| intensity == 1.0));
+    }
+}
@@ -0,0 +1,353 @@
+use std::f32::consts::FRAC_PI_4;
+
+use amethyst::{
+    core::math::Vector3,
+    renderer::{
+        palette::Srgb,
+        rendy::{
+            hal,
+            mesh::{MeshBuilder, Normal, Position, Tangent, TexCoord},
+            texture::{TextureBuilder, pixel::Rgba8Srgb},
+        },
+        types::{MeshData, TextureData},
+    },
+};
+
+use crate::planet::{terrain::Terrain, Planet};
+
+/// One of the six faces of the cube which is projected onto the planet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    /// Every face of the cube.
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// Gets the normal of the face and the two axes across it.
+    /// The axes are chosen so that `u.cross(v) == normal`, which means that the triangles wind counter clockwise from outside.
+    pub fn axes(self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        match self {
+            CubeFace::PositiveX => (Vector3::x(), Vector3::y(), Vector3::z()),
+            CubeFace::NegativeX => (-Vector3::x(), Vector3::z(), Vector3::y()),
+            CubeFace::PositiveY => (Vector3::y(), Vector3::z(), Vector3::x()),
+            CubeFace::NegativeY => (-Vector3::y(), Vector3::x(), Vector3::z()),
+            CubeFace::PositiveZ => (Vector3::z(), Vector3::x(), Vector3::y()),
+            CubeFace::NegativeZ => (-Vector3::z(), Vector3::y(), Vector3::x()),
+        }
+    }
+
+    /// Gets the direction from the center of the planet through the point (u, v) on this face, where u and v are from -1.0 to 1.0.
+    pub fn direction(self, u: f32, v: f32) -> Vector3<f32> {
+        let (normal, axis_u, axis_v) = self.axes();
+        cube_to_sphere(normal + axis_u * u + axis_v * v)
+    }
+}
+
+/// Projects a point on the surface of the unit cube onto the unit sphere.
+/// This spreads the vertices more evenly than normalizing, so the chunks near the corners of the cube aren't squashed.
+pub fn cube_to_sphere(p: Vector3<f32>) -> Vector3<f32> {
+    let (x2, y2, z2) = (p.x * p.x, p.y * p.y, p.z * p.z);
+    let sphere = Vector3::new(
+        p.x * (1.0 - y2 / 2.0 - z2 / 2.0 + y2 * z2 / 3.0).max(0.0).sqrt(),
+        p.y * (1.0 - z2 / 2.0 - x2 / 2.0 + z2 * x2 / 3.0).max(0.0).sqrt(),
+        p.z * (1.0 - x2 / 2.0 - y2 / 2.0 + x2 * y2 / 3.0).max(0.0).sqrt(),
+    );
+    sphere.normalize()
+}
+
+/// Identifies a chunk in the quadtree of one of the faces of the cube.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    /// The face of the cube which the chunk is on.
+    pub face: CubeFace,
+
+    /// The depth of the chunk in the quadtree, where the whole face is level 0.
+    pub level: u8,
+
+    /// The column of the chunk within its level (0 to 2^level - 1).
+    pub x: u32,
+
+    /// The row of the chunk within its level (0 to 2^level - 1).
+    pub y: u32,
+}
+
+impl ChunkKey {
+    /// Gets the chunk which covers a whole face.
+    pub fn root(face: CubeFace) -> Self {
+        Self { face, level: 0, x: 0, y: 0 }
+    }
+
+    /// Gets the four chunks which this chunk is split into.
+    pub fn children(&self) -> [ChunkKey; 4] {
+        let child = |dx: u32, dy: u32| ChunkKey { face: self.face, level: self.level + 1, x: self.x * 2 + dx, y: self.y * 2 + dy };
+        [child(0, 0), child(1, 0), child(0, 1), child(1, 1)]
+    }
+
+    /// Gets the width of the chunk on the face of the cube, where the whole face is 2.0 wide.
+    pub fn size(&self) -> f32 {
+        2.0 / (1u64 << self.level) as f32
+    }
+
+    /// Gets the approximate width of the chunk on the surface of a sphere with the specified radius.
+    pub fn width(&self, radius: f32) -> f32 {
+        // The whole face covers a quarter of the circumference.
+        self.size() * FRAC_PI_4 * radius
+    }
+
+    /// Gets the direction from the center of the planet through the point (s, t) of this chunk, where s and t are from 0.0 to 1.0.
+    pub fn direction(&self, s: f32, t: f32) -> Vector3<f32> {
+        let size = self.size();
+        self.face.direction(-1.0 + (self.x as f32 + s) * size, -1.0 + (self.y as f32 + t) * size)
+    }
+}
+
+/// The vertices of a chunk, relative to the center of the chunk so that they stay precise on large planets.
+#[derive(Debug, Clone)]
+pub struct ChunkGeometry {
+    /// The center of the chunk relative to the center of the planet.
+    pub center: Vector3<f32>,
+    pub positions: Vec<Position>,
+    pub normals: Vec<Normal>,
+    pub tangents: Vec<Tangent>,
+    pub tex_coords: Vec<TexCoord>,
+    pub indices: Vec<u32>,
+    /// The distance from the center of the chunk to its furthest vertex.
+    pub bounding_radius: f32,
+}
+
+impl ChunkGeometry {
+    /// Builds a chunk of the surface of a planet, including its terrain.
+    pub fn planet(key: ChunkKey, planet: &Planet, terrain: Option<&Terrain>, resolution: u32, skirt_depth: f32) -> Self {
+        Self::build(key, resolution, skirt_depth, |direction| planet.surface_point(terrain, direction))
+    }
+
+    /// Builds a chunk of a smooth sphere with the specified radius.
+    pub fn sphere(key: ChunkKey, radius: f32, resolution: u32, skirt_depth: f32) -> Self {
+        Self::build(key, resolution, skirt_depth, |direction| direction * radius)
+    }
+
+    /// Builds a chunk where `surface` maps a direction from the center of the planet to the point on the surface in that direction.
+    /// The chunk has `resolution` quads along each side, and a skirt which hangs `skirt_depth` (relative to the width of the chunk) below each edge.
+    pub fn build<F>(key: ChunkKey, resolution: u32, skirt_depth: f32, surface: F) -> Self
+        where
+            F: Fn(Vector3<f32>) -> Vector3<f32>,
+    {
+        let quads = resolution.max(1) as usize;
+        let n = quads + 1;
+        let point = |s: f32, t: f32| surface(key.direction(s, t));
+        let center = point(0.5, 0.5);
+        // The normals are found from the neighbouring points, which also works across the edges of the chunk.
+        let eps = 0.5 / quads as f32;
+
+        let mut geometry = ChunkGeometry {
+            center,
+            positions: Vec::with_capacity(n * n + 4 * quads),
+            normals: Vec::with_capacity(n * n + 4 * quads),
+            tangents: Vec::with_capacity(n * n + 4 * quads),
+            tex_coords: Vec::with_capacity(n * n + 4 * quads),
+            indices: Vec::with_capacity(6 * quads * quads + 24 * quads),
+            bounding_radius: 0.0,
+        };
+
+        for j in 0..n {
+            for i in 0..n {
+                let s = i as f32 / quads as f32;
+                let t = j as f32 / quads as f32;
+                let du = point(s + eps, t) - point(s - eps, t);
+                let dv = point(s, t + eps) - point(s, t - eps);
+                geometry.push_vertex(point(s, t), du.cross(&dv).normalize(), du.normalize(), [s, t]);
+            }
+        }
+        for j in 0..quads {
+            for i in 0..quads {
+                let a = (j * n + i) as u32;
+                let b = a + 1;
+                let c = a + n as u32 + 1;
+                let d = a + n as u32;
+                geometry.indices.extend_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+
+        // The skirt goes around the edge counter clockwise (as seen from outside), so that it faces away from the chunk.
+        let perimeter: Vec<usize> = (0..quads)
+            .chain((0..quads).map(|j| j * n + quads))
+            .chain((0..quads).map(|i| quads * n + quads - i))
+            .chain((0..quads).map(|j| (quads - j) * n))
+            .collect();
+        let skirt = key.width(center.norm()) * skirt_depth;
+        let first_skirt = geometry.positions.len() as u32;
+        for vertex in perimeter.iter() {
+            let position = Vector3::from(geometry.positions[*vertex].0) + center;
+            let down = position.normalize() * skirt;
+            let (normal, tangent, tex_coord) = (geometry.normals[*vertex].0, geometry.tangents[*vertex].0, geometry.tex_coords[*vertex].0);
+            geometry.push_vertex(position - down, Vector3::from(normal), Vector3::new(tangent[0], tangent[1], tangent[2]), tex_coord);
+        }
+        for k in 0..perimeter.len() {
+            let next = (k + 1) % perimeter.len();
+            let (a, b) = (perimeter[k] as u32, perimeter[next] as u32);
+            let (a_skirt, b_skirt) = (first_skirt + k as u32, first_skirt + next as u32);
+            geometry.indices.extend_from_slice(&[a, a_skirt, b, b, a_skirt, b_skirt]);
+        }
+
+        geometry
+    }
+
+    fn push_vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, tangent: Vector3<f32>, tex_coord: [f32; 2]) {
+        let local = position - self.center;
+        self.bounding_radius = self.bounding_radius.max(local.norm());
+        self.positions.push(Position(local.into()));
+        self.normals.push(Normal(normal.into()));
+        self.tangents.push(Tangent([tangent.x, tangent.y, tangent.z, 1.0]));
+        self.tex_coords.push(TexCoord(tex_coord));
+    }
+
+    /// Converts the chunk into mesh data, which can be loaded with the `Loader`.
+    pub fn to_mesh_data(&self) -> MeshData {
+        MeshBuilder::new()
+            .with_vertices(self.positions.clone())
+            .with_vertices(self.normals.clone())
+            .with_vertices(self.tangents.clone())
+            .with_vertices(self.tex_coords.clone())
+            .with_indices(self.indices.clone())
+            .into()
+    }
+}
+
+/// Generates the albedo texture of a chunk, which is `size` texels wide and matches the texture coordinates of `ChunkGeometry`.
+/// `albedo` maps a direction from the center of the planet to the color of the surface in that direction.
+pub fn chunk_albedo<F>(key: ChunkKey, size: u32, albedo: F) -> TextureData
+    where
+        F: Fn(Vector3<f32>) -> Srgb,
+{
+    let size = size.max(1);
+    let mut texels = Vec::with_capacity((size * size) as usize);
+    for j in 0..size {
+        for i in 0..size {
+            let s = (i as f32 + 0.5) / size as f32;
+            let t = (j as f32 + 0.5) / size as f32;
+            let color = albedo(key.direction(s, t));
+            let to_byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            texels.push(Rgba8Srgb { repr: [to_byte(color.red), to_byte(color.green), to_byte(color.blue), 255] });
+        }
+    }
+    let builder = TextureBuilder::new()
+        .with_kind(hal::image::Kind::D2(size, size, 1, 1))
+        .with_view_kind(hal::image::ViewKind::D2)
+        .with_data_width(size)
+        .with_data_height(size)
+        .with_sampler_info(hal::image::SamplerInfo::new(hal::image::Filter::Linear, hal::image::WrapMode::Clamp))
+        .with_data(texels);
+    TextureData(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUADS: u32 = 4;
+
+    /// The positions of the vertices of a chunk relative to the center of the planet, without its skirt.
+    fn surface_points(geometry: &ChunkGeometry) -> Vec<Vector3<f32>> {
+        let n = (QUADS + 1) * (QUADS + 1);
+        geometry.positions[..n as usize].iter().map(|p| Vector3::from(p.0) + geometry.center).collect()
+    }
+
+    #[test]
+    fn cube_to_sphere_projects_the_cube_onto_the_unit_sphere() {
+        for face in CubeFace::ALL.iter() {
+            let (normal, axis_u, axis_v) = face.axes();
+            assert_eq!(axis_u.cross(&axis_v), normal);
+            for &(u, v) in &[(0.0, 0.0), (1.0, 0.0), (-0.5, 0.25), (1.0, -1.0)] {
+                let p = normal + axis_u * u + axis_v * v;
+                let sphere = cube_to_sphere(p);
+                assert!((sphere.norm() - 1.0).abs() < 1e-5);
+                // The point stays on the same side of the cube.
+                assert!(sphere.dot(&normal) > 0.5);
+            }
+            assert!((cube_to_sphere(normal) - normal).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn children_split_their_parent_into_quarters() {
+        let parent = ChunkKey { face: CubeFace::NegativeY, level: 2, x: 3, y: 1 };
+        let children = parent.children();
+        for child in children.iter() {
+            assert_eq!(child.face, parent.face);
+            assert_eq!(child.level, parent.level + 1);
+            assert_eq!(child.size(), parent.size() / 2.0);
+        }
+        // The children cover the corners of their parent.
+        assert!((children[0].direction(0.0, 0.0) - parent.direction(0.0, 0.0)).norm() < 1e-6);
+        assert!((children[1].direction(1.0, 0.0) - parent.direction(1.0, 0.0)).norm() < 1e-6);
+        assert!((children[2].direction(0.0, 1.0) - parent.direction(0.0, 1.0)).norm() < 1e-6);
+        assert!((children[3].direction(1.0, 1.0) - parent.direction(1.0, 1.0)).norm() < 1e-6);
+        assert!((children[3].direction(0.0, 0.0) - parent.direction(0.5, 0.5)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn chunks_have_a_grid_and_a_skirt() {
+        let geometry = ChunkGeometry::sphere(ChunkKey::root(CubeFace::PositiveZ), 2.0, QUADS, 0.05);
+        let q = QUADS as usize;
+        assert_eq!(geometry.positions.len(), (q + 1) * (q + 1) + 4 * q);
+        assert_eq!(geometry.indices.len(), 6 * q * q + 24 * q);
+        assert!(geometry.indices.iter().all(|index| (*index as usize) < geometry.positions.len()));
+        assert_eq!(geometry.normals.len(), geometry.positions.len());
+        assert_eq!(geometry.tex_coords.len(), geometry.positions.len());
+    }
+
+    #[test]
+    fn normals_point_outward() {
+        for face in CubeFace::ALL.iter() {
+            let key = ChunkKey::root(*face).children()[1];
+            let geometry = ChunkGeometry::sphere(key, 3.0, QUADS, 0.05);
+            for (point, normal) in surface_points(&geometry).iter().zip(geometry.normals.iter()) {
+                assert!(Vector3::from(normal.0).dot(&point.normalize()) > 0.99);
+            }
+            // The triangles wind counter clockwise when seen from outside.
+            let indices = &geometry.indices[..3];
+            let corner = |i: usize| Vector3::from(geometry.positions[indices[i] as usize].0);
+            let winding = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+            assert!(winding.dot(&geometry.center) > 0.0);
+        }
+    }
+
+    #[test]
+    fn adjacent_chunks_share_their_edges() {
+        let shares_edge = |a: &ChunkGeometry, b: &ChunkGeometry| {
+            let b_points = surface_points(b);
+            surface_points(a)
+                .iter()
+                .filter(|p| b_points.iter().any(|q| (*p - q).norm() < 1e-4))
+                .count()
+        };
+
+        // Neighbours within a face.
+        let parent = ChunkKey::root(CubeFace::PositiveX);
+        let [a, b, _, _] = parent.children();
+        let (a, b) = (ChunkGeometry::sphere(a, 1.0, QUADS, 0.05), ChunkGeometry::sphere(b, 1.0, QUADS, 0.05));
+        assert_eq!(shares_edge(&a, &b), QUADS as usize + 1);
+
+        // Neighbours across the edge between two faces.
+        for face in CubeFace::ALL.iter().skip(1) {
+            let a = ChunkGeometry::sphere(ChunkKey::root(CubeFace::PositiveX), 1.0, QUADS, 0.05);
+            let b = ChunkGeometry::sphere(ChunkKey::root(*face), 1.0, QUADS, 0.05);
+            let expected = if *face == CubeFace::NegativeX { 0 } else { QUADS as usize + 1 };
+            assert_eq!(shares_edge(&a, &b), expected);
+        }
+    }
+}
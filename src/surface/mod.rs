@@ -0,0 +1,105 @@
+pub mod mesh;
+pub mod system;
+
+pub use mesh::{chunk_albedo, ChunkGeometry, ChunkKey, CubeFace};
+pub use system::{PlanetSurfaceBundle, PlanetSurfaceSystem, SurfaceChunk};
+
+use amethyst::{
+    assets::{Handle, PrefabData},
+    derive::PrefabData,
+    ecs::prelude::*,
+    error::Error,
+    renderer::Material,
+};
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PrefabData)]
+#[prefab(Component)]
+/// Generates a surface mesh for the `Planet` on the same entity.
+/// The surface is a cube projected onto a sphere, where each face of the cube is a quadtree of chunks.
+/// Chunks are split as the camera approaches, so that the detail follows the distance to the camera.
+/// Each chunk is a child entity with a standard `Mesh` and `Material`, so it is drawn by the pbr passes.
+pub struct PlanetSurface {
+    /// The number of quads along each side of a chunk.
+    pub resolution: u32,
+
+    /// The deepest level of the quadtree, where the faces of the cube are level 0.
+    pub max_depth: u8,
+
+    /// A chunk is split when the camera is closer than this many times the width of the chunk.
+    pub split_distance: f32,
+
+    /// The depth of the skirts around each chunk (relative to the width of the chunk), which hide the cracks between levels.
+    pub skirt_depth: f32,
+
+    /// The number of texels along each side of the albedo texture of a chunk, which is generated when the planet has terrain.
+    #[serde(default = "default_albedo_resolution")]
+    pub albedo_resolution: u32,
+
+    /// The material of every chunk. The default material is used if there is none.
+    /// When the planet has terrain, the albedo of the material is replaced by the colors of the biomes.
+    #[serde(skip)]
+    pub material: Option<Handle<Material>>,
+}
+
+fn default_albedo_resolution() -> u32 {
+    32
+}
+
+impl Default for PlanetSurface {
+    fn default() -> Self {
+        Self {
+            resolution: 16,
+            max_depth: 8,
+            split_distance: 2.0,
+            skirt_depth: 0.05,
+            albedo_resolution: default_albedo_resolution(),
+            material: None,
+        }
+    }
+}
+
+impl PlanetSurface {
+    /// Create a new surface with the default level of detail.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Changes the number of quads along each side of a chunk.
+    pub fn with_resolution(mut self, resolution: u32) -> Self {
+        assert!(resolution > 0, "A chunk needs at least one quad!");
+        self.resolution = resolution;
+        self
+    }
+
+    /// Changes the deepest level of the quadtree and the distance (relative to the width of a chunk) at which chunks are split.
+    pub fn with_lod(mut self, max_depth: u8, split_distance: f32) -> Self {
+        self.max_depth = max_depth;
+        self.split_distance = split_distance;
+        self
+    }
+
+    /// Changes the depth of the skirts around each chunk.
+    pub fn with_skirt_depth(mut self, skirt_depth: f32) -> Self {
+        self.skirt_depth = skirt_depth;
+        self
+    }
+
+    /// Changes the number of texels along each side of the albedo texture of a chunk.
+    pub fn with_albedo_resolution(mut self, albedo_resolution: u32) -> Self {
+        assert!(albedo_resolution > 0, "The albedo texture needs at least one texel!");
+        self.albedo_resolution = albedo_resolution;
+        self
+    }
+
+    /// Changes the material of every chunk.
+    pub fn with_material(mut self, material: Handle<Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+}
+
+impl Component for PlanetSurface {
+    type Storage = DenseVecStorage<Self>;
+}
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader},
+    core::{
+        bundle::SystemBundle,
+        math::{Point3, Vector3},
+        transform::{Parent, Transform},
+    },
+    ecs::prelude::*,
+    error::Error,
+    prelude::WorldExt,
+    renderer::{
+        camera::{ActiveCamera, Camera},
+        mtl::{Material, MaterialDefaults},
+        visibility::BoundingSphere,
+        Mesh,
+        Texture,
+    },
+};
+
+use super::{chunk_albedo, ChunkGeometry, ChunkKey, CubeFace, PlanetSurface};
+use crate::{
+    planet::{terrain::{changed_terrain, register_terrain_reader, Terrain}, Planet},
+    renderutils::find_camera_position,
+};
+
+/// Marks a child entity which holds one chunk of the surface of a planet.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SurfaceChunk {
+    /// The planet entity which the chunk belongs to.
+    pub planet: Entity,
+
+    /// The position of the chunk in the quadtree.
+    pub key: ChunkKey,
+}
+
+impl Component for SurfaceChunk {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The chunks which currently exist for a planet, along with the settings they were built with.
+/// The chunks are also rebuilt whenever the `Terrain` of the planet changes.
+#[derive(Debug)]
+struct PlanetChunks {
+    planet: Planet,
+    surface: PlanetSurface,
+    chunks: HashMap<ChunkKey, (Entity, Handle<Mesh>)>,
+}
+
+/// Finds the chunks which should be drawn, by splitting the quadtree of each face wherever the camera is close enough.
+/// The camera position is relative to the center of the planet, in the planet's local space.
+pub fn select_chunks(camera: Option<Vector3<f32>>, radius: f32, surface: &PlanetSurface) -> Vec<ChunkKey> {
+    let mut leaves = Vec::new();
+    let mut stack: Vec<ChunkKey> = CubeFace::ALL.iter().map(|face| ChunkKey::root(*face)).collect();
+    while let Some(key) = stack.pop() {
+        let split = match camera {
+            Some(camera) if key.level < surface.max_depth => {
+                let center = key.direction(0.5, 0.5) * radius;
+                (camera - center).norm() < key.width(radius) * surface.split_distance
+            }
+            _ => false,
+        };
+        if split {
+            stack.extend_from_slice(&key.children());
+        } else {
+            leaves.push(key);
+        }
+    }
+    leaves
+}
+
+/// Creates and removes the `SurfaceChunk` entities of every `Planet` with a `PlanetSurface`, as the camera moves.
+#[derive(Debug, Default)]
+pub struct PlanetSurfaceSystem {
+    planets: HashMap<Entity, PlanetChunks>,
+    default_material: Option<Handle<Material>>,
+    terrain_reader: Option<ReaderId<ComponentEvent>>,
+}
+
+impl<'a> System<'a> for PlanetSurfaceSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Planet>,
+        ReadStorage<'a, Terrain>,
+        ReadStorage<'a, PlanetSurface>,
+        ReadStorage<'a, Camera>,
+        Read<'a, ActiveCamera>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Parent>,
+        WriteStorage<'a, SurfaceChunk>,
+        WriteStorage<'a, Handle<Mesh>>,
+        WriteStorage<'a, Handle<Material>>,
+        WriteStorage<'a, BoundingSphere>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Material>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.terrain_reader = Some(register_terrain_reader(world));
+    }
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            planets,
+            terrains,
+            surfaces,
+            cameras,
+            active_camera,
+            mut transforms,
+            mut parents,
+            mut chunks,
+            mut meshes,
+            mut materials,
+            mut bounding_spheres,
+            loader,
+            mesh_storage,
+            material_storage,
+            texture_storage,
+            material_defaults,
+        ): Self::SystemData,
+    ) {
+        let camera_position = find_camera_position(&entities, &active_camera, &cameras, &transforms);
+
+        // Remove the chunks of planets which no longer exist, or whose surface has changed.
+        let changed = changed_terrain(&terrains, self.terrain_reader.as_mut());
+        self.planets.retain(|entity, planet_chunks| {
+            let unchanged = entities.is_alive(*entity)
+                && !changed.contains(entity.id())
+                && planets.get(*entity) == Some(&planet_chunks.planet)
+                && surfaces.get(*entity) == Some(&planet_chunks.surface);
+            if !unchanged {
+                for (chunk, _) in planet_chunks.chunks.values() {
+                    let _ = entities.delete(*chunk);
+                }
+            }
+            unchanged
+        });
+
+        let default_material = self.default_material
+            .get_or_insert_with(|| loader.load_from_data(material_defaults.0.clone(), (), &material_storage))
+            .clone();
+
+        for (entity, planet, surface) in (&entities, &planets, &surfaces).join() {
+            let global = match transforms.get(entity) {
+                Some(transform) => *transform.global_matrix(),
+                None => continue,
+            };
+            // The chunks are built in the planet's local space, so the camera is moved into it.
+            let local_camera = camera_position
+                .and_then(|position| global.try_inverse().map(|inverse| inverse.transform_point(&Point3::from(position)).coords));
+            let wanted: HashSet<ChunkKey> = select_chunks(local_camera, planet.radius, surface).into_iter().collect();
+
+            let planet_chunks = self.planets.entry(entity).or_insert_with(|| PlanetChunks {
+                planet: *planet,
+                surface: surface.clone(),
+                chunks: HashMap::new(),
+            });
+
+            let terrain = terrains.get(entity);
+            let material = surface.material.clone().unwrap_or_else(|| default_material.clone());
+            for key in wanted.iter().copied() {
+                if planet_chunks.chunks.contains_key(&key) {
+                    continue;
+                }
+                let geometry = ChunkGeometry::planet(key, planet, terrain, surface.resolution, surface.skirt_depth);
+                let mesh = loader.load_from_data(geometry.to_mesh_data(), (), &mesh_storage);
+                // Each chunk of a planet with terrain gets its own material, with the colors of the biomes as its albedo.
+                let chunk_material = match terrain {
+                    Some(terrain) => {
+                        let albedo = chunk_albedo(key, surface.albedo_resolution, |direction| terrain.sample(direction).albedo);
+                        let mut chunk_material = material_storage
+                            .get(&material)
+                            .cloned()
+                            .unwrap_or_else(|| material_defaults.0.clone());
+                        chunk_material.albedo = loader.load_from_data(albedo, (), &texture_storage);
+                        loader.load_from_data(chunk_material, (), &material_storage)
+                    }
+                    None => material.clone(),
+                };
+                let mut transform = Transform::default();
+                transform.set_translation(geometry.center);
+                let chunk = entities
+                    .build_entity()
+                    .with(transform, &mut transforms)
+                    .with(Parent { entity }, &mut parents)
+                    .with(SurfaceChunk { planet: entity, key }, &mut chunks)
+                    .with(mesh.clone(), &mut meshes)
+                    .with(chunk_material, &mut materials)
+                    .with(BoundingSphere::new(Point3::origin(), geometry.bounding_radius), &mut bounding_spheres)
+                    .build();
+                planet_chunks.chunks.insert(key, (chunk, mesh));
+            }
+
+            // The chunks which are no longer wanted are kept until the new chunks have loaded, so that there are no holes.
+            let loaded = wanted.iter().all(|key| mesh_storage.get(&planet_chunks.chunks[key].1).is_some());
+            if loaded {
+                planet_chunks.chunks.retain(|key, (chunk, _)| {
+                    let keep = wanted.contains(key);
+                    if !keep {
+                        let _ = entities.delete(*chunk);
+                    }
+                    keep
+                });
+            }
+        }
+    }
+}
+
+/// Adds the `PlanetSurfaceSystem` to the dispatcher, which generates the surface meshes of planets.
+#[derive(Debug, Default)]
+pub struct PlanetSurfaceBundle;
+
+impl PlanetSurfaceBundle {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for PlanetSurfaceBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        world.register::<Planet>();
+        world.register::<Terrain>();
+        world.register::<PlanetSurface>();
+        world.register::<SurfaceChunk>();
+        builder.add(PlanetSurfaceSystem::default(), "planet_surface_system", &[]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface(max_depth: u8) -> PlanetSurface {
+        PlanetSurface { max_depth, ..Default::default() }
+    }
+
+    /// The fraction of the faces of the cube which is covered by the chunks.
+    fn coverage(chunks: &[ChunkKey]) -> f32 {
+        chunks.iter().map(|key| key.size() * key.size() / 4.0).sum::<f32>() / 6.0
+    }
+
+    #[test]
+    fn far_cameras_select_the_faces() {
+        for camera in &[None, Some(Vector3::new(0.0, 1000.0, 0.0))] {
+            let chunks = select_chunks(*camera, 1.0, &surface(8));
+            assert_eq!(chunks.len(), 6);
+            assert!(chunks.iter().all(|key| key.level == 0));
+            for face in CubeFace::ALL.iter() {
+                assert!(chunks.contains(&ChunkKey::root(*face)));
+            }
+        }
+    }
+
+    #[test]
+    fn near_cameras_split_the_chunks_below_them() {
+        let max_depth = 5;
+        let below = ChunkKey { face: CubeFace::PositiveY, level: max_depth, x: 7, y: 20 };
+        let camera = below.direction(0.5, 0.5) * 2.001;
+        let chunks = select_chunks(Some(camera), 2.0, &surface(max_depth));
+        assert!(chunks.contains(&below));
+        assert!(chunks.iter().all(|key| key.level <= max_depth));
+        // The chunks on the other side of the planet are coarser.
+        assert!(chunks.iter().filter(|key| key.face == CubeFace::NegativeY).all(|key| key.level < 2));
+        // The chunks cover the whole planet without overlapping.
+        assert!((coverage(chunks.as_slice()) - 1.0).abs() < 1e-5);
+        let unique: HashSet<ChunkKey> = chunks.iter().copied().collect();
+        assert_eq!(unique.len(), chunks.len());
+    }
+}